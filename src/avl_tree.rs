@@ -10,6 +10,7 @@ use std::iter::zip;
 use std::cmp::{max, Ordering};
 use std::collections::HashSet;
 use std::marker::PhantomData;
+use std::ops::Sub;
 use std::ptr::NonNull;
 use std::string::ToString;
 use std::default::Default;
@@ -23,6 +24,12 @@ type Link<K, V> = Option<NodePtr<K, V>>;
 type LinkPtr<K, V> = NonNull<Link<K, V>>;
 type BoxedNode<K, V> = Box<Node<K, V>>;
 
+/// Tallest tree display will render
+const MAX_DISPLAY_HEIGHT: isize = 16;
+
+/// Most removed nodes kept for reuse by later inserts
+const FREE_LIST_CAPACITY: usize = 1024;
+
 /// AVL tree of FIFO stacks with a reference to the root and a node count
 pub struct AVLTree<K, V, Z>
     where K: Display + Debug + PartialOrd + Clone + ToString,
@@ -30,6 +37,11 @@ pub struct AVLTree<K, V, Z>
           Z: Default {
     root: Link<K, V>,
     len: usize,
+    display_precision: usize,
+    successor_strategy: SuccessorStrategy,
+    free_list: Vec<BoxedNode<K, V>>,
+    reused_nodes: usize,
+    key_epsilon: Option<(K, fn(&K, &K, &K) -> bool)>,
     _boo: PhantomData<K>,
 }
 
@@ -42,6 +54,8 @@ pub struct Node<K, V>
     pub parent: Link<K, V>,
     pub left: Link<K, V>,
     pub right: Link<K, V>,
+    /// Height of the subtree rooted at this node, a leaf being 1
+    pub height: isize,
 }
 
 pub struct Iter<'a, K, V, Z>
@@ -53,6 +67,23 @@ pub struct Iter<'a, K, V, Z>
     _boo: PhantomData<&'a K>,
 }
 
+/// Owning iterator that moves (key, value) pairs out of the tree in key order,
+/// freeing each node as it's yielded
+pub struct IntoIter<K, V, Z>
+    where K: Display + Debug + PartialOrd + Clone + ToString,
+          V: Collection<Item=Z> + PushBack + New + Len,
+          Z: Default {
+    tree: AVLTree<K, V, Z>,
+}
+
+/// Node that takes the place of a removed node with two children: its in-order successor
+/// (the minimum of its right subtree) or its in-order predecessor (the maximum of its left subtree)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SuccessorStrategy {
+    MinUnderRight,
+    MaxUnderLeft,
+}
+
 #[derive(PartialEq)]
 pub enum LinkRotation {
     LLCase,
@@ -79,7 +110,26 @@ impl<K, V, Z> AVLTree<K, V, Z>
           Z: Default {
     /// Create new AVL Tree
     pub fn new() -> Self {
-        AVLTree { root: None, len: 0, _boo: PhantomData}
+        AVLTree {
+            root: None,
+            len: 0,
+            display_precision: 2,
+            successor_strategy: SuccessorStrategy::MinUnderRight,
+            free_list: Vec::new(),
+            reused_nodes: 0,
+            key_epsilon: None,
+            _boo: PhantomData,
+        }
+    }
+
+    /// Set which node replaces a removed node that has two children
+    pub fn set_successor_strategy(&mut self, strategy: SuccessorStrategy) {
+        self.successor_strategy = strategy;
+    }
+
+    /// Set the number of decimals keys are rendered with by display
+    pub fn set_display_precision(&mut self, precision: usize) {
+        self.display_precision = precision;
     }
 
     /// Return count of nodes in tree
@@ -90,6 +140,11 @@ impl<K, V, Z> AVLTree<K, V, Z>
     /// Return true if tree is empty
     pub fn is_empty(&self) -> bool { if self.len == 0 { true } else { false } }
 
+    /// Return count of inserts that reused a removed node instead of allocating
+    pub fn reused_nodes(&self) -> usize {
+        self.reused_nodes
+    }
+
     /// Get reference to key's value
     pub fn get(&self, key: &K) -> Option<&V> {
         let link = self.find_link(&key);
@@ -124,13 +179,18 @@ impl<K, V, Z> AVLTree<K, V, Z>
         } else { true }
     }
 
+    /// Return the height of the tree
+    pub fn tree_height(&self) -> isize {
+        Self::height(&self.root)
+    }
+
     /// Get immutable reference to a link associated with the passed key
     fn find_link(&self, key: &K) -> &Link<K, V> {
         let mut current: &Link<K, V> = &self.root;
         unsafe {
             while let Some(node_ptr) = current.as_ref() {
                 let node = &(*node_ptr.as_ptr());
-                match key.partial_cmp(&node.key) {
+                match self.compare_keys(key, &node.key) {
                     Some(Ordering::Greater) => {
                         current = &node.right;
                     }
@@ -147,6 +207,14 @@ impl<K, V, Z> AVLTree<K, V, Z>
         current
     }
 
+    /// Compare a key against a node's key, treating keys within the key epsilon as equal
+    fn compare_keys(&self, key: &K, node_key: &K) -> Option<Ordering> {
+        match &self.key_epsilon {
+            Some((epsilon, within)) if within(key, node_key, epsilon) => Some(Ordering::Equal),
+            _ => key.partial_cmp(node_key),
+        }
+    }
+
     /// Get mutable location of where a key would exist in the tree,
     /// regardless of whether it exists.
     fn find_link_location(&mut self, key: &K) -> LinkLocation<K, V> {
@@ -159,7 +227,7 @@ impl<K, V, Z> AVLTree<K, V, Z>
 
             while let Some(node_ptr) = current.as_ref() {
                 let node = &mut (*node_ptr.as_ptr());
-                match key.partial_cmp(&node.key) {
+                match self.compare_keys(key, &node.key) {
                     Some(Ordering::Greater) => {
                         parent = *current.as_ptr();
                         current = LinkPtr::new_unchecked(&mut node.right);
@@ -205,7 +273,7 @@ impl<K, V, Z> AVLTree<K, V, Z>
                     let mut new_link: Link<K, V> = {
                         let mut deque = V::new();
                         deque.push_back(value);
-                        Some(Node::new(key, deque, parent))
+                        Some(self.allocate(key, deque, parent))
                     };
 
                     std::mem::swap(link, &mut new_link);
@@ -274,24 +342,19 @@ impl<K, V, Z> AVLTree<K, V, Z>
         distance
     }
 
-    /// Get link's height
+    /// Get link's height, as stored in its node, or 0 for an empty link
     pub fn height(link: &Link<K, V>) -> isize {
-        if link.is_none() {return 0};
-        let mut right_height: isize = 0;
-        let mut left_height: isize = 0;
-        unsafe {
-            let node = &(*link.unwrap().as_ptr());
-            if node.right.is_some() {
-                right_height = AVLTree::height(&node.right);
-            }
-            if node.left.is_some() {
-                left_height = AVLTree::height(&node.left);
-            }
-        };
-        if left_height > right_height {
-            left_height + 1
-        } else {
-            right_height + 1
+        match link {
+            None => 0,
+            Some(node_ptr) => unsafe { (*node_ptr.as_ptr()).height },
+        }
+    }
+
+    /// Recompute a node's stored height from its children's
+    fn update_height(link: &Link<K, V>) {
+        if let Some(node_ptr) = link {
+            let node = unsafe { &mut (*node_ptr.as_ptr()) };
+            node.height = max(Self::height(&node.left), Self::height(&node.right)) + 1;
         }
     }
 
@@ -309,6 +372,7 @@ impl<K, V, Z> AVLTree<K, V, Z>
     /// After balancing, calls balance on the link's parent.
     fn balance(&mut self, link: &mut Link<K, V>) {
         if link.is_some() {
+            Self::update_height(link);
             let balance_factor = Self::balance_factor(link);
             let node = unsafe { &mut (*link.unwrap().as_ptr()) };
 
@@ -432,6 +496,10 @@ impl<K, V, Z> AVLTree<K, V, Z>
                         (*pivot_left.unwrap().as_ptr()).parent = *root_ptr;
                     }
 
+                    // root is now below pivot, so its height is recomputed first
+                    Self::update_height(&root);
+                    Self::update_height(&pivot);
+
                     // println!("\n<<SWAPS>>\n");
                     // println!("parent_ptr: {}\t\tparent: {}", Self::debug_link(&*parent_ptr), Self::debug_link(&parent));
                     // println!("pivot_ptr: {}\t\tpivot: {}", Self::debug_link(&*pivot_ptr), Self::debug_link(&pivot));
@@ -521,6 +589,10 @@ impl<K, V, Z> AVLTree<K, V, Z>
                         (*pivot_right.unwrap().as_ptr()).parent = *root_ptr;
                     }
 
+                    // root is now below pivot, so its height is recomputed first
+                    Self::update_height(&root);
+                    Self::update_height(&pivot);
+
                     // println!("\n<<SWAPS DONE>>\n");
                     // println!("parent_ptr: {}\t\tparent: {}", Self::debug_link(&*parent_ptr), Self::debug_link(&parent));
                     // println!("pivot_ptr: {}\t\tpivot: {}", Self::debug_link(&*pivot_ptr), Self::debug_link(&pivot));
@@ -669,14 +741,15 @@ impl<K, V, Z> AVLTree<K, V, Z>
         removed
     }
 
-    /// Remove key-value pair from the tree, by key
-    pub fn remove(&mut self, key: &K) -> Option<BoxedNode<K, V>> {
+    /// Remove key-value pair from the tree, by key, returning the removed key and value.
+    /// The emptied node is kept on the free list for reuse by a later insert.
+    pub fn remove(&mut self, key: &K) -> Option<(K, V)> {
         let location = self.find_link_location(&key);
         self.remove_by_location(location)
     }
 
     /// Remove key-value pair from the treem, by link location
-    fn remove_by_location(&mut self, location: LinkLocation<K, V>) -> Option<BoxedNode<K, V>> {
+    fn remove_by_location(&mut self, location: LinkLocation<K, V>) -> Option<(K, V)> {
         // println!("\nCalled remove on {}", &key);
 
         let link_for_removal;
@@ -715,43 +788,58 @@ impl<K, V, Z> AVLTree<K, V, Z>
                     removed_link = self.replace_with_child(link_for_removal, Branch::Right)
                 },
 
-                // Choose a successor to replace removed node
+                // Choose a successor to replace removed node, by default the min under its right.
+                // With MaxUnderLeft the predecessor is used instead, mirroring left and right below.
                 // Removed node's parent inherits successor
-                // Successor either keeps its right child or inherits the removed node's right child
-                // Successor inherits removed node's left child
-                // Successor's parent inherits successor's right child
-                // Successor has no left child to give away
+                // Successor either keeps its near child or inherits the removed node's near child,
+                // where near is the side of the removed node the successor came from
+                // Successor inherits removed node's far child
+                // Successor's parent inherits successor's near child
+                // Successor has no far child to give away
                 Children::Both => {
 
                     // need a raw pointer to work around borrow checker
                     let removed_node_ptr = (*link_for_removal)?.as_ptr();
 
+                    let use_predecessor = self.successor_strategy == SuccessorStrategy::MaxUnderLeft;
+                    let near_branch = if use_predecessor { Branch::Left } else { Branch::Right };
+
                     // mutable ref to successor
-                    let successor = Self::min_under_right(link_for_removal);
+                    let successor = if use_predecessor {
+                        Self::max_under_left(link_for_removal)
+                    } else {
+                        Self::min_under_right(link_for_removal)
+                    };
 
                     // temporary spot to hold successor in upcoming detachment / reattachment
                     let successor_temp: Link<K, V>;
 
-                    // determine whether the successor's parent will adopt the successor's right child
-                    // if successor is the removed node's right, it will remain in control of its right child
-                    // otherwise, if the successor is somewhere further down the right subtree, it's right child
-                    // will become the successor's parent's left child
+                    // determine whether the successor's parent will adopt the successor's near child
+                    // if successor is the removed node's near child, it will remain in control of it
+                    // otherwise, if the successor is somewhere further down the near subtree, its near child
+                    // will become the successor's parent's far child
                     let successor_key = Self::get_key(successor).unwrap();
-                    let removed_node_right_key = Self::get_key(&(*removed_node_ptr).right).unwrap();
-                    if successor_key != removed_node_right_key {
-                        let removed_node_right = &mut (*removed_node_ptr).right;
-
-                        // replace successor's old parent's left child with the successor node's right
-                        successor_temp = self.replace_with_child(successor, Branch::Right);
-
-                        // alias successor's node for readability and
-                        // set successor's new right child and update the right child's parent reference
-                        (*successor_temp.unwrap().as_ptr()).right = *removed_node_right;
-                        (*(*successor_temp.unwrap().as_ptr()).right.unwrap().as_ptr()).parent = successor_temp;
+                    let removed_node_near: *mut Link<K, V> = if use_predecessor {
+                        &mut (*removed_node_ptr).left
+                    } else {
+                        &mut (*removed_node_ptr).right
+                    };
+                    let removed_node_near_key = Self::get_key(&*removed_node_near).unwrap();
+                    if successor_key != removed_node_near_key {
+                        // replace successor's old parent's far child with the successor node's near child
+                        successor_temp = self.replace_with_child(successor, near_branch);
+
+                        // set successor's new near child and update the near child's parent reference
+                        let successor_near = if use_predecessor {
+                            &mut (*successor_temp.unwrap().as_ptr()).left
+                        } else {
+                            &mut (*successor_temp.unwrap().as_ptr()).right
+                        };
+                        *successor_near = *removed_node_near;
+                        (*successor_near.unwrap().as_ptr()).parent = successor_temp;
 
                     } else {
-                        let removed_node_right = &mut (*removed_node_ptr).right;
-                        successor_temp = removed_node_right.take();
+                        successor_temp = (*removed_node_near).take();
                     }
 
                     // alias successor's node for readability
@@ -759,9 +847,15 @@ impl<K, V, Z> AVLTree<K, V, Z>
                     // store mutable reference to successor's old parent for balancing starting point later
                     successor_old_parent = &mut (*self.flip_link(&mut successor_node.parent).as_ptr());
 
-                    // give the successor the removed link's left child and update its parent reference
-                    successor_node.left = (*removed_node_ptr).left.take();
-                    (*successor_node.left.unwrap().as_ptr()).parent = successor_temp;
+                    // give the successor the removed link's far child and update its parent reference
+                    let successor_far = if use_predecessor {
+                        successor_node.right = (*removed_node_ptr).right.take();
+                        successor_node.right
+                    } else {
+                        successor_node.left = (*removed_node_ptr).left.take();
+                        successor_node.left
+                    };
+                    (*successor_far.unwrap().as_ptr()).parent = successor_temp;
 
                     drop(removed_node_ptr);
 
@@ -800,7 +894,36 @@ impl<K, V, Z> AVLTree<K, V, Z>
 
         }
         self.len -= 1;
-        Some(removed_node)
+        Some(self.recycle(removed_node))
+    }
+
+    /// Allocate a node, reusing one from the free list if available
+    fn allocate(&mut self, key: K, value: V, parent: Link<K, V>) -> NodePtr<K, V> {
+        match self.free_list.pop() {
+            Some(mut node) => {
+                node.key = key;
+                node.value = value;
+                node.parent = parent;
+                node.height = 1;
+                self.reused_nodes += 1;
+                NonNull::from(Box::leak(node))
+            },
+            None => Node::new(key, value, parent),
+        }
+    }
+
+    /// Move the key and value out of a detached node, keeping the node on the free list
+    /// with its links cleared, unless the list is full
+    fn recycle(&mut self, mut node: BoxedNode<K, V>) -> (K, V) {
+        let key = node.key.clone();
+        let value = std::mem::replace(&mut node.value, V::new());
+        if self.free_list.len() < FREE_LIST_CAPACITY {
+            node.parent = None;
+            node.left = None;
+            node.right = None;
+            self.free_list.push(node);
+        }
+        (key, value)
     }
 
     /// Return non-empty link with the smallest key that's greater than the passed link's key
@@ -889,13 +1012,41 @@ impl<K, V, Z> AVLTree<K, V, Z>
     }
 
 
+    /// Validates parent/child references, key ordering and node count, adding a message
+    /// to error_msgs for every inconsistency found
     pub fn check(&self, mut error_msgs: HashSet<String>) -> HashSet<String> {
+        if let Some(root) = self.root {
+            let root = unsafe { &*root.as_ptr() };
+            if let Some(parent) = root.parent {
+                let msg: String = format!("Root {} has parent {}", root.key, unsafe { &(*parent.as_ptr()).key });
+                error_msgs.insert(msg);
+            }
+        }
+
+        let mut count: usize = 0;
+        let mut previous: Option<&K> = None;
         let mut tree_iter = self.iter();
         while let Some(node) = tree_iter.next() {
+            count += 1;
+
+            // check the stored height against the children's
+            let height = max(Self::height(&node.left), Self::height(&node.right)) + 1;
+            if node.height != height {
+                let msg: String = format!("Stale height found: node {}, stored {}, actual {}", node.key, node.height, height);
+                error_msgs.insert(msg);
+            }
+
+            // check in-order key ordering validity across the whole tree
+            if let Some(previous) = previous {
+                if previous.partial_cmp(&node.key) != Some(Ordering::Less) {
+                    let msg: String = format!("Keys out of order: {} precedes {}", previous, node.key);
+                    error_msgs.insert(msg);
+                }
+            }
+            previous = Some(&node.key);
 
             if node.left.is_some() {
                 let left = unsafe { &*node.left.unwrap().as_ptr() };
-                let left_parent = unsafe { &*left.parent.unwrap().as_ptr() };
 
                 // check key ordering validity
                 match left.key.partial_cmp(&node.key) {
@@ -907,15 +1058,23 @@ impl<K, V, Z> AVLTree<K, V, Z>
                 }
 
                 // check parent validity
-                if node.key != left_parent.key {
-                    let msg: String = format!("Invalid parent/child references found: node {}, left.parent {}", node.key, left_parent.key);
-                    error_msgs.insert(msg);
+                match left.parent {
+                    None => {
+                        let msg: String = format!("Missing parent reference found: node {}, left {}", node.key, left.key);
+                        error_msgs.insert(msg);
+                    },
+                    Some(parent) => {
+                        let left_parent = unsafe { &*parent.as_ptr() };
+                        if node.key != left_parent.key {
+                            let msg: String = format!("Invalid parent/child references found: node {}, left.parent {}", node.key, left_parent.key);
+                            error_msgs.insert(msg);
+                        }
+                    }
                 }
             }
 
             if node.right.is_some() {
                 let right = unsafe { &*node.right.unwrap().as_ptr() };
-                let right_parent = unsafe { &*right.parent.unwrap().as_ptr() };
 
                 // check key ordering validity
                 match right.key.partial_cmp(&node.key) {
@@ -927,19 +1086,42 @@ impl<K, V, Z> AVLTree<K, V, Z>
                 }
 
                 // check parent validity
-                if node.key != right_parent.key {
-                    let msg: String = format!("Invalid parent/child references found: node {}, right.parent {}", node.key, right_parent.key);
-                    error_msgs.insert(msg);
+                match right.parent {
+                    None => {
+                        let msg: String = format!("Missing parent reference found: node {}, right {}", node.key, right.key);
+                        error_msgs.insert(msg);
+                    },
+                    Some(parent) => {
+                        let right_parent = unsafe { &*parent.as_ptr() };
+                        if node.key != right_parent.key {
+                            let msg: String = format!("Invalid parent/child references found: node {}, right.parent {}", node.key, right_parent.key);
+                            error_msgs.insert(msg);
+                        }
+                    }
                 }
             }
         }
+
+        // check node count validity
+        if count != self.len {
+            let msg: String = format!("Node count mismatch: len {}, counted {}", self.len, count);
+            error_msgs.insert(msg);
+        }
         error_msgs
     }
 
     /// Returns printable tree string
     ///
-    /// Calls the display tree method in the root node.
+    /// Calls the display tree method in the root node. Trees taller than
+    /// MAX_DISPLAY_HEIGHT are summarized in a single line instead, since line width grows
+    /// exponentially with height.
     pub fn display(&self) -> Vec<String> {
+        let height = self.tree_height();
+        if height > MAX_DISPLAY_HEIGHT {
+            return vec![format!(
+                "Tree too deep to display (height {} > {}, {} nodes)", height, MAX_DISPLAY_HEIGHT, self.len
+            )]
+        }
         let lines: Vec<String>;
         unsafe {
             match &self.root {
@@ -947,13 +1129,57 @@ impl<K, V, Z> AVLTree<K, V, Z>
                     lines = Vec::new()
                 }
                 Some(node) => {
-                    lines = (*node.as_ptr()).display()
+                    lines = (*node.as_ptr()).display(self.display_precision)
                 }
             }
         }
         lines
     }
 
+    /// Returns the shape of the tree as adjacency data, in-order.
+    ///
+    /// Each entry is (key, left child key, right child key, balance factor).
+    pub fn structure(&self) -> Vec<(K, Option<K>, Option<K>, isize)> {
+        self.iter().map(|node| {
+            let link: Link<K, V> = Some(NonNull::from(node));
+            (
+                node.key.clone(),
+                Self::get_key(&node.left),
+                Self::get_key(&node.right),
+                Self::balance_factor(&link),
+            )
+        }).collect()
+    }
+
+    /// Removes every node, iteratively from the root. Removed nodes go to the free list,
+    /// so refilling a cleared tree reuses them.
+    pub fn clear(&mut self) {
+        unsafe {
+            while let Some(link) = self.root {
+                let key = (*link.as_ptr()).key.clone();
+                self.remove(&key);
+                // self.display();
+            }
+        }
+    }
+
+    /// Removes every node for which the predicate returns false, returning count removed.
+    ///
+    /// Keys are collected during a traversal first and removed afterwards, since removal
+    /// rotates the tree under the iterator.
+    pub fn retain<F>(&mut self, mut f: F) -> usize
+        where F: FnMut(&K, &V) -> bool
+    {
+        let keys: Vec<K> = self.iter()
+            .filter(|node| !f(&node.key, &node.value))
+            .map(|node| node.key.clone())
+            .collect();
+        for key in &keys {
+            self.remove(key);
+        }
+        keys.len()
+    }
+
     pub fn iter(&self) -> Iter<K, V, Z> {
         Iter {
             current_link: self.root,
@@ -961,6 +1187,82 @@ impl<K, V, Z> AVLTree<K, V, Z>
             _boo: PhantomData,
         }
     }
+
+    /// Returns an ascending iterator starting at the first node with key >= start.
+    ///
+    /// Only meant for forward iteration; iterating it from the back isn't supported.
+    pub fn iter_from(&self, start: &K) -> Iter<K, V, Z> {
+        // descend to the lower bound, remembering the greatest key below start
+        let mut lower_bound: Link<K, V> = None;
+        let mut predecessor: Link<K, V> = None;
+        let mut current = self.root;
+        while let Some(node_ptr) = current {
+            let node = unsafe { &*node_ptr.as_ptr() };
+            if node.key >= *start {
+                lower_bound = current;
+                current = node.left;
+            } else {
+                predecessor = current;
+                current = node.right;
+            }
+        }
+
+        match (lower_bound, predecessor) {
+            // no key >= start
+            (None, _) => Iter { current_link: None, first_move: true, _boo: PhantomData },
+            // resume after the predecessor, so the next move lands on the lower bound
+            (Some(_), Some(_)) => Iter { current_link: predecessor, first_move: false, _boo: PhantomData },
+            // lower bound is the minimum, so start from scratch
+            (Some(_), None) => self.iter(),
+        }
+    }
+
+    /// Removes the node with the smallest key, returning its key and value
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        let mut current = self.root?;
+        unsafe {
+            while let Some(left) = (*current.as_ptr()).left {
+                current = left;
+            }
+            let key = (*current.as_ptr()).key.clone();
+            self.remove(&key)
+        }
+    }
+
+    /// Removes the node with the largest key, returning its key and value
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        let mut current = self.root?;
+        unsafe {
+            while let Some(right) = (*current.as_ptr()).right {
+                current = right;
+            }
+            let key = (*current.as_ptr()).key.clone();
+            self.remove(&key)
+        }
+    }
+}
+
+impl<K, V, Z> AVLTree<K, V, Z>
+    where K: Display + Debug + PartialOrd + Clone + ToString + Copy + Sub<Output=K>,
+          V: Collection<Item=Z> + PushBack + New + Len,
+          Z: Default {
+    /// Treat keys within epsilon of a node's key as that key when inserting and looking up,
+    /// so near-duplicate prices from f64 rounding land on the same node, which keeps the key
+    /// it was created with. Pass None, the default, for exact comparison.
+    ///
+    /// Epsilon must be smaller than the minimum gap between distinct keys (for prices, the
+    /// tick size). A key is then within epsilon of at most one node, and orders the same as
+    /// that node against every other node, so the search path and the BST invariant that
+    /// rotations rely on are unaffected. Set it before inserting; existing nodes aren't merged.
+    pub fn set_key_epsilon(&mut self, epsilon: Option<K>) {
+        self.key_epsilon = epsilon.map(|epsilon| {
+            let within: fn(&K, &K, &K) -> bool = |a, b, epsilon| {
+                let difference = if a > b { *a - *b } else { *b - *a };
+                difference <= *epsilon
+            };
+            (epsilon, within)
+        });
+    }
 }
 
 unsafe impl<K, V, Z> Sync for AVLTree<K, V, Z>
@@ -1128,18 +1430,48 @@ impl<'a, K, V: 'a, Z: 'a> DoubleEndedIterator for Iter <'a, K, V, Z>
     }
 }
 
+impl<K, V, Z> Iterator for IntoIter<K, V, Z>
+    where K: Display + Debug + PartialOrd + Clone + ToString,
+          V: Collection<Item=Z> + PushBack + New + Len,
+          Z: Default {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.tree.pop_first()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.tree.len(), Some(self.tree.len()))
+    }
+}
+
+impl<K, V, Z> DoubleEndedIterator for IntoIter<K, V, Z>
+    where K: Display + Debug + PartialOrd + Clone + ToString,
+          V: Collection<Item=Z> + PushBack + New + Len,
+          Z: Default {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.tree.pop_last()
+    }
+}
+
+impl<K, V, Z> IntoIterator for AVLTree<K, V, Z>
+    where K: Display + Debug + PartialOrd + Clone + ToString,
+          V: Collection<Item=Z> + PushBack + New + Len,
+          Z: Default {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V, Z>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter { tree: self }
+    }
+}
+
 impl<K, V, Z> Drop for AVLTree<K, V, Z>
     where K: Display + Debug + PartialOrd + Clone + ToString,
           V: Collection<Item=Z> + PushBack + New + Len,
           Z: Default {
     fn drop(&mut self) {
-        unsafe {
-            while let Some(link) = self.root {
-                let key = (*link.as_ptr()).key.clone();
-                self.remove(&key);
-                // self.display();
-            }
-        }
+        self.clear();
     }
 }
 
@@ -1163,6 +1495,7 @@ impl<K, V> Node<K, V>
             parent,
             left: None,
             right: None,
+            height: 1,
         });
         unsafe {
             NonNull::new_unchecked(Box::into_raw(boxed_node))
@@ -1202,20 +1535,20 @@ impl<K, V> Node<K, V>
     }
 
     /// Display tree wrapper method
-    fn display(&self) -> Vec<String>{
-        let (lines, _, _, _) = Node::display_aux(self);
+    fn display(&self, precision: usize) -> Vec<String>{
+        let (lines, _, _, _) = Node::display_aux(self, precision);
         lines
     }
 
     /// Core display tree function
-    fn display_aux(node: &Node<K, V>) -> (Vec<String>, usize, usize, usize) {
+    fn display_aux(node: &Node<K, V>, precision: usize) -> (Vec<String>, usize, usize, usize) {
         // print!("display_aux - Node {} > ", &node.key);
         let children = Node::which_children_exist(&node);
         unsafe {
             match children {
                 Children::None => {
                     // println!("no children >");
-                    let line = format!("{:?}/{:?}", &node.key, &node.value.len());
+                    let line = format!("{:.prec$}/{:?}", &node.key, &node.value.len(), prec=precision);
                     let width = line.len();
                     let height = 1;
                     let middle = width / 2;
@@ -1223,9 +1556,9 @@ impl<K, V> Node<K, V>
                 }
                 Children::Left => {
                     // println!("left child >");
-                    let (lines, n, p, x) = Node::display_aux(&(*node.left.unwrap().as_ptr()));
+                    let (lines, n, p, x) = Node::display_aux(&(*node.left.unwrap().as_ptr()), precision);
                     // println!("back to {}", &node.key);
-                    let s = format!("{:?}/{:?}", &node.key, &node.value.len());
+                    let s = format!("{:.prec$}/{:?}", &node.key, &node.value.len(), prec=precision);
                     let u = s.len();
                     let first_line = " ".repeat(x + 1) + &"_".repeat(n - x - 1) + &s;
                     let second_line = " ".repeat(x) + &"/" + &" ".repeat(n - x - 1 + u);
@@ -1237,9 +1570,9 @@ impl<K, V> Node<K, V>
                 }
                 Children::Right => {
                     // println!("right child >");
-                    let (lines, n, p, x) = Node::display_aux(&(*node.right.unwrap().as_ptr()));
+                    let (lines, n, p, x) = Node::display_aux(&(*node.right.unwrap().as_ptr()), precision);
                     // println!("back to {}", &node.key);
-                    let s = format!("{:?}/{:?}", &node.key, &node.value.len());
+                    let s = format!("{:.prec$}/{:?}", &node.key, &node.value.len(), prec=precision);
                     let u = s.len();
                     let first_line = s + &"_".repeat(x) + &" ".repeat(n - x);
                     let second_line = " ".repeat(u + x) + &r"\" + &" ".repeat(n - x - 1);
@@ -1251,11 +1584,11 @@ impl<K, V> Node<K, V>
                 }
                 Children::Both => {
                     // println!("left child >");
-                    let (mut left, n, p, x) = Node::display_aux(&(*node.left.unwrap().as_ptr()));
+                    let (mut left, n, p, x) = Node::display_aux(&(*node.left.unwrap().as_ptr()), precision);
                     // println!("now {}'s right >", &node.key);
-                    let (mut right, m, q, y) = Node::display_aux(&(*node.right.unwrap().as_ptr()));
+                    let (mut right, m, q, y) = Node::display_aux(&(*node.right.unwrap().as_ptr()), precision);
                     // println!("back to {}", &node.key);
-                    let s = format!("{:?}/{:?}", &node.key, &node.value.len());
+                    let s = format!("{:.prec$}/{:?}", &node.key, &node.value.len(), prec=precision);
                     let u = s.len();
                     let first_line = " ".repeat(x + 1) + &"_".repeat(n - x - 1) + &s + &"_".repeat(y) + &" ".repeat(m - y);
                     let second_line = " ".repeat(x) + &"/" + &" ".repeat(n - x - 1 + u + y) + &r"\" + &" ".repeat(m - y - 1);
@@ -1301,6 +1634,7 @@ enum Children {
 mod tests {
     use rand::{Rng, seq::{IteratorRandom, SliceRandom}, distributions::uniform::SampleUniform};
     use super::*;
+    use std::collections::HashMap;
     // use lazy_static::lazy_static;
     // use std::sync::Mutex;
     // use std::mem::swap;
@@ -1482,7 +1816,7 @@ mod tests {
             let node_option = avl_tree.remove(&key);
             match node_option {
                 None => println!("No node to remove."),
-                Some(node) => println!("Removed {:?}", node.key)
+                Some((key, _)) => println!("Removed {:?}", key)
             };
             avl_tree.display().iter().for_each(|line| println!("{line}"));
         }
@@ -1496,7 +1830,7 @@ mod tests {
             let node_option = avl_tree.remove(&key);
             match node_option {
                 None => println!("No node to remove."),
-                Some(node) => println!("Removed {:?}", node.key)
+                Some((key, _)) => println!("Removed {:?}", key)
             };
         }
 
@@ -1512,4 +1846,303 @@ mod tests {
         }
         assert_eq!(avl_tree.len, 0);
     }
+
+    #[test]
+    fn test_structure() {
+        println!("\n---------TESTING TREE STRUCTURE---------\n");
+        let mut avl_tree: AVLTree<i32, OrderStack, Order> = AVLTree::new();
+        let mut rng = rand::thread_rng();
+        let keys: Vec<i32> = (0..30).map(|_| rng.gen_range(0..100)).collect();
+        for key in &keys {
+            avl_tree.insert(key.clone(), None);
+        }
+        avl_tree.display().iter().for_each(|line| println!("{line}"));
+
+        let structure = avl_tree.structure();
+        assert_eq!(structure.len(), avl_tree.len());
+
+        // in-order keys
+        let inorder: Vec<i32> = avl_tree.iter().map(|node| node.key).collect();
+        let structure_keys: Vec<i32> = structure.iter().map(|entry| entry.0).collect();
+        assert_eq!(structure_keys, inorder);
+
+        // every node except the root is referenced as a child exactly once,
+        // left children are lesser and right children are greater
+        let mut child_counts: HashMap<i32, usize> = HashMap::new();
+        for (key, left, right, balance) in &structure {
+            assert!((-1..=1).contains(balance));
+            if let Some(left) = left {
+                assert!(left < key);
+                assert!(structure_keys.contains(left));
+                *child_counts.entry(*left).or_insert(0) += 1;
+            }
+            if let Some(right) = right {
+                assert!(right > key);
+                assert!(structure_keys.contains(right));
+                *child_counts.entry(*right).or_insert(0) += 1;
+            }
+        }
+        assert_eq!(child_counts.len(), structure.len() - 1);
+        assert!(child_counts.values().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn test_retain() {
+        println!("\n---------TESTING TREE RETAIN---------\n");
+        let mut avl_tree: AVLTree<i32, OrderStack, Order> = AVLTree::new();
+        let mut rng = rand::thread_rng();
+        let keys: Vec<i32> = (0..100).map(|_| rng.gen_range(0..1000)).collect();
+        for key in &keys {
+            let order = Order::new(key.to_string(), None, Some(*key as f64), Some((key % 10) as f64), "dummy_datetime".to_string());
+            avl_tree.insert(key.clone(), order);
+        }
+        let before = avl_tree.len();
+        let survivors: Vec<i32> = avl_tree.iter()
+            .filter(|node| node.value.size() > 4.0)
+            .map(|node| node.key)
+            .collect();
+
+        let removed = avl_tree.retain(|_, order_stack| order_stack.size() > 4.0);
+        assert_eq!(removed, before - survivors.len());
+        assert_eq!(avl_tree.len(), survivors.len());
+        assert_eq!(avl_tree.iter().map(|node| node.key).collect::<Vec<i32>>(), survivors);
+        assert!(avl_tree.is_balanced());
+        assert!(avl_tree.check(HashSet::new()).is_empty());
+    }
+
+    #[test]
+    fn test_display_precision() {
+        let mut avl_tree: AVLTree<f64, OrderStack, Order> = AVLTree::new();
+        for key in [19123.456789, 19123.5, 19124.0] {
+            avl_tree.insert(key, None);
+        }
+        let lines = avl_tree.display();
+        assert!(lines[0].contains("19123.50/1"));
+        assert!(lines.iter().any(|line| line.contains("19123.46/1")));
+
+        avl_tree.set_display_precision(4);
+        let lines = avl_tree.display();
+        assert!(lines[0].contains("19123.5000/1"));
+        assert!(lines.iter().any(|line| line.contains("19123.4568/1")));
+        assert!(lines.iter().any(|line| line.contains("19124.0000/1")));
+    }
+
+    #[test]
+    fn test_check() {
+        let mut avl_tree: AVLTree<i32, OrderStack, Order> = AVLTree::new();
+        let mut rng = rand::thread_rng();
+        for _ in 0..50 {
+            avl_tree.insert(rng.gen_range(0..100), None);
+        }
+        assert!(avl_tree.check(HashSet::new()).is_empty());
+
+        avl_tree.len += 1;
+        let error_msgs = avl_tree.check(HashSet::new());
+        assert_eq!(error_msgs.len(), 1);
+        assert!(error_msgs.iter().next().unwrap().starts_with("Node count mismatch"));
+        avl_tree.len -= 1;
+    }
+
+    fn recursive_height<K, V>(link: &Link<K, V>) -> isize
+        where K: Display + Debug + PartialOrd + Clone
+    {
+        match link {
+            None => 0,
+            Some(node_ptr) => {
+                let node = unsafe { &(*node_ptr.as_ptr()) };
+                max(recursive_height(&node.left), recursive_height(&node.right)) + 1
+            }
+        }
+    }
+
+    #[test]
+    fn test_height() {
+        let mut avl_tree: AVLTree<i32, OrderStack, Order> = AVLTree::new();
+        assert_eq!(avl_tree.tree_height(), 0);
+        let mut keys: Vec<i32> = (0..2000).collect();
+        keys.shuffle(&mut rand::thread_rng());
+        for key in keys {
+            avl_tree.insert(key, None);
+        }
+        assert_eq!(avl_tree.tree_height(), recursive_height(&avl_tree.root));
+        let mut tree_iter = avl_tree.iter();
+        while let Some(node) = tree_iter.next() {
+            let link: Link<i32, OrderStack> = Some(NonNull::from(node));
+            assert_eq!(AVLTree::<i32, OrderStack, Order>::height(&link), recursive_height(&link));
+        }
+
+        // stored heights stay exact through removals and the rotations they trigger
+        let mut keys: Vec<i32> = (0..2000).collect();
+        keys.shuffle(&mut rand::thread_rng());
+        for key in &keys[..1500] {
+            avl_tree.remove(key);
+            assert!(avl_tree.is_balanced());
+        }
+        assert!(avl_tree.check(HashSet::new()).is_empty());
+        assert_eq!(avl_tree.tree_height(), recursive_height(&avl_tree.root));
+    }
+
+    #[test]
+    fn test_degenerate_tree() {
+        // hand-build a right-leaning chain far deeper than recursion could handle
+        let depth = 1_000_000;
+        let mut avl_tree: AVLTree<i32, OrderStack, Order> = AVLTree::new();
+        let mut parent: Link<i32, OrderStack> = None;
+        for key in 0..depth {
+            let node = Node::new(key, OrderStack::new(), parent);
+            unsafe { (*node.as_ptr()).height = (depth - key) as isize };
+            match parent {
+                None => avl_tree.root = Some(node),
+                Some(parent) => unsafe { (*parent.as_ptr()).right = Some(node) },
+            }
+            parent = Some(node);
+        }
+        avl_tree.len = depth as usize;
+
+        assert_eq!(avl_tree.tree_height(), depth as isize);
+        let lines = avl_tree.display();
+        assert_eq!(lines, vec![format!(
+            "Tree too deep to display (height {} > {}, {} nodes)", depth, MAX_DISPLAY_HEIGHT, depth
+        )]);
+
+        // free the chain directly, since removals would rebalance it node by node
+        let mut link = avl_tree.root.take();
+        while let Some(node_ptr) = link {
+            let node = unsafe { Box::from_raw(node_ptr.as_ptr()) };
+            link = node.right;
+        }
+        avl_tree.len = 0;
+    }
+
+    #[test]
+    fn test_iter_from() {
+        let mut avl_tree: AVLTree<i32, OrderStack, Order> = AVLTree::new();
+        assert_eq!(avl_tree.iter_from(&5).count(), 0);
+        let mut rng = rand::thread_rng();
+        for _ in 0..200 {
+            avl_tree.insert(rng.gen_range(0..500) * 2, None);
+        }
+        for start in -1..1002 {
+            let expected: Vec<i32> = avl_tree.iter().skip_while(|node| node.key < start).map(|node| node.key).collect();
+            let actual: Vec<i32> = avl_tree.iter_from(&start).map(|node| node.key).collect();
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let mut avl_tree: AVLTree<i32, OrderStack, Order> = AVLTree::new();
+        for key in [5, 3, 8, 1, 4, 7, 9, 2, 6] {
+            avl_tree.insert(key, None);
+        }
+        assert_eq!(avl_tree.pop_first().map(|(key, _)| key), Some(1));
+        assert_eq!(avl_tree.pop_last().map(|(key, _)| key), Some(9));
+        assert!(avl_tree.is_balanced());
+        assert_eq!(avl_tree.len(), 7);
+
+        let keys: Vec<i32> = avl_tree.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn test_successor_strategies() {
+        let mut rng = rand::thread_rng();
+        for strategy in [SuccessorStrategy::MinUnderRight, SuccessorStrategy::MaxUnderLeft] {
+            let mut avl_tree: AVLTree<i32, OrderStack, Order> = AVLTree::new();
+            avl_tree.set_successor_strategy(strategy);
+            let mut keys: Vec<i32> = (0..300).collect();
+            keys.shuffle(&mut rng);
+            for key in &keys {
+                avl_tree.insert(*key, None);
+            }
+            let mut remaining: Vec<i32> = (0..300).collect();
+
+            // repeatedly remove a node with two children
+            for _ in 0..150 {
+                let internal = avl_tree.structure().into_iter()
+                    .filter(|(_, left, right, _)| left.is_some() && right.is_some())
+                    .map(|(key, _, _, _)| key)
+                    .choose(&mut rng);
+                let key = match internal {
+                    Some(key) => key,
+                    None => break,
+                };
+                assert_eq!(avl_tree.remove(&key).map(|(key, _)| key), Some(key));
+                remaining.retain(|&k| k != key);
+
+                assert!(avl_tree.is_balanced(), "{:?} left the tree unbalanced", strategy);
+                assert!(avl_tree.check(HashSet::new()).is_empty());
+                assert_eq!(avl_tree.iter().map(|node| node.key).collect::<Vec<i32>>(), remaining);
+            }
+        }
+    }
+
+    #[test]
+    fn test_node_reuse() {
+        let mut rng = rand::thread_rng();
+        let mut avl_tree: AVLTree<i32, OrderStack, Order> = AVLTree::new();
+        let mut keys: Vec<i32> = (0..500).collect();
+        for key in &keys {
+            avl_tree.insert(*key, None);
+        }
+        assert_eq!(avl_tree.reused_nodes(), 0);
+
+        // churn: remove and reinsert random keys
+        for _ in 0..2000 {
+            let key = *keys.choose(&mut rng).unwrap();
+            assert_eq!(avl_tree.remove(&key).map(|(key, value)| (key, value.len())), Some((key, 1)));
+            avl_tree.insert(key, None);
+        }
+        assert_eq!(avl_tree.reused_nodes(), 2000);
+        assert!(avl_tree.is_balanced());
+        assert!(avl_tree.check(HashSet::new()).is_empty());
+        assert_eq!(avl_tree.iter().map(|node| node.key).collect::<Vec<i32>>(), keys);
+        assert!(avl_tree.iter().all(|node| node.value.len() == 1));
+
+        // the free list is capped
+        keys.extend(500..(FREE_LIST_CAPACITY as i32 + 100));
+        for key in &keys[500..] {
+            avl_tree.insert(*key, None);
+        }
+        avl_tree.clear();
+        assert_eq!(avl_tree.free_list.len(), FREE_LIST_CAPACITY);
+        for key in &keys {
+            avl_tree.insert(*key, None);
+        }
+        assert_eq!(avl_tree.reused_nodes(), 2000 + FREE_LIST_CAPACITY);
+        assert_eq!(avl_tree.len(), keys.len());
+        assert!(avl_tree.check(HashSet::new()).is_empty());
+    }
+
+    #[test]
+    fn test_key_epsilon() {
+        let mut avl_tree: AVLTree<f64, OrderStack, Order> = AVLTree::new();
+        avl_tree.set_key_epsilon(Some(1e-9));
+        let mut rng = rand::thread_rng();
+        let prices: Vec<f64> = (0..200).map(|i| 100.0 + i as f64 * 0.01).collect();
+        for price in &prices {
+            avl_tree.insert(*price, None);
+        }
+        // near-duplicates from float arithmetic, e.g. 100.1 vs 100.0 + 10 * 0.01
+        for _ in 0..1000 {
+            let price = *prices.choose(&mut rng).unwrap();
+            let noise = rng.gen_range(-1e-10..1e-10);
+            avl_tree.insert(price + noise, None);
+            assert!(avl_tree.has(&(price - noise)));
+        }
+        assert_eq!(avl_tree.len(), prices.len());
+        assert_eq!(avl_tree.iter().map(|node| node.key).collect::<Vec<f64>>(), prices);
+        assert_eq!(avl_tree.iter().map(|node| node.value.len()).sum::<usize>(), 1200);
+        assert!(avl_tree.is_balanced());
+        assert!(avl_tree.check(HashSet::new()).is_empty());
+        assert!(!avl_tree.has(&100.005));
+        assert!(avl_tree.remove(&(100.5 + 5e-10)).is_some());
+        assert!(!avl_tree.has(&100.5));
+
+        // exact comparison by default
+        avl_tree.set_key_epsilon(None);
+        avl_tree.insert(prices[20] + 5e-10, None);
+        assert_eq!(avl_tree.len(), prices.len());
+        assert!(!avl_tree.has(&(prices[20] - 5e-10)));
+    }
 }
@@ -0,0 +1,203 @@
+#![allow(dead_code)]
+
+// Crates
+use chrono::{DateTime, Duration, Utc};
+
+/// Longest interval or tolerance a builder accepts, one year. Spans near chrono's Duration
+/// limit would overflow DateTime arithmetic when a candle's end is computed.
+const MAX_SPAN_SECS: i64 = 365 * 24 * 60 * 60;
+
+/// OHLCV candle covering the half-open interval [start, end)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candle {
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Builds OHLCV candles of a fixed interval from a stream of trades.
+///
+/// Trades that arrive out of order are folded into the open candle as long as they're no
+/// older than the candle's start minus the tolerance; they can move its high, low and volume
+/// but never its close. Anything older is counted in late_trades and dropped.
+pub struct CandleBuilder {
+    interval: Duration,
+    tolerance: Duration,
+    fill_gaps: bool,
+    current: Option<Candle>,
+    last_trade: Option<DateTime<Utc>>,
+    late_trades: usize,
+}
+
+impl Candle {
+    /// Create a candle from the first trade in its interval
+    fn open(start: DateTime<Utc>, interval: Duration, price: f64, size: f64) -> Self {
+        Candle { open: price, high: price, low: price, close: price, volume: size, start, end: start + interval }
+    }
+
+    /// Create an empty candle for an interval without trades, flat at the previous close
+    fn empty(start: DateTime<Utc>, interval: Duration, close: f64) -> Self {
+        Candle { open: close, high: close, low: close, close, volume: 0.0, start, end: start + interval }
+    }
+}
+
+impl CandleBuilder {
+    /// Create a candle builder for the passed interval. If fill_gaps is true, intervals
+    /// without trades are emitted as empty candles. Errors if the interval isn't positive, or
+    /// if the interval or tolerance is longer than MAX_SPAN_SECS.
+    pub fn new(interval_secs: i64, tolerance_secs: i64, fill_gaps: bool) -> Result<Self, String> {
+        if interval_secs <= 0 {
+            return Err(format!("candle interval must be positive, got {}", interval_secs))
+        }
+        if interval_secs > MAX_SPAN_SECS || tolerance_secs > MAX_SPAN_SECS {
+            return Err(format!(
+                "candle interval and tolerance must be at most {} seconds, got {} and {}",
+                MAX_SPAN_SECS, interval_secs, tolerance_secs
+            ))
+        }
+        Ok(CandleBuilder {
+            interval: Duration::seconds(interval_secs),
+            tolerance: Duration::seconds(tolerance_secs.max(0)),
+            fill_gaps,
+            current: None,
+            last_trade: None,
+            late_trades: 0,
+        })
+    }
+
+    /// Return the candle currently being built
+    pub fn current(&self) -> Option<&Candle> {
+        self.current.as_ref()
+    }
+
+    /// Return count of trades dropped for arriving later than the tolerance allows
+    pub fn late_trades(&self) -> usize {
+        self.late_trades
+    }
+
+    /// Consume a trade. Returns any candles closed by it, oldest first.
+    pub fn push(&mut self, timestamp: DateTime<Utc>, price: f64, size: f64) -> Vec<Candle> {
+        let mut closed: Vec<Candle> = Vec::new();
+
+        let (start, end) = match &self.current {
+            None => {
+                self.current = Some(Candle::open(self.bucket_start(timestamp), self.interval, price, size));
+                self.last_trade = Some(timestamp);
+                return closed
+            },
+            Some(candle) => (candle.start, candle.end),
+        };
+
+        if timestamp >= end {
+            // trade belongs to a later interval, so close the current candle
+            let previous = self.current.take().unwrap();
+            let bucket_start = self.bucket_start(timestamp);
+            let mut gap_start = previous.end;
+            let close = previous.close;
+            closed.push(previous);
+            if self.fill_gaps {
+                while gap_start < bucket_start {
+                    closed.push(Candle::empty(gap_start, self.interval, close));
+                    gap_start = gap_start + self.interval;
+                }
+            }
+            self.current = Some(Candle::open(bucket_start, self.interval, price, size));
+            self.last_trade = Some(timestamp);
+        } else if timestamp >= start - self.tolerance {
+            let candle = self.current.as_mut().unwrap();
+            candle.high = candle.high.max(price);
+            candle.low = candle.low.min(price);
+            candle.volume += size;
+            // out-of-order trades don't move the close
+            if self.last_trade.map_or(true, |last| timestamp >= last) {
+                candle.close = price;
+                self.last_trade = Some(timestamp);
+            }
+        } else {
+            self.late_trades += 1;
+        }
+        closed
+    }
+
+    /// Close and return the candle currently being built
+    pub fn flush(&mut self) -> Option<Candle> {
+        self.last_trade = None;
+        self.current.take()
+    }
+
+    /// Return the start of the interval containing the passed timestamp
+    fn bucket_start(&self, timestamp: DateTime<Utc>) -> DateTime<Utc> {
+        let offset = timestamp.timestamp_millis().rem_euclid(self.interval.num_milliseconds());
+        timestamp - Duration::milliseconds(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> DateTime<Utc> {
+        format!("2022-09-27T19:{}Z", s).parse::<DateTime<Utc>>().unwrap()
+    }
+
+    #[test]
+    fn candles() {
+        let mut builder = CandleBuilder::new(60, 1, true).unwrap();
+
+        assert!(builder.push(time("31:10.000000"), 10.0, 1.0).is_empty());
+        assert!(builder.push(time("31:50.000000"), 12.0, 2.0).is_empty());
+        // out of order within the interval: moves the low but not the close
+        assert!(builder.push(time("31:30.000000"), 9.0, 1.0).is_empty());
+
+        let closed = builder.push(time("32:05.500000"), 11.0, 1.0);
+        assert_eq!(closed, vec![Candle {
+            open: 10.0, high: 12.0, low: 9.0, close: 12.0, volume: 4.0,
+            start: time("31:00.000000"), end: time("32:00.000000"),
+        }]);
+
+        // late but within tolerance: folded into the open candle
+        assert!(builder.push(time("31:59.500000"), 13.0, 0.5).is_empty());
+        // later than tolerance: dropped
+        assert!(builder.push(time("31:30.000000"), 1.0, 100.0).is_empty());
+        assert_eq!(builder.late_trades(), 1);
+
+        // skipping an interval emits an empty gap candle
+        let closed = builder.push(time("34:10.000000"), 15.0, 3.0);
+        assert_eq!(closed, vec![
+            Candle {
+                open: 11.0, high: 13.0, low: 11.0, close: 11.0, volume: 1.5,
+                start: time("32:00.000000"), end: time("33:00.000000"),
+            },
+            Candle {
+                open: 11.0, high: 11.0, low: 11.0, close: 11.0, volume: 0.0,
+                start: time("33:00.000000"), end: time("34:00.000000"),
+            },
+        ]);
+
+        assert_eq!(builder.flush(), Some(Candle {
+            open: 15.0, high: 15.0, low: 15.0, close: 15.0, volume: 3.0,
+            start: time("34:00.000000"), end: time("35:00.000000"),
+        }));
+        assert_eq!(builder.current(), None);
+    }
+
+    #[test]
+    fn candles_without_gap_filling() {
+        assert!(CandleBuilder::new(0, 0, false).is_err());
+        assert!(CandleBuilder::new(-60, 0, false).is_err());
+        assert!(CandleBuilder::new(i64::MAX, 0, false).is_err());
+        assert!(CandleBuilder::new(60, i64::MAX, false).is_err());
+        let mut longest = CandleBuilder::new(MAX_SPAN_SECS, MAX_SPAN_SECS, false).unwrap();
+        assert!(longest.push(time("31:00"), 100.0, 1.0).is_empty());
+
+        let mut builder = CandleBuilder::new(60, 0, false).unwrap();
+        builder.push(time("31:10.000000"), 10.0, 1.0);
+        let closed = builder.push(time("35:10.000000"), 11.0, 1.0);
+        assert_eq!(closed.len(), 1);
+        assert_eq!(builder.current().unwrap().start, time("35:00.000000"));
+    }
+}
@@ -0,0 +1,175 @@
+#![allow(dead_code)]
+
+// Standard Library
+use std::str::FromStr;
+// Crates
+use rust_decimal::Decimal;
+use serde::Deserialize;
+// Homebrew
+use crate::orderbook::{Order, Side};
+
+/// Error raised when a Coinbase feed message can't be parsed
+#[derive(Clone, Debug, PartialEq)]
+pub enum CoinbaseError {
+    MissingField(String),
+    InvalidNumber { field: String, value: String },
+    InvalidSide(String),
+}
+
+impl std::fmt::Display for CoinbaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CoinbaseError::MissingField(field) => write!(f, "message is missing {}", field),
+            CoinbaseError::InvalidNumber { field, value } => write!(f, "invalid {} {:?}", field, value),
+            CoinbaseError::InvalidSide(side) => write!(f, "invalid side {:?}", side),
+        }
+    }
+}
+
+impl std::error::Error for CoinbaseError {}
+
+/// Parse a numeric string field, e.g. a price of "1900.05", exactly. Going through f64
+/// would round it to the nearest binary fraction; the Decimal keeps every digit and the
+/// scale, trailing zeros included.
+pub fn parse_decimal(field: &str, value: &str) -> Result<Decimal, CoinbaseError> {
+    Decimal::from_str(value).map_err(|_| CoinbaseError::InvalidNumber {
+        field: field.to_string(),
+        value: value.to_string(),
+    })
+}
+
+/// Convert a Decimal to the nearest f64, for the f64-keyed book. Only do this at the last
+/// moment, once the value is about to enter the book.
+pub fn decimal_to_f64(value: Decimal) -> f64 {
+    // the shortest decimal string parses to the correctly rounded f64
+    value.to_string().parse().unwrap()
+}
+
+/// Map Coinbase's "buy"/"sell" to the side of the book the order rests on
+pub fn parse_side(side: &str) -> Result<Side, CoinbaseError> {
+    match side {
+        "buy" => Ok(Side::Bids),
+        "sell" => Ok(Side::Asks),
+        _ => Err(CoinbaseError::InvalidSide(side.to_string())),
+    }
+}
+
+/// One change from an l2update message, e.g. ["buy", "10101.80000000", "0.162567"].
+/// A size of zero removes the level.
+#[derive(Clone, Debug, PartialEq)]
+pub struct L2Change {
+    pub side: Side,
+    pub price: Decimal,
+    pub size: Decimal,
+}
+
+/// Parse one [side, price, size] entry of an l2update message's changes
+pub fn parse_l2_change(change: &[String; 3]) -> Result<L2Change, CoinbaseError> {
+    Ok(L2Change {
+        side: parse_side(&change[0])?,
+        price: parse_decimal("price", &change[1])?,
+        size: parse_decimal("size", &change[2])?,
+    })
+}
+
+/// Order fields of a full channel (L3) message: received, open, change or done.
+/// Numbers arrive as strings and stay strings until parsed with parse_decimal.
+#[derive(Clone, Debug, Deserialize)]
+pub struct L3Message {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub time: String,
+    pub order_id: String,
+    pub side: String,
+    pub price: Option<String>,
+    pub size: Option<String>,
+    pub remaining_size: Option<String>,
+    pub new_size: Option<String>,
+    pub reason: Option<String>,
+}
+
+impl L3Message {
+    /// Return the message's price, parsed exactly
+    pub fn price(&self) -> Result<Decimal, CoinbaseError> {
+        let price = self.price.as_deref().ok_or_else(|| CoinbaseError::MissingField("price".to_string()))?;
+        parse_decimal("price", price)
+    }
+
+    /// Return the order's size after the message, parsed exactly: new_size for a change,
+    /// remaining_size for open and done, size for received
+    pub fn size(&self) -> Result<Decimal, CoinbaseError> {
+        let (field, size) = [("new_size", &self.new_size), ("remaining_size", &self.remaining_size), ("size", &self.size)]
+            .into_iter()
+            .find_map(|(field, size)| size.as_deref().map(|size| (field, size)))
+            .ok_or_else(|| CoinbaseError::MissingField("size".to_string()))?;
+        parse_decimal(field, size)
+    }
+
+    /// Build an Order for the book, converting price and size to f64 only here
+    pub fn to_order(&self) -> Result<Order, CoinbaseError> {
+        let mut order = Order::new(
+            self.order_id.clone(),
+            Some(parse_side(&self.side)?),
+            Some(decimal_to_f64(self.price()?)),
+            Some(decimal_to_f64(self.size()?)),
+            self.time.clone(),
+        );
+        order.reason = self.reason.clone();
+        Ok(order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_decimals() {
+        let price = parse_decimal("price", "1900.050").unwrap();
+        assert_eq!(price, Decimal::new(190005, 2));
+        assert_eq!(price.scale(), 3);
+        assert_eq!(price.to_string(), "1900.050");
+
+        let size = parse_decimal("size", "0.1234567890123456789012345678").unwrap();
+        assert_eq!(size.to_string(), "0.1234567890123456789012345678");
+        assert_eq!(size.scale(), 28);
+
+        assert_eq!(parse_decimal("size", "10.00000000").unwrap().to_string(), "10.00000000");
+        assert_eq!(decimal_to_f64(price), 1900.05);
+        assert_eq!(decimal_to_f64(Decimal::new(1, 1)), 0.1);
+        assert_eq!(parse_decimal("price", "1,900.05"),
+                   Err(CoinbaseError::InvalidNumber { field: "price".to_string(), value: "1,900.05".to_string() }));
+    }
+
+    #[test]
+    fn parse_messages() {
+        let change = ["sell".to_string(), "10101.80000000".to_string(), "0.162567".to_string()];
+        assert_eq!(parse_l2_change(&change).unwrap(), L2Change {
+            side: Side::Asks,
+            price: Decimal::new(101018, 1),
+            size: Decimal::new(162567, 6),
+        });
+
+        let mut message = L3Message {
+            kind: "open".to_string(),
+            time: "2022-09-27T19:31:00.123456Z".to_string(),
+            order_id: "d50ec984".to_string(),
+            side: "buy".to_string(),
+            price: Some("200.20".to_string()),
+            size: None,
+            remaining_size: Some("1.00".to_string()),
+            new_size: None,
+            reason: None,
+        };
+        assert_eq!(message.to_order().unwrap(), Order::new(
+            "d50ec984".to_string(), Some(Side::Bids), Some(200.2), Some(1.0), "2022-09-27T19:31:00.123456Z".to_string()
+        ));
+
+        message.new_size = Some("0.5".to_string());
+        assert_eq!(message.size().unwrap(), Decimal::new(5, 1));
+        message.price = None;
+        assert_eq!(message.to_order(), Err(CoinbaseError::MissingField("price".to_string())));
+        message.side = "short".to_string();
+        assert_eq!(message.to_order(), Err(CoinbaseError::InvalidSide("short".to_string())));
+    }
+}
@@ -0,0 +1,214 @@
+#![allow(dead_code)]
+
+// Standard Library
+use std::future::Future;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+// Crates
+use futures::{Stream, StreamExt};
+// Homebrew
+use crate::orderbook::{LimitOrderbook, Order, Submit};
+use crate::snapshotter::Snapshotter;
+use crate::wire::encode_update;
+
+/// Applies a live stream of L3 updates to a book, recording every update it receives so
+/// the session can be replayed. Records are encode_update messages, each prefixed with
+/// its length as a little-endian u32.
+pub struct Feed<W: Write> {
+    book: LimitOrderbook,
+    recorder: BufWriter<W>,
+    snapshotter: Snapshotter,
+}
+
+/// What a feed did before it stopped
+#[derive(Debug)]
+pub struct FeedSummary {
+    pub events: usize,
+    pub duration: Duration,
+    pub snapshot: PathBuf,
+}
+
+impl<W: Write> Feed<W> {
+    /// Create a feed applying updates to book, recording them to recorder and writing
+    /// the final snapshot with snapshotter
+    pub fn new(book: LimitOrderbook, recorder: W, snapshotter: Snapshotter) -> Self {
+        Feed {
+            book,
+            recorder: BufWriter::new(recorder),
+            snapshotter,
+        }
+    }
+
+    /// Return the book the feed maintains
+    pub fn book(&self) -> &LimitOrderbook {
+        &self.book
+    }
+
+    /// Return the recorder's underlying writer. Records still buffered aren't in it yet.
+    pub fn recorder(&self) -> &W {
+        self.recorder.get_ref()
+    }
+
+    /// Record and apply updates from the stream until it ends or shutdown resolves, e.g.
+    /// signal::ctrl_c(). Then flush the recorder, snapshot the book and print a summary.
+    ///
+    /// Shutdown is polled first, so a pending shutdown stops the loop before the next
+    /// update. Updates the book rejects are logged and still recorded. A failed write to the
+    /// recorder also stops the loop; the recorder is still flushed and the book snapshotted
+    /// before the first error is returned.
+    pub async fn run_until<S, F>(&mut self, mut stream: S, shutdown: F) -> io::Result<FeedSummary>
+        where S: Stream<Item=(Order, Submit)> + Unpin,
+              F: Future,
+    {
+        let start = Instant::now();
+        let mut events = 0;
+        let mut recorded = Ok(());
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                biased;
+                _ = &mut shutdown => {
+                    println!("Shutdown requested, stopping feed");
+                    break
+                },
+                next = stream.next() => match next {
+                    Some((order, action)) => {
+                        if let Err(e) = self.record(&order, &action) {
+                            println!("Warning: failed to record update for order {}, stopping feed: {}", order.uid, e);
+                            recorded = Err(e);
+                            break
+                        }
+                        let uid = order.uid.clone();
+                        if let Err(e) = self.book.process(order, action) {
+                            println!("Warning: failed to apply update for order {}: {}", uid, e);
+                        }
+                        events += 1;
+                    },
+                    None => break,
+                },
+            }
+        }
+        let flushed = self.recorder.flush();
+        let snapshot = self.snapshotter.write(&self.book);
+        recorded?;
+        flushed?;
+        let snapshot = snapshot?;
+        let duration = start.elapsed();
+        println!("Processed {} events in {:.1}s, final snapshot written to {}",
+                 events, duration.as_secs_f64(), snapshot.display());
+        Ok(FeedSummary { events, duration, snapshot })
+    }
+
+    fn record(&mut self, order: &Order, action: &Submit) -> io::Result<()> {
+        let message = encode_update(order, action);
+        self.recorder.write_all(&(message.len() as u32).to_le_bytes())?;
+        self.recorder.write_all(&message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use futures::executor::block_on;
+    use futures::future;
+    use futures::stream;
+    use crate::orderbook::Side;
+    use crate::wire::decode_update;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rust_orderbook_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn updates() -> Vec<(Order, Submit)> {
+        let order = |uid: &str, side: Side, price: f64, size: f64| {
+            Order::new(uid.to_string(), Some(side), Some(price), Some(size), "dummy_datetime".to_string())
+        };
+        vec![
+            (order("0", Side::Bids, 99.0, 1.0), Submit::Insert),
+            (order("1", Side::Asks, 101.0, 2.0), Submit::Insert),
+            (order("0", Side::Bids, 99.0, 3.0), Submit::Update),
+        ]
+    }
+
+    /// Writer whose every write fails, like a full disk
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::Other, "disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn records(mut bytes: &[u8]) -> Vec<(Order, Submit)> {
+        let mut records = Vec::new();
+        while !bytes.is_empty() {
+            let len = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize;
+            records.push(decode_update(&bytes[4..4 + len]).unwrap());
+            bytes = &bytes[4 + len..];
+        }
+        records
+    }
+
+    #[test]
+    fn shutdown() {
+        let dir = temp_dir("feed_shutdown");
+        let mut book = LimitOrderbook::new();
+        let (order, action) = updates().remove(0);
+        book.process(order, action).unwrap();
+        let mut feed = Feed::new(book, Vec::new(), Snapshotter::new(&dir, 1));
+
+        // shutdown is already resolved, so the stream is never read
+        let summary = block_on(feed.run_until(stream::iter(updates()), future::ready(()))).unwrap();
+        assert_eq!(summary.events, 0);
+        assert!(feed.recorder().is_empty());
+        assert_eq!(feed.book().len(), 1);
+        // the book's state at shutdown is snapshotted
+        assert_eq!(fs::read_to_string(&summary.snapshot).unwrap(), feed.book().to_snapshot_json());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stream_end() {
+        let dir = temp_dir("feed_stream_end");
+        let mut feed = Feed::new(LimitOrderbook::new(), Vec::new(), Snapshotter::new(&dir, 1));
+
+        let summary = block_on(feed.run_until(stream::iter(updates()), future::pending::<()>())).unwrap();
+        assert_eq!(summary.events, 3);
+        assert_eq!(feed.book().len(), 2);
+        assert_eq!(feed.book().get_order("0".to_string()).unwrap().size, 3.0);
+        // every update is flushed to the recorder
+        let recorded = records(feed.recorder());
+        assert_eq!(recorded.len(), 3);
+        assert_eq!(format!("{:?}", recorded), format!("{:?}", updates()));
+        assert!(summary.snapshot.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn record_error() {
+        let dir = temp_dir("feed_record_error");
+        let mut feed = Feed::new(LimitOrderbook::new(), FailingWriter, Snapshotter::new(&dir, 1));
+
+        // enough updates to overflow the recorder's buffer, so a record write fails mid-stream
+        let updates = (0..1000).map(|uid| {
+            (Order::new(uid.to_string(), Some(Side::Bids), Some(99.0), Some(1.0), "dummy_datetime".to_string()), Submit::Insert)
+        });
+        assert!(block_on(feed.run_until(stream::iter(updates), future::pending::<()>())).is_err());
+        let applied = feed.book().len();
+        assert!(applied > 0 && applied < 1000);
+        // the book is still snapshotted
+        let snapshots: Vec<PathBuf> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(fs::read_to_string(&snapshots[0]).unwrap(), feed.book().to_snapshot_json());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,12 +1,16 @@
 #![allow(dead_code)]
 
+use std::future::Future;
+use std::time::{Duration, Instant};
+
 use barter_data::{
     builder::Streams,
     model::{MarketEvent, subscription::SubKind},
     ExchangeId,
 };
 use barter_integration::model::InstrumentKind;
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
+use tokio::signal;
 
 #[tokio::main]
 async fn websocket() {
@@ -22,10 +26,39 @@ async fn websocket() {
 
     // Join all exchange streams into a StreamMap
     // Note: Use `streams.select(ExchangeId)` to interact with the individual exchange streams!
-    let mut joined_stream = streams.join_map::<MarketEvent>().await;
+    let joined_stream = streams.join_map::<MarketEvent>().await;
+
+    let (events, duration) = run_until(joined_stream, signal::ctrl_c()).await;
+    println!("Processed {} events in {:.1}s", events, duration.as_secs_f64());
+}
 
-    while let Some((exchange, event)) = joined_stream.next().await {
-        println!("Exchange: {}, MarketEvent: {:?}", exchange, event);
-        println!("{:?}", event.kind);
+/// Print events from the stream until it ends or shutdown resolves, e.g. on Ctrl+C.
+/// Returns count of events processed and how long the loop ran.
+///
+/// Events are only printed here; feed::Feed::run_until is the variant that records L3
+/// updates and applies them to a book, flushing and snapshotting on shutdown.
+async fn run_until<S, E, F>(mut stream: S, shutdown: F) -> (usize, Duration)
+    where S: Stream<Item=(ExchangeId, E)> + Unpin,
+          E: std::fmt::Debug,
+          F: Future,
+{
+    let start = Instant::now();
+    let mut events = 0;
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => {
+                println!("Shutdown requested, stopping stream");
+                break
+            },
+            next = stream.next() => match next {
+                Some((exchange, event)) => {
+                    println!("Exchange: {}, MarketEvent: {:?}", exchange, event);
+                    events += 1;
+                },
+                None => break,
+            },
+        }
     }
-}
\ No newline at end of file
+    (events, start.elapsed())
+}
@@ -1,4 +1,13 @@
 pub mod orderbook;
+pub mod sync;
+pub mod candles;
+pub mod strategy;
+pub mod replay;
+pub mod snapshotter;
+pub mod manager;
+pub mod coinbase;
+pub mod wire;
+pub mod feed;
 mod avl_tree;
 
 use pyo3::prelude::*;
@@ -6,12 +15,15 @@ use orderbook::*;
 
 /// An unsafe AVL-tree limit orderbook written in Rust
 ///
-/// No UB and thread-safety not guaranteed
+/// No UB and thread-safety not guaranteed; share a book across threads via `sync::SyncOrderbook`
 #[pymodule]
 fn rust_orderbook(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<LimitOrderbook>()?;
     m.add_class::<Order>()?;
     m.add_class::<Side>()?;
     m.add_class::<Submit>()?;
+    m.add_class::<MatchPolicy>()?;
+    m.add_class::<OutlierMode>()?;
+    m.add_class::<SizeUnit>()?;
     Ok(())
 }
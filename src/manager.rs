@@ -0,0 +1,89 @@
+#![allow(dead_code)]
+
+// Standard Library
+use std::collections::HashMap;
+// Crates
+use pyo3::PyResult;
+// Homebrew
+use crate::orderbook::{LimitOrderbook, Order, Submit};
+
+/// Routes updates for many markets to one orderbook per product id, e.g. "ETH-USD",
+/// creating each book the first time its product is seen
+pub struct OrderbookManager {
+    books: HashMap<String, LimitOrderbook>,
+}
+
+impl OrderbookManager {
+    /// Create a manager with no books
+    pub fn new() -> Self {
+        OrderbookManager { books: HashMap::new() }
+    }
+
+    /// Process an update on the product's book, creating the book if needed
+    pub fn process(&mut self, product: &str, order: Order, action: Submit) -> PyResult<()> {
+        // look up first so the product id is only copied when a book is created
+        match self.books.get_mut(product) {
+            Some(book) => book.process(order, action),
+            None => {
+                let book = self.books.entry(product.to_string()).or_insert_with(LimitOrderbook::new);
+                book.process(order, action)
+            },
+        }
+    }
+
+    /// Return the product's book, if any update for it has been processed
+    pub fn book(&self, product: &str) -> Option<&LimitOrderbook> {
+        self.books.get(product)
+    }
+
+    /// Return the product ids with a book, sorted
+    pub fn products(&self) -> Vec<String> {
+        let mut products: Vec<String> = self.books.keys().cloned().collect();
+        products.sort();
+        products
+    }
+
+    /// Return count of resting orders across every book
+    pub fn total_orders(&self) -> usize {
+        self.books.values().map(|book| book.len()).sum()
+    }
+}
+
+impl Default for OrderbookManager {
+    fn default() -> Self {
+        OrderbookManager::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::Side;
+
+    fn order(uid: &str, side: Side, price: f64, size: f64) -> Order {
+        Order::new(uid.to_string(), Some(side), Some(price), Some(size), "dummy_datetime".to_string())
+    }
+
+    #[test]
+    fn routing() {
+        let mut manager = OrderbookManager::new();
+        assert!(manager.book("ETH-USD").is_none());
+        assert_eq!(manager.total_orders(), 0);
+
+        manager.process("ETH-USD", order("0", Side::Bids, 1300.0, 1.0), Submit::Insert).unwrap();
+        manager.process("ETH-USD", order("1", Side::Asks, 1301.0, 2.0), Submit::Insert).unwrap();
+        manager.process("BTC-USD", order("0", Side::Bids, 19000.0, 0.5), Submit::Insert).unwrap();
+        assert_eq!(manager.products(), vec!["BTC-USD".to_string(), "ETH-USD".to_string()]);
+        assert_eq!(manager.total_orders(), 3);
+
+        // the same uid on another product is a different order
+        manager.process("BTC-USD", order("0", Side::Bids, 19000.0, 0.0), Submit::Remove).unwrap();
+        let eth = manager.book("ETH-USD").unwrap();
+        let btc = manager.book("BTC-USD").unwrap();
+        assert_eq!(eth.len(), 2);
+        assert_eq!(eth.best_bid(), Some(1300.0));
+        assert!(btc.is_empty());
+        assert_eq!(btc.items_processed(), 2);
+        assert_eq!(manager.total_orders(), 2);
+    }
+}
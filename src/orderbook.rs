@@ -7,18 +7,29 @@
 // Standard Library
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::Debug;
+use std::io::BufRead;
 use std::iter::{Peekable};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, OnceLock};
+use std::sync::mpsc::Sender;
+use std::sync::atomic::{AtomicU64, Ordering};
 use cc_traits::{Collection, Len, PushBack};
+use parking_lot::Mutex;
 // Crates
 use serde::{Serialize, Deserialize};
 use pyo3::prelude::*;
-use chrono::Utc;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::types::PyBytes;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use crate::avl_tree;
 use crate::avl_tree::New;
 // Homebrew
 use crate::avl_tree::{AVLTree, Node};
 
 
+/// Sequence used to generate process-unique uids for synthetic orders
+static AUTO_UID_SEQ: AtomicU64 = AtomicU64::new(0);
+
 /// Struct representing the Limit orderbook of a single market
 #[pyclass]
 pub struct LimitOrderbook {
@@ -31,9 +42,46 @@ pub struct LimitOrderbook {
     avl_tree_size_display_cutoff: usize,
     timestamp: String,
     outlier_factor: f64,
+    outlier_mode: OutlierMode,
+    outlier_abs_tol: f64,
     bid_cutoff: f64,
     ask_cutoff: f64,
     outliers: usize,
+    max_order_size: Option<f64>,
+    rejected: usize,
+    last_bid_update: Option<String>,
+    last_ask_update: Option<String>,
+    event_sink: Option<Sender<BookEvent>>,
+    trade_tape: VecDeque<(String, Side, f64, f64)>,
+    trade_tape_capacity: usize,
+    imbalance_history: VecDeque<(String, f64)>,
+    imbalance_history_capacity: usize,
+    imbalance_history_depth: usize,
+    mid_history: VecDeque<(DateTime<Utc>, f64)>,
+    top_of_book: (Option<f64>, Option<f64>),
+    spread_stats: SpreadStats,
+    pending: HashMap<String, Order>,
+    check_interval: Option<usize>,
+    halted: bool,
+    level_ttl_secs: Option<f64>,
+    is_valid: bool,
+    last_sequence: Option<u64>,
+    max_depth_per_side: Option<usize>,
+    linger_empty_levels: bool,
+    generation: u64,
+    empty_since: HashMap<(Side, u64), u64>,
+    pressure_ema: Option<f64>,
+    pressure_ema_alpha: f64,
+    pressure_depth: usize,
+    filled_count: usize,
+    canceled_count: usize,
+    total_adds: usize,
+    total_cancels: usize,
+    total_updates: usize,
+    checksum_failures: usize,
+    max_checksum_failures: usize,
+    bbo_only: bool,
+    levels_cache: Mutex<(Option<Arc<Vec<(f64, f64, f64)>>>, Option<Arc<Vec<(f64, f64, f64)>>>)>,
 }
 
 /// OrderStack is a FIFO stack
@@ -49,13 +97,46 @@ pub struct Order {
     pub price: f64,
     #[pyo3(get, set)]
     pub size: f64,
-    pub timestamp: String,
+    timestamp: String,
+    /// Size not shown on the book, e.g. the reserve of an iceberg order
+    #[pyo3(get, set)]
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub hidden_size: f64,
+    /// Reason sent with a done message, "filled" or "canceled", read by Submit::RemoveWithReason
+    #[pyo3(get, set)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Client metadata, e.g. a JSON string of strategy tags, carried through the book untouched
+    #[pyo3(get, set)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<String>,
+    /// Timestamp of the latest update or fill applied to the order, None until one arrives
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_update: Option<String>,
+    #[serde(skip)]
+    parsed_time: ParsedTime,
+}
+
+/// An Order's timestamp as a DateTime, parsed on first use and reset by set_timestamp.
+/// Comparisons ignore it, so equal orders stay equal whether or not their time was read.
+#[derive(Clone, Default)]
+struct ParsedTime(OnceLock<Option<DateTime<Utc>>>);
+
+impl PartialEq for ParsedTime {
+    fn eq(&self, _other: &Self) -> bool { true }
+}
+
+impl std::fmt::Debug for ParsedTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ParsedTime")
+    }
 }
 
 /// Enum for differentiating between bids and asks.
 /// Embedded integer exists solely for PyO3 support.
 #[pyclass]
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Side {
     Bids,
     Asks,
@@ -63,16 +144,173 @@ pub enum Side {
 
 enum SubmitRust {
     Insert { order: Order },
-    Remove { uid: String },
-    Update { uid: String, new_size: f64 },
+    Remove { uid: String, reason: Option<RemovalReason> },
+    Update { uid: String, new_size: f64, timestamp: String },
+    Fill { uid: String, fill_qty: f64 },
+    Move { uid: String, new_price: f64 },
 }
 
 #[pyclass]
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize)]
 pub enum Submit {
     Insert,
     Remove,
-    Update
+    RemoveWithReason,
+    Update,
+    Fill,
+    Move,
+}
+
+/// Why an order left the book, as reported by a done message
+#[derive(Clone, Debug, PartialEq)]
+enum RemovalReason {
+    Filled,
+    Canceled,
+}
+
+/// Error recorded in error_msgs while the book is invalidated by a sequence gap
+const INVALIDATED_MSG: &str = "book invalidated, awaiting resync";
+
+/// Schema version of the MessagePack snapshot, bumped whenever the snapshot layout changes
+const SNAPSHOT_MSGPACK_VERSION: u8 = 2;
+
+/// Oldest snapshot version still readable. Version 1 predates the rejected and activity
+/// counters, which restore as zero.
+const MIN_SNAPSHOT_MSGPACK_VERSION: u8 = 1;
+
+/// Tolerance within which the best bid and best ask count as equal, i.e. a locked market
+const LOCKED_EPSILON: f64 = 1e-9;
+
+/// Levels per side covered by level_crc32
+const CHECKSUM_DEPTH: usize = 25;
+
+/// Most mid-price samples kept for realized_vol
+const MID_HISTORY_CAPACITY: usize = 10_000;
+
+/// Seconds in a year of 365.25 days, for annualizing volatility
+const SECONDS_PER_YEAR: f64 = 31_557_600.0;
+
+/// Error raised when an update can't be applied to the orderbook
+#[derive(Clone, Debug, PartialEq)]
+pub enum OrderbookError {
+    InvalidPrice { uid: String, price: f64 },
+    InvalidSize { uid: String, size: f64 },
+    Halted,
+    SequenceGap { expected: u64, received: u64 },
+    Invalidated,
+}
+
+impl std::fmt::Display for OrderbookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrderbookError::InvalidPrice { uid, price } => write!(f, "invalid price {} for order {}", price, uid),
+            OrderbookError::InvalidSize { uid, size } => write!(f, "invalid size {} for order {}", size, uid),
+            OrderbookError::Halted => write!(f, "orderbook halted after a failed check"),
+            OrderbookError::SequenceGap { expected, received } => {
+                write!(f, "sequence gap, expected {} but received {}", expected, received)
+            },
+            OrderbookError::Invalidated => write!(f, "{}", INVALIDATED_MSG),
+        }
+    }
+}
+
+impl std::error::Error for OrderbookError {}
+
+impl From<OrderbookError> for PyErr {
+    fn from(e: OrderbookError) -> Self {
+        match e {
+            OrderbookError::Halted
+            | OrderbookError::SequenceGap { .. }
+            | OrderbookError::Invalidated => PyRuntimeError::new_err(format!("orderbook.process error: {}", e)),
+            _ => PyValueError::new_err(format!("orderbook.process error: {}", e)),
+        }
+    }
+}
+
+/// Enum for choosing how a matched quantity is allocated among the orders at a price level
+#[pyclass]
+#[derive(Clone, Debug, PartialEq)]
+pub enum MatchPolicy {
+    /// Orders are filled in full, in time priority
+    Fifo,
+    /// Every order at the level is filled in proportion to its size
+    ProRata,
+}
+
+/// Enum for choosing whether sizes are quoted in the base currency or as quote-currency notional
+#[pyclass]
+#[derive(Clone, Debug, PartialEq)]
+pub enum SizeUnit {
+    Base,
+    Quote,
+}
+
+/// Enum for choosing how far from the touch a new order may be before it's rejected as an outlier
+#[pyclass]
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutlierMode {
+    /// Reject bids at or below the best bid / outlier_factor and asks at or above the best ask * outlier_factor
+    Multiplicative,
+    /// Reject orders at least outlier_abs_tol away from the touch
+    Absolute,
+    /// Reject orders that fail either check
+    Both,
+}
+
+/// Event describing the effect a processed update had on the book.
+/// Updated carries the order's new size; Removed carries the order as it was before removal.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BookEvent {
+    Inserted { uid: String, side: Side, price: f64, size: f64 },
+    Removed { uid: String, side: Side, price: f64, size: f64 },
+    Updated { uid: String, side: Side, price: f64, size: f64 },
+    OutlierRejected { uid: String, side: Side, price: f64, size: f64 },
+}
+
+/// Running count, sum, min and max of observed spreads
+struct SpreadStats {
+    count: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+/// Serializable state of a LimitOrderbook: every resting order in book order, plus the
+/// counters that monitoring expects to stay monotonic across a reload.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct OrderbookSnapshot {
+    orders: Vec<Order>,
+    items_processed: usize,
+    outliers: usize,
+    #[serde(default)]
+    rejected: usize,
+    #[serde(default)]
+    filled_count: usize,
+    #[serde(default)]
+    canceled_count: usize,
+    #[serde(default)]
+    total_adds: usize,
+    #[serde(default)]
+    total_cancels: usize,
+    #[serde(default)]
+    total_updates: usize,
+}
+
+/// One line of an NDJSON update stream: an order's fields plus the action to apply, e.g.
+/// {"uid":"0","side":"Bids","price":100.0,"size":1.0,"timestamp":"...","action":"Insert"}
+#[derive(Debug, Deserialize)]
+struct NdjsonUpdate {
+    #[serde(flatten)]
+    order: Order,
+    action: Submit,
+}
+
+/// Best levels per side as [price, size] pairs in book order, for depth-ladder feeds
+#[derive(Debug, Serialize, Deserialize)]
+struct DepthLadder {
+    bids: Vec<(f64, f64)>,
+    asks: Vec<(f64, f64)>,
+    ts: String,
 }
 
 #[pymethods]
@@ -90,12 +328,79 @@ impl LimitOrderbook {
             avl_tree_size_display_cutoff: 1000,
             timestamp: Utc::now().format("%Y-%m-%dT%H:%M:%S.%6fZ").to_string(),
             outlier_factor: 2.0,
+            outlier_mode: OutlierMode::Multiplicative,
+            outlier_abs_tol: 0.0,
             bid_cutoff: 0.0,
             ask_cutoff: 0.0,
             outliers: 0,
+            max_order_size: None,
+            rejected: 0,
+            last_bid_update: None,
+            last_ask_update: None,
+            event_sink: None,
+            trade_tape: VecDeque::new(),
+            trade_tape_capacity: 1000,
+            imbalance_history: VecDeque::new(),
+            imbalance_history_capacity: 1000,
+            imbalance_history_depth: 5,
+            mid_history: VecDeque::new(),
+            top_of_book: (None, None),
+            spread_stats: SpreadStats::new(),
+            pending: HashMap::new(),
+            check_interval: None,
+            halted: false,
+            level_ttl_secs: None,
+            is_valid: true,
+            last_sequence: None,
+            max_depth_per_side: None,
+            linger_empty_levels: false,
+            generation: 0,
+            empty_since: HashMap::new(),
+            pressure_ema: None,
+            pressure_ema_alpha: 0.1,
+            pressure_depth: 5,
+            filled_count: 0,
+            canceled_count: 0,
+            total_adds: 0,
+            total_cancels: 0,
+            total_updates: 0,
+            checksum_failures: 0,
+            max_checksum_failures: 3,
+            bbo_only: false,
+            levels_cache: Mutex::new((None, None)),
         }
     }
 
+    /// Create a limit orderbook with order_map pre-sized for expected_orders, avoiding rehashing
+    /// while a burst of inserts fills the book. The AVL trees allocate nodes individually, so
+    /// only order_map is reserved. The tree display cutoff is raised to expected_orders, so a
+    /// book of the expected size can still be displayed; otherwise the book is identical to
+    /// one from new().
+    #[staticmethod]
+    pub fn with_capacity(expected_orders: usize) -> Self {
+        let mut orderbook = Self::new();
+        orderbook.order_map = HashMap::with_capacity(expected_orders);
+        orderbook.avl_tree_size_display_cutoff = orderbook.avl_tree_size_display_cutoff.max(expected_orders);
+        orderbook
+    }
+
+    /// Create a limit orderbook that only keeps the best level of each side, for consumers
+    /// that only need the BBO. Orders on worse levels are discarded as they arrive, so the
+    /// book stays tiny and levels() returns at most one level per side.
+    ///
+    /// This is lossy: once the best level empties, the side stays empty until a new order
+    /// arrives, rather than falling back to the next level a full book would have kept.
+    #[staticmethod]
+    pub fn with_bbo_only() -> Self {
+        let mut orderbook = Self::new();
+        orderbook.bbo_only = true;
+        orderbook
+    }
+
+    #[getter(bbo_only)]
+    /// Returns true if the book only keeps the best level of each side
+    pub fn bbo_only(&self) -> bool { self.bbo_only }
+
     #[getter(items_processed)]
     /// Returns the count of items processed by the orderbook
     pub fn items_processed(&self) -> usize { self.items_processed }
@@ -104,26 +409,220 @@ impl LimitOrderbook {
     /// Returns the count of items ignored by the orderbook due to being an outlier
     pub fn outliers(&self) -> usize { self.outliers }
 
+    #[getter(rejected)]
+    /// Returns the count of inserts rejected for exceeding max_order_size
+    pub fn rejected(&self) -> usize { self.rejected }
+
+    #[getter(filled_count)]
+    /// Returns the count of orders removed with reason "filled"
+    pub fn filled_count(&self) -> usize { self.filled_count }
+
+    #[getter(canceled_count)]
+    /// Returns the count of orders removed with reason "canceled" or an unknown reason
+    pub fn canceled_count(&self) -> usize { self.canceled_count }
+
+    #[getter(total_adds)]
+    /// Returns the count of orders added to the book. Inserts rejected as outliers or
+    /// oversized aren't counted.
+    pub fn total_adds(&self) -> usize { self.total_adds }
+
+    #[getter(total_cancels)]
+    /// Returns the count of live orders removed by a cancel, i.e. removes without a filled reason
+    pub fn total_cancels(&self) -> usize { self.total_cancels }
+
+    #[getter(total_updates)]
+    /// Returns the count of updates applied to live orders. Updates for uids the book
+    /// doesn't hold aren't counted.
+    pub fn total_updates(&self) -> usize { self.total_updates }
+
+    /// Return (cancels / adds, updates / adds) over the processed messages, a measure of
+    /// how much posted liquidity is withdrawn or amended. Both are 0 before the first add.
+    pub fn flow_ratios(&self) -> (f64, f64) {
+        if self.total_adds == 0 {
+            return (0.0, 0.0)
+        }
+        let adds = self.total_adds as f64;
+        (self.total_cancels as f64 / adds, self.total_updates as f64 / adds)
+    }
+
     #[getter(timestamp)]
     /// Returns the timestamp of the latest order processed by the orderbook
     pub fn timestamp(&self) -> String { self.timestamp.clone() }
 
+    #[getter(last_bid_update)]
+    /// Returns the timestamp of the latest order that modified the bids
+    pub fn last_bid_update(&self) -> Option<String> { self.last_bid_update.clone() }
+
+    #[getter(last_ask_update)]
+    /// Returns the timestamp of the latest order that modified the asks
+    pub fn last_ask_update(&self) -> Option<String> { self.last_ask_update.clone() }
+
     #[getter(best_ask)]
     /// Return the lowest asking price in the book
     pub fn best_ask(&self) -> Option<f64> {
-        Some(self.asks.iter().next()?.key.clone())
+        Some(self.side_iter(&Side::Asks).next()?.key)
     }
 
     #[getter(best_bid)]
     /// Return the highest bidding price in the book
     pub fn best_bid(&self) -> Option<f64> {
-        Some(self.bids.iter().next_back()?.key.clone())
+        Some(self.side_iter(&Side::Bids).next()?.key)
+    }
+
+    #[getter(mid_price)]
+    /// Return the midpoint between the best bid and best ask
+    pub fn mid_price(&self) -> Option<f64> {
+        Some((self.best_bid()? + self.best_ask()?) / 2.0)
+    }
+
+    /// Return the highest bidding price, or an error rather than None while the book is
+    /// invalidated by a sequence gap or halted by a failed check. clear() makes the book usable again.
+    pub fn try_best_bid(&self) -> PyResult<Option<f64>> {
+        self.ensure_usable()?;
+        Ok(self.best_bid())
+    }
+
+    /// Return the lowest asking price, or an error rather than None while the book is
+    /// invalidated by a sequence gap or halted by a failed check. clear() makes the book usable again.
+    pub fn try_best_ask(&self) -> PyResult<Option<f64>> {
+        self.ensure_usable()?;
+        Ok(self.best_ask())
+    }
+
+    /// Return the midpoint between best bid and best ask, or an error rather than None while
+    /// the book is invalidated by a sequence gap or halted by a failed check. clear() makes the book
+    /// usable again.
+    pub fn try_mid_price(&self) -> PyResult<Option<f64>> {
+        self.ensure_usable()?;
+        Ok(self.mid_price())
+    }
+
+    #[getter(is_locked)]
+    /// Return true if the best bid equals the best ask, within LOCKED_EPSILON
+    pub fn is_locked(&self) -> bool {
+        match (self.best_bid(), self.best_ask()) {
+            (Some(best_bid), Some(best_ask)) => (best_bid - best_ask).abs() <= LOCKED_EPSILON,
+            _ => false,
+        }
+    }
+
+    #[getter(is_crossed)]
+    /// Return true if the best bid is above the best ask, beyond LOCKED_EPSILON
+    pub fn is_crossed(&self) -> bool {
+        match (self.best_bid(), self.best_ask()) {
+            (Some(best_bid), Some(best_ask)) => best_bid - best_ask > LOCKED_EPSILON,
+            _ => false,
+        }
+    }
+
+    #[getter(spread_bps)]
+    /// Return the spread between best ask and best bid in basis points of the mid price,
+    /// or None if the book is one-sided or crossed
+    pub fn spread_bps(&self) -> Option<f64> {
+        let (best_bid, best_ask) = (self.best_bid()?, self.best_ask()?);
+        if best_ask <= best_bid {
+            return None
+        }
+        Some((best_ask - best_bid) / self.mid_price()? * 10_000.0)
+    }
+
+    #[getter(microprice)]
+    /// Return the top-of-book size-weighted price, which leans towards the side with less
+    /// visible size at the touch: (bid * ask_size + ask * bid_size) / (bid_size + ask_size)
+    pub fn microprice(&self) -> Option<f64> {
+        let best_bid = self.side_iter(&Side::Bids).next()?;
+        let best_ask = self.side_iter(&Side::Asks).next()?;
+        let (bid_size, ask_size) = (best_bid.value.size(), best_ask.value.size());
+        Some((best_bid.key * ask_size + best_ask.key * bid_size) / (bid_size + ask_size))
+    }
+
+    /// Return (bid size - ask size) / (bid size + ask size) over the best depth levels of each
+    /// side, in [-1, 1] with positive values meaning more resting bids, or None if one-sided
+    pub fn imbalance(&self, depth: usize) -> Option<f64> {
+        let bid_size: f64 = self.side_iter(&Side::Bids).take(depth).map(|node| node.value.size()).sum();
+        let ask_size: f64 = self.side_iter(&Side::Asks).take(depth).map(|node| node.value.size()).sum();
+        if bid_size <= 0.0 || ask_size <= 0.0 {
+            return None
+        }
+        Some((bid_size - ask_size) / (bid_size + ask_size))
+    }
+
+    /// Return the Gini coefficient of resting order sizes on a side, in [0, 1): 0 when every
+    /// order is the same size, approaching 1 as a few large orders dominate the liquidity.
+    /// None with fewer than two orders or no resting size.
+    pub fn size_gini(&self, side: Side) -> Option<f64> {
+        let mut sizes: Vec<f64> = self.side_iter(&side)
+            .flat_map(|node| node.value.0.iter().map(|order| order.size))
+            .collect();
+        let total: f64 = sizes.iter().sum();
+        if sizes.len() < 2 || total <= 0.0 {
+            return None
+        }
+        sizes.sort_by(|a, b| a.total_cmp(b));
+        let n = sizes.len() as f64;
+        let weighted: f64 = sizes.iter().enumerate().map(|(i, size)| (i + 1) as f64 * size).sum();
+        Some(2.0 * weighted / (n * total) - (n + 1.0) / n)
+    }
+
+    /// Return the annualized realized volatility of the mid price over the last window_secs
+    /// before the latest sample, i.e. sqrt(sum of squared log returns * seconds per year / elapsed
+    /// seconds). Mids are sampled with their update's timestamp whenever the top of book changes.
+    /// None with fewer than two samples in the window or no elapsed time between them.
+    /// A window reaching back further than a DateTime can represent covers the whole history.
+    pub fn realized_vol(&self, window_secs: u64) -> Option<f64> {
+        let (latest, _) = self.mid_history.back()?;
+        // chrono durations hold at most i64::MAX milliseconds; None means unbounded
+        let start = i64::try_from(window_secs).ok()
+            .filter(|secs| *secs <= i64::MAX / 1000)
+            .and_then(|secs| latest.checked_sub_signed(chrono::Duration::seconds(secs)));
+        let samples: Vec<&(DateTime<Utc>, f64)> = self.mid_history.iter()
+            .skip_while(|(time, _)| start.is_some_and(|start| *time < start))
+            .collect();
+        if samples.len() < 2 {
+            return None
+        }
+        let elapsed = (*latest - samples[0].0).num_microseconds()? as f64 / 1e6;
+        if elapsed <= 0.0 {
+            return None
+        }
+        let sum_squares: f64 = samples.windows(2).map(|pair| (pair[1].1 / pair[0].1).ln().powi(2)).sum();
+        Some((sum_squares * SECONDS_PER_YEAR / elapsed).sqrt())
+    }
+
+    #[getter(pressure_ema)]
+    /// Return the exponential moving average of imbalance, sampled on each top-of-book change
+    /// while the book is two-sided, or None before the first sample
+    pub fn pressure_ema(&self) -> Option<f64> { self.pressure_ema }
+
+    /// Return alpha * microprice + (1 - alpha) * mid price, with alpha clamped to [0, 1],
+    /// or None if the book lacks a two-sided top
+    pub fn weighted_mid(&self, alpha: f64) -> Option<f64> {
+        let alpha = alpha.clamp(0.0, 1.0);
+        Some(alpha * self.microprice()? + (1.0 - alpha) * self.mid_price()?)
     }
 
     #[getter(node_count)]
-    /// Return count of unique price levels
+    /// Return count of unique price levels, not counting lingering empty levels
     pub fn node_count(&self) -> usize {
-        self.bids.len() + self.asks.len()
+        self.bids.len() + self.asks.len() - self.empty_since.len()
+    }
+
+    #[getter(bid_tree_height)]
+    /// Return height of the bids AVL-tree
+    pub fn bid_tree_height(&self) -> isize {
+        self.bids.tree_height()
+    }
+
+    #[getter(ask_tree_height)]
+    /// Return height of the asks AVL-tree
+    pub fn ask_tree_height(&self) -> isize {
+        self.asks.tree_height()
+    }
+
+    #[getter(is_balanced)]
+    /// Return true if both AVL-trees are balanced
+    pub fn is_balanced(&self) -> bool {
+        self.bids.is_balanced() && self.asks.is_balanced()
     }
 
     #[getter(len)]
@@ -132,6 +631,37 @@ impl LimitOrderbook {
         self.len
     }
 
+    /// Return true if any bids are resting. Lingering empty levels don't count.
+    pub fn has_bids(&self) -> bool {
+        self.side_iter(&Side::Bids).next().is_some()
+    }
+
+    /// Return true if any asks are resting. Lingering empty levels don't count.
+    pub fn has_asks(&self) -> bool {
+        self.side_iter(&Side::Asks).next().is_some()
+    }
+
+    /// Return true if the book holds no orders, i.e. len is 0. Like len, this counts the
+    /// stored orders, so it's false for an invalidated book that still holds some.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn __len__(&self) -> usize {
+        self.len
+    }
+
+    pub fn __contains__(&self, uid: String) -> bool {
+        self.has(uid)
+    }
+
+    pub fn __repr__(&self) -> PyResult<String> {
+        Ok(format!(
+            "LimitOrderbook(orders={}, bid_levels={}, ask_levels={}, best_bid={:?}, best_ask={:?})",
+            self.len, self.bids.len(), self.asks.len(), self.best_bid(), self.best_ask()
+        ))
+    }
+
     #[getter(error_msgs)]
     /// Return any error_msgs resulting from unsuccessful checks
     pub fn error_msgs(&self) -> HashSet<String> { self.error_msgs.clone() }
@@ -141,692 +671,4463 @@ impl LimitOrderbook {
         self.display_trees_aux(side, true);
     }
 
+    /// Return the shape of the AVL-tree for bids or asks as adjacency data, for visualization.
+    ///
+    /// Each entry is (price, left child price, right child price, balance factor), in-order.
+    pub fn tree_structure(&self, side: Side) -> Vec<(f64, Option<f64>, Option<f64>, isize)> {
+        match side {
+            Side::Bids => self.bids.structure(),
+            Side::Asks => self.asks.structure(),
+        }
+    }
+
     /// Return true if order exists in tree
     pub fn has(&self, order_uid: String) -> bool {
+        if !self.is_valid { return false }
         if let Some(_) = self.get_order(order_uid) { true } else { false }
     }
 
+    /// Return the side and price an order rests at, without fetching the whole order
+    pub fn locate(&self, uid: String) -> Option<(Side, f64)> {
+        if !self.is_valid { return None }
+        self.order_map.get(&uid).cloned()
+    }
+
+    /// Return the aggregate size of the orders queued ahead of an order at its price level,
+    /// or None if the order isn't in the book
+    pub fn queue_ahead(&self, uid: String) -> Option<f64> {
+        if !self.is_valid { return None }
+        let (side, price) = self.order_map.get(&uid)?;
+        let order_stack = match side {
+            Side::Bids => self.bids.get(price)?,
+            Side::Asks => self.asks.get(price)?,
+        };
+        let mut ahead = 0.0;
+        for order in order_stack.0.iter() {
+            if order.uid == uid {
+                return Some(ahead)
+            }
+            ahead += order.size;
+        }
+        None
+    }
+
+    /// Return the n oldest resting orders by timestamp, oldest first.
+    ///
+    /// Orders whose timestamp can't be parsed are excluded and reported in error_msgs.
+    pub fn oldest_orders(&mut self, n: usize) -> Vec<Order> {
+        if !self.is_valid { return Vec::new() }
+        let mut unparseable = 0;
+        let mut aged: Vec<(DateTime<Utc>, &Order)> = self.iter()
+            .filter_map(|order| match order.time() {
+                Some(timestamp) => Some((timestamp, order)),
+                None => { unparseable += 1; None },
+            })
+            .collect();
+        aged.sort_by_key(|(timestamp, _)| *timestamp);
+        let oldest = aged.into_iter().take(n).map(|(_, order)| order.clone()).collect();
+
+        if unparseable > 0 {
+            self.error_msgs.insert(format!("{} orders have unparseable timestamps", unparseable));
+        }
+        oldest
+    }
+
     /// Return vector of (f64, f64, f64) tuples representing current snapshot of price, marginal
     /// order size (aggregate order size at each level)
-    /// and cumulative depth (integral of price * order size)
-    fn levels(&self, side: Side) -> Vec<(f64, f64, f64)> {
-        match side {
-            Side::Bids => {
-                self.bids.iter().rev().scan(0.0, |cumsum, node| Option::from({
-                    *cumsum += node.key * node.value.size();
-                    (node.key, node.value.size(), cumsum.clone())
-                })).collect()
-            },
-            Side::Asks => {
-                self.asks.iter().scan(0.0, |cumsum, node| Option::from({
-                    *cumsum += node.key * node.value.size();
-                    (node.key, node.value.size(), cumsum.clone())
-                })).collect()
-                // println!("rust ask levels \n {:?}", result);
-                // result
-            },
+    /// and cumulative depth (integral of price * order size).
+    /// With SizeUnit::Quote, order size is given as notional (price * size); unit defaults to Base.
+    ///
+    /// Levels are read from the cache behind cached_levels, which spares repeated calls the
+    /// tree walk, but each call still copies the whole vector, as PyO3 needs an owned one to
+    /// build the Python list. Rust callers that only read the levels should use cached_levels.
+    pub fn levels(&self, side: Side, unit: Option<SizeUnit>) -> Vec<(f64, f64, f64)> {
+        let levels = self.cached_levels(side);
+        match unit.unwrap_or(SizeUnit::Base) {
+            SizeUnit::Base => levels.as_ref().clone(),
+            SizeUnit::Quote => levels.iter()
+                .map(|&(price, size, cumulative)| (price, price * size, cumulative))
+                .collect(),
         }
     }
 
-    /// Process a given order
-    pub fn process(&mut self, order: Order, action: Submit) {
-        let action = Self::parse_query(order, action);
-        match action {
-            Ok(SubmitRust::Insert { order }) => {
-                self.insert(order);
-            },
-            Ok(SubmitRust::Remove { uid }) => {
-                self.remove(uid);
-                // Ok("Removed")
-            },
-            Ok(SubmitRust::Update { uid, new_size }) => {
-                self.update(uid, new_size);
-                // Ok("Updated")
-            },
-            Err(e) => {
-                panic!("orderbook.process error on {}", e);
+    /// Return (bids, asks) levels in a single call, each in book order as in levels()
+    pub fn both_levels(&self) -> (Vec<(f64, f64, f64)>, Vec<(f64, f64, f64)>) {
+        (self.levels(Side::Bids, None), self.levels(Side::Asks, None))
+    }
+
+    /// Return levels() as parallel (prices, sizes, cumulative depth) vectors in book order,
+    /// ready to become numpy arrays or DataFrame columns
+    pub fn levels_columns(&self, side: Side) -> (Vec<f64>, Vec<f64>, Vec<f64>) {
+        let mut columns = (Vec::new(), Vec::new(), Vec::new());
+        for (price, size, cumulative) in self.levels_iter(side) {
+            columns.0.push(price);
+            columns.1.push(size);
+            columns.2.push(cumulative);
+        }
+        columns
+    }
+
+    /// Return true if levels() on a side is strictly ordered: bids descending, asks ascending.
+    /// A false result means the tree's in-order traversal is out of key order, e.g. after a
+    /// bad rotation corrupted the unsafe AVL links, and each violation is logged.
+    pub fn levels_are_ordered(&self, side: Side) -> bool {
+        let levels = self.levels(side.clone(), None);
+        let mut ordered = true;
+        for pair in levels.windows(2) {
+            let in_order = match side {
+                Side::Bids => pair[0].0 > pair[1].0,
+                Side::Asks => pair[0].0 < pair[1].0,
+            };
+            if !in_order {
+                println!("Warning: {:?} levels out of order: {} followed by {}", side, pair[0].0, pair[1].0);
+                ordered = false;
             }
         }
-        self.items_processed += 1;
+        ordered
     }
 
-    /// Return some notes regarding what has been processed so far
-    pub fn log_notes(&self) -> String {
-        let mut notes_vec: Vec<String> = Vec::new();
+    /// Return (total size, level count) of the levels within band_bps basis points of the
+    /// best price on a side. The walk stops at the first level outside the band.
+    /// With SizeUnit::Quote, size is summed as notional; unit defaults to Base.
+    pub fn liquidity_in_band(&self, side: Side, band_bps: f64, unit: Option<SizeUnit>) -> (f64, usize) {
+        let quote = unit == Some(SizeUnit::Quote);
+        let best = match self.side_iter(&side).next() {
+            Some(node) => node.key,
+            None => return (0.0, 0),
+        };
+        let bound = match side {
+            Side::Bids => best * (1.0 - band_bps / 10_000.0),
+            Side::Asks => best * (1.0 + band_bps / 10_000.0),
+        };
+        self.side_iter(&side)
+            .take_while(|node| match side {
+                Side::Bids => node.key >= bound,
+                Side::Asks => node.key <= bound,
+            })
+            .fold((0.0, 0), |(size, count), node| {
+                let level_size = if quote { node.key * node.value.size() } else { node.value.size() };
+                (size + level_size, count + 1)
+            })
+    }
 
-        if self.error_msgs.len() == 0 {
-            notes_vec.push("No errors encountered".to_string());
-        } else {
-            notes_vec.push("******Errors encountered******".to_string());
-            for msg in &self.error_msgs {
-                notes_vec.push(msg.clone());
+    /// Simulate matching qty against the resting orders on a side, best price first,
+    /// without modifying the book. Within a level that's only partly consumed, the policy
+    /// decides which orders are filled. Returns (uid, price, fill qty) for each order hit.
+    pub fn simulate_fill(&self, side: Side, qty: f64, policy: MatchPolicy) -> Vec<(String, f64, f64)> {
+        let mut fills = Vec::new();
+        let mut remaining = qty;
+        for node in self.side_iter(&side) {
+            if remaining <= 0.0 {
+                break
             }
-            notes_vec.push("---------------------------------".to_string());
+            let level_size = node.value.size();
+            let level_fill = remaining.min(level_size);
+            let mut level_remaining = level_fill;
+            for order in node.value.0.iter() {
+                let fill_qty = match policy {
+                    MatchPolicy::Fifo => order.size.min(level_remaining),
+                    MatchPolicy::ProRata => level_fill * order.size / level_size,
+                };
+                if fill_qty <= 0.0 {
+                    continue
+                }
+                fills.push((order.uid.clone(), node.key, fill_qty));
+                level_remaining -= fill_qty;
+            }
+            remaining -= level_fill;
         }
+        fills
+    }
 
-        if self.bids.len() > self.avl_tree_size_display_cutoff {
-            let mut msg = format!("Bids AVL Tree too large to display ({} nodes). ", self.bids.len());
-            msg += &*format!("Increase avl_tree_size_display_cutoff ({}) \
-                in orderbook to display larger trees", self.avl_tree_size_display_cutoff);
-            notes_vec.push(msg);
-        } else {
-            let msg = format!("{} bid levels = {:?}", self.bids.len(), self.levels(Side::Bids));
-            notes_vec.push(msg);
-            notes_vec.extend(self.display_trees_aux(Side::Bids, false));
+    /// Return the resting orders an incoming order would cross, as (uid, fillable size) in
+    /// match order, without modifying the book: asks priced at or below a buy's price, or
+    /// bids at or above a sell's, in price-time priority until the incoming size is used up.
+    /// Empty for an order that isn't marketable.
+    pub fn would_match(&self, order: &Order) -> Vec<(String, f64)> {
+        let crosses = |price: f64| match order.side {
+            Side::Bids => price <= order.price,
+            Side::Asks => price >= order.price,
+        };
+        let mut matches = Vec::new();
+        let mut remaining = order.size;
+        for node in self.side_iter(&order.side.opposite()).take_while(|node| crosses(node.key)) {
+            for resting in node.value.0.iter() {
+                if remaining <= 0.0 {
+                    return matches
+                }
+                let fillable = resting.size.min(remaining);
+                if fillable > 0.0 {
+                    matches.push((resting.uid.clone(), fillable));
+                    remaining -= fillable;
+                }
+            }
         }
+        matches
+    }
 
-        if self.asks.len() > self.avl_tree_size_display_cutoff {
-            let mut msg = format!("Asks AVL Tree too large to display ({} nodes). ", self.asks.len());
-            msg += &*format!("Increase avl_tree_size_display_cutoff ({}) \
-                in orderbook to display larger trees", self.avl_tree_size_display_cutoff);
-            notes_vec.push(msg);
-        } else {
-            let msg = format!("{} ask levels = {:?}", self.asks.len(), self.levels(Side::Asks));
-            notes_vec.push(msg);
-            notes_vec.extend(self.display_trees_aux(Side::Asks, false));
+    /// Return the volume-weighted average price of taking quantity from a side of the book,
+    /// best price first, or None if the side can't supply the quantity
+    pub fn vwap(&self, side: Side, quantity: f64) -> Option<f64> {
+        if quantity.is_nan() || quantity <= 0.0 {
+            return None
+        }
+        let mut remaining = quantity;
+        let mut notional = 0.0;
+        for node in self.side_iter(&side) {
+            let taken = remaining.min(node.value.size());
+            notional += taken * node.key;
+            remaining -= taken;
+            if remaining <= 0.0 {
+                return Some(notional / quantity)
+            }
         }
+        None
+    }
 
-        notes_vec.push(format!("Items processed by orderbook: {}", self.items_processed));
-        notes_vec.push(format!("Outliers ignored by orderbook: {}", self.outliers));
-        notes_vec.join("\n")
+    /// Return the price of the last level touched when taking quantity from a side of the book,
+    /// best price first, i.e. the marginal (worst) price paid, or None if the side can't supply
+    /// the quantity. Complements vwap, which gives the average price.
+    pub fn sweep_price(&self, side: Side, quantity: f64) -> Option<f64> {
+        if quantity.is_nan() || quantity <= 0.0 {
+            return None
+        }
+        let mut remaining = quantity;
+        for node in self.side_iter(&side) {
+            remaining -= node.value.size();
+            if remaining <= 0.0 {
+                return Some(node.key)
+            }
+        }
+        None
     }
 
-    /// Perform checks
-    pub fn check(&mut self) {
-        let mut error_msgs: HashSet<String> = HashSet::new();
-        error_msgs = self.bids.check(error_msgs);
-        error_msgs = self.asks.check(error_msgs);
+    /// Return the effective spread of trading quantity, i.e. the difference between the
+    /// VWAP of buying it from the asks and of selling it into the bids, or None if either
+    /// side can't supply the quantity
+    pub fn effective_spread(&self, quantity: f64) -> Option<f64> {
+        Some(self.vwap(Side::Asks, quantity)? - self.vwap(Side::Bids, quantity)?)
+    }
 
-        if !self.bids.is_balanced() {
-            error_msgs.insert("Bids are not balanced!".to_string());
-        }
-        if !self.asks.is_balanced() {
-            error_msgs.insert("Asks are not balanced!".to_string());
+    /// Return true if taking quantity from a side of the book would be filled in full,
+    /// e.g. Side::Asks for a buy. Like vwap and sweep_price, side is the resting side.
+    pub fn can_fill(&self, side: Side, quantity: f64) -> bool {
+        if quantity <= 0.0 {
+            return true
         }
-        self.error_msgs = error_msgs;
+        let filled: f64 = self.simulate_fill(side, quantity, MatchPolicy::Fifo).iter()
+            .map(|(_, _, fill_qty)| fill_qty)
+            .sum();
+        filled >= quantity
     }
-}
 
-impl LimitOrderbook {
+    /// Return the size-weighted average price of every resting order on both sides,
+    /// or None if the book is empty
+    pub fn resting_vwap(&self) -> Option<f64> {
+        if !self.is_valid { return None }
+        let (notional, size) = self.iter()
+            .fold((0.0, 0.0), |(notional, size), order| (notional + order.price * order.size, size + order.size));
+        if size > 0.0 { Some(notional / size) } else { None }
+    }
 
-    fn parse_query(order: Order, action: Submit) -> Result<SubmitRust, String> {
-        match action {
-            Submit::Insert => Ok(SubmitRust::Insert { order }),
-            Submit::Remove => Ok(SubmitRust::Remove { uid: order.uid }),
-            Submit::Update => Ok(SubmitRust::Update { uid: order.uid, new_size: order.size }),
+    /// Return levels() counting only visible size, i.e. excluding the hidden size of
+    /// iceberg orders. Identical to levels() when no order has hidden size.
+    pub fn levels_visible(&self, side: Side) -> Vec<(f64, f64, f64)> {
+        self.side_iter(&side).scan(0.0, |cumsum, node| {
+            *cumsum += node.key * node.value.visible_size();
+            Some((node.key, node.value.visible_size(), *cumsum))
+        }).collect()
+    }
+
+    /// Return (price, aggregate size) for the levels ranked skip to skip + take from the
+    /// touch, in book order, e.g. skip 50 and take 50 for levels 50 through 99. The tree
+    /// keeps no subtree counts, so reaching the window walks the skipped levels.
+    pub fn levels_page(&self, side: Side, skip: usize, take: usize) -> Vec<(f64, f64)> {
+        self.l2_iter(side).skip(skip).take(take).collect()
+    }
+
+    /// Return vector of (f64, f64) tuples of price and cumulative notional, i.e. the running
+    /// sum of price * order size from the top of book down to and including each level.
+    ///
+    /// This is the same quantity as the third element of levels(). It is distinct from
+    /// cumulative size, the running sum of order size alone, which counts units of the base
+    /// asset rather than their value in the quote asset.
+    pub fn cumulative_notional(&self, side: Side) -> Vec<(f64, f64)> {
+        self.levels_iter(side).map(|(price, _, notional)| (price, notional)).collect()
+    }
+
+    /// Return (price, order count) for the best n levels of a side, in book order
+    pub fn order_counts_top(&self, side: Side, n: usize) -> Vec<(f64, usize)> {
+        self.side_iter(&side).take(n).map(|node| (node.key, node.value.len())).collect()
+    }
+
+    /// Return the orders resting at the best price level, in FIFO order
+    pub fn top_level_orders(&self, side: Side) -> Vec<Order> {
+        match self.side_iter(&side).next() {
+            Some(node) => node.value.0.iter().cloned().collect(),
+            None => Vec::new(),
         }
     }
 
-    /// Get reference to an order in the limit orderbook by its order_uid
-    pub fn get_order(&self, order_uid: String) -> Option<&Order> {
-        if let Some((side, key)) = self.order_map.get(&*order_uid) {
-            let order_stack = match side {
-                Side::Bids => self.bids.get(key).unwrap(),
-                Side::Asks => self.asks.get(key).unwrap(),
-            };
-            let order_ref = order_stack.get_order(order_uid).unwrap();
-            Some(order_ref)
-        } else {
-            // println!("Order uid {} not found in order_map", order_uid);
-            None
+    /// Return every order resting at a price level, in FIFO order, or an empty vector
+    /// if the side has no level at that price
+    pub fn orders_at_price(&self, side: Side, price: f64) -> Vec<Order> {
+        if !self.is_valid { return Vec::new() }
+        let tree = match side {
+            Side::Bids => &self.bids,
+            Side::Asks => &self.asks,
+        };
+        match tree.get(&price) {
+            Some(order_stack) => order_stack.0.iter().cloned().collect(),
+            None => Vec::new(),
         }
     }
 
-    /// Get mutable reference to an order in the limit orderbook by its order_uid
-    fn get_order_mut(&mut self, order_uid: String) -> Option<&mut Order> {
-        if let Some((side, key)) = self.order_map.get(&*order_uid) {
-            let order_stack = match side {
-                Side::Bids => self.bids.get_mut(key).unwrap(),
-                Side::Asks => self.asks.get_mut(key).unwrap(),
+    /// Return a crude probability that the level at price is consumed, recent_volume / level
+    /// size clamped to [0, 1], where recent_volume is the volume recently traded against the
+    /// side. Level size includes hidden size. None if the side has no orders at that price.
+    pub fn level_consumption_estimate(&self, side: Side, price: f64, recent_volume: f64) -> Option<f64> {
+        if !self.is_valid { return None }
+        let tree = match side {
+            Side::Bids => &self.bids,
+            Side::Asks => &self.asks,
+        };
+        let size = tree.get(&price).filter(|order_stack| !order_stack.is_empty())?.total_size();
+        Some((recent_volume / size).clamp(0.0, 1.0))
+    }
+
+    /// Return (bids, asks) as maps of price to aggregate size at that price.
+    /// Prices are keyed by their string representation to avoid f64 dict keys.
+    pub fn as_maps(&self) -> (HashMap<String, f64>, HashMap<String, f64>) {
+        let to_map = |side: Side| self.levels_iter(side)
+            .map(|(price, size, _)| (price.to_string(), size))
+            .collect();
+        (to_map(Side::Bids), to_map(Side::Asks))
+    }
+
+    /// Return vector of (f64, f64) tuples representing price levels bucketed to the passed
+    /// tick size, and the aggregate order size in each bucket, in book order.
+    ///
+    /// Asks are floored to their bucket and bids are ceiled, so buckets never cross the spread.
+    pub fn aggregated_levels(&self, side: Side, tick: f64) -> PyResult<Vec<(f64, f64)>> {
+        if tick.is_nan() || tick <= 0.0 {
+            return Err(PyValueError::new_err(format!("tick must be positive, got {}", tick)));
+        }
+        let mut buckets: Vec<(i64, f64)> = Vec::new();
+        let mut add = |bucket: i64, size: f64| {
+            match buckets.last_mut() {
+                Some((last, total)) if *last == bucket => *total += size,
+                _ => buckets.push((bucket, size)),
+            }
+        };
+        for node in self.side_iter(&side) {
+            let bucket = match side {
+                Side::Bids => (node.key / tick).ceil(),
+                Side::Asks => (node.key / tick).floor(),
             };
-            let order_ref = order_stack.get_order_mut(order_uid).unwrap();
-            Some(order_ref)
-        } else {
-            // println!("Order uid {} not found in order_map", order_uid);
-            None
+            add(bucket as i64, node.value.size());
         }
+        Ok(buckets.into_iter().map(|(bucket, size)| (bucket as f64 * tick, size)).collect())
     }
 
-    /// Check if order meets outlier condition.
-    /// If it doesn't, update bid/ask cutoffs.
-    fn handle_outlier(&mut self, order: &Order) -> bool {
-        // best_bid or best_ask being none means tree is empty, so no way to determine if
-        // order has outlier price. Assume it isn't
-        match order.side {
-            Side::Bids => {
-                if self.bids.is_empty() {
-                    self.bid_cutoff = order.price / self.outlier_factor;
-                    false
-                } else if order.price > self.best_bid().unwrap() {
-                    self.bid_cutoff = order.price / self.outlier_factor;
-                    false
-                } else if order.price > self.bid_cutoff {
-                    false
+    /// Return aggregate order size resting between the top of the book and the passed price,
+    /// inclusive. Returns 0.0 if the price is beyond the top of book on the wrong side.
+    pub fn depth_to_price(&self, side: Side, price: f64) -> f64 {
+        self.side_iter(&side)
+            .take_while(|node| match side {
+                Side::Bids => node.key >= price,
+                Side::Asks => node.key <= price,
+            })
+            .fold(0.0, |sum, node| sum + node.value.size())
+    }
+
+    /// Return vector of (f64, f64, f64) tuples representing, for each passed offset in basis
+    /// points, (offset, bid depth, ask depth), where bid depth is the aggregate order size between
+    /// the mid price and mid * (1 - offset / 10000), and ask depth mirrors it above the mid.
+    /// Returns an empty vector if the book has no mid price.
+    pub fn depth_curve(&self, offsets_bps: Vec<f64>) -> Vec<(f64, f64, f64)> {
+        let mid = match self.mid_price() {
+            Some(mid) => mid,
+            None => return Vec::new(),
+        };
+        offsets_bps.into_iter().map(|bps| {
+            let bid_depth = self.depth_to_price(Side::Bids, mid * (1.0 - bps / 10000.0));
+            let ask_depth = self.depth_to_price(Side::Asks, mid * (1.0 + bps / 10000.0));
+            (bps, bid_depth, ask_depth)
+        }).collect()
+    }
+
+    /// Process a given order
+    pub fn process(&mut self, order: Order, action: Submit) -> PyResult<()> {
+        if self.halted {
+            return Err(OrderbookError::Halted.into())
+        }
+        if !self.is_valid {
+            return Err(OrderbookError::Invalidated.into())
+        }
+        let order = self.resolve_pending(order, &action);
+        let timestamp = order.timestamp().to_string();
+        let action = Self::parse_query(order, action)?;
+        let mut touched = self.touched_side(&action);
+        let subject = self.event_subject(&action);
+        let inserting = matches!(action, SubmitRust::Insert { .. });
+        match action {
+            SubmitRust::Insert { order } => {
+                let uid = order.uid.clone();
+                if self.insert(order) {
+                    self.total_adds += 1;
+                    self.pending.remove(&uid);
                 } else {
-                    true
+                    touched = None;
                 }
             },
-            Side::Asks => {
-                if self.asks.is_empty() {
-                    self.ask_cutoff = order.price * self.outlier_factor;
-                    false
-                } else if order.price < self.best_ask().unwrap() {
-                    self.ask_cutoff = order.price * self.outlier_factor;
-                    false
-                } else if order.price < self.ask_cutoff {
-                    false
-                } else {
-                    true
+            SubmitRust::Remove { uid, reason } => {
+                if self.order_map.contains_key(&uid) {
+                    match reason {
+                        Some(RemovalReason::Filled) => self.filled_count += 1,
+                        Some(RemovalReason::Canceled) => {
+                            self.canceled_count += 1;
+                            self.total_cancels += 1;
+                        },
+                        None => self.total_cancels += 1,
+                    }
+                }
+                self.remove(uid);
+                // Ok("Removed")
+            },
+            SubmitRust::Update { uid, new_size, timestamp } => {
+                if self.order_map.contains_key(&uid) {
+                    self.total_updates += 1;
                 }
+                self.update(uid, new_size, Some(timestamp));
+                // Ok("Updated")
+            },
+            SubmitRust::Fill { uid, fill_qty } => {
+                self.fill(uid, fill_qty, timestamp.clone());
+            },
+            SubmitRust::Move { uid, new_price } => {
+                self.move_order(uid, new_price);
+            },
+        }
+        if let Some(side) = &touched {
+            match side {
+                Side::Bids => self.last_bid_update = Some(timestamp.clone()),
+                Side::Asks => self.last_ask_update = Some(timestamp.clone()),
             }
         }
-    }
-
-    /// Inserts an order. Returns true if inserted
-    fn insert(&mut self, order: Order) -> bool {
-        if !self.handle_outlier(&order) {
-            match order.side {
-                Side::Bids => {
-                    self.bids.insert(order.price.clone(), Some(order.clone()));
-                },
-                Side::Asks => self.asks.insert(order.price.clone(), Some(order.clone())),
+        if let Some(order) = subject {
+            let event = if inserting {
+                if touched.is_some() {
+                    BookEvent::Inserted { uid: order.uid, side: order.side, price: order.price, size: order.size }
+                } else {
+                    BookEvent::OutlierRejected { uid: order.uid, side: order.side, price: order.price, size: order.size }
+                }
+            } else if let Some(updated) = self.get_order(order.uid.clone()) {
+                BookEvent::Updated {
+                    uid: updated.uid.clone(), side: updated.side.clone(), price: updated.price, size: updated.size
+                }
+            } else {
+                BookEvent::Removed { uid: order.uid, side: order.side, price: order.price, size: order.size }
             };
-            self.order_map.insert(order.uid, (order.side, order.price));
-            self.len += 1;
-            true
-        } else {
-            self.outliers += 1;
-            false
+            self.emit(event);
         }
-    }
-
-    /// Removes an order
-    fn remove(&mut self, order_uid: String) {
-        if let Some((side, key)) = self.order_map.get(&*order_uid) {
-            match side {
-                Side::Bids => {
-                    let order_stack = self.bids.get_mut(key).unwrap();
-                    order_stack.remove(order_uid.clone());
-                    if order_stack.is_empty() { self.bids.remove(key); } // todo: make a method to remove nodes by reference
-                },
-                Side::Asks => {
-                    let order_stack = self.asks.get_mut(key).unwrap();
-                    order_stack.remove(order_uid.clone());
-                    if order_stack.is_empty() { self.asks.remove(key); }
+        let previous_top_of_book = self.top_of_book;
+        self.refresh_top_of_book();
+        if self.top_of_book != previous_top_of_book {
+            if let (Some(mid), Some(time)) = (self.mid_price(), parse_timestamp(&timestamp)) {
+                self.record_mid((time, mid));
+            }
+        }
+        if let Some(imbalance) = self.imbalance(self.imbalance_history_depth) {
+            self.record_imbalance((timestamp, imbalance));
+        }
+        self.items_processed += 1;
+        if let Some(interval) = self.check_interval {
+            // a locked book is reported by check but is a feed anomaly, so it doesn't halt
+            if self.items_processed % interval == 0 {
+                let locked = self.locked_msg();
+                if self.check().iter().any(|msg| Some(msg) != locked.as_ref()) {
+                    self.halted = true;
                 }
             }
-            self.len -= 1;
-            self.order_map.remove(&*order_uid);
         }
+        Ok(())
     }
 
-    /// Updates an order
-    fn update(&mut self, order_uid: String, new_size: f64) {
-        if let Some(order) = self.get_order_mut(order_uid.clone()) {
-            if new_size == 0.0 {
-                self.remove(order_uid)
-            } else {
-                order.size = new_size;
-            };
+    /// Process an order carrying a feed sequence number. Messages at or below the last
+    /// sequence seen are stale and ignored. A gap in the sequence invalidates the book until
+    /// it's resynced with clear().
+    pub fn process_sequenced(&mut self, order: Order, action: Submit, sequence: u64) -> PyResult<()> {
+        if !self.is_valid {
+            return Err(OrderbookError::Invalidated.into())
         }
-    }
-
-    /// Auxiliary method for displaying trees
-    fn display_trees_aux(&self, side: Side, print: bool) -> Vec<String> {
-        let mut tree_vector: Vec<String> = Vec::new();
-        match side {
-            Side::Bids => {
-                tree_vector.push("\nBids: ".to_string());
-                tree_vector.extend(self.bids.display())
-            },
-            Side::Asks => {
-                tree_vector.push("\nAsks: ".to_string());
-                tree_vector.extend(self.asks.display())
+        if let Some(last) = self.last_sequence {
+            if sequence <= last {
+                return Ok(())
             }
-        }
-        if print {
-            for line in &tree_vector {
-                println!("{}", line);
+            if sequence > last + 1 {
+                self.is_valid = false;
+                self.error_msgs.insert(INVALIDATED_MSG.to_string());
+                return Err(OrderbookError::SequenceGap { expected: last + 1, received: sequence }.into())
             }
         }
-        tree_vector
+        self.process(order, action)?;
+        self.last_sequence = Some(sequence);
+        Ok(())
     }
 
-    /// Iterate over every order in the orderbook.
-    ///
-    /// Does this by iterating over orders in each OrderStack, choosing OrderStacks
-    /// using In-order traversal on first the bid tree and then the ask tree.
-    fn iter(&self) -> Iter {
-        let mut iter = Iter {
-            side: Side::Bids,
-            current_node: None,
-            bid_tree_iter: self.bids.iter().peekable(),
-            ask_tree_iter: self.asks.iter().peekable(),
-            stack_iter: None,
-        };
+    #[getter(is_valid)]
+    /// Returns false while the book is invalidated by a sequence gap. An invalid book reads
+    /// as empty: every reader of its levels or orders, from best_bid and levels to has,
+    /// queue_ahead and level_crc32, returns None, nothing or zero. Counters, settings, the mid,
+    /// imbalance and trade histories, len, is_empty, tree diagnostics, check and the snapshot
+    /// serializers still see the stored state, as does get_order, which updates use internally.
+    pub fn is_valid(&self) -> bool { self.is_valid }
 
-        if iter.bid_tree_iter.peek().is_some() {
-            iter.current_node = iter.bid_tree_iter.next();
-        } else if iter.ask_tree_iter.peek().is_some() {
-            iter.current_node = iter.ask_tree_iter.next();
-        };
+    /// Remove every order and reset sequencing, e.g. to resync from a fresh snapshot.
+    /// This also lifts a halt, since the state the failed check found is gone.
+    /// Configuration and processing counters are kept.
+    pub fn clear(&mut self) {
+        self.invalidate_levels();
+        self.bids.clear();
+        self.asks.clear();
+        self.order_map.clear();
+        self.pending.clear();
+        self.empty_since.clear();
+        self.len = 0;
+        self.bid_cutoff = 0.0;
+        self.ask_cutoff = 0.0;
+        self.last_bid_update = None;
+        self.last_ask_update = None;
+        self.last_sequence = None;
+        self.is_valid = true;
+        self.halted = false;
+        self.error_msgs.remove(INVALIDATED_MSG);
+        self.pressure_ema = None;
+        self.mid_history.clear();
+        self.checksum_failures = 0;
+        self.refresh_top_of_book();
+    }
 
-        if let Some(node) = iter.current_node {
-            let order_stack = &node.value;
-            iter.stack_iter = Some(order_stack.0.iter());
+    /// Process a batch of orders and their actions in order, under a single call.
+    /// Stops at the first update that fails, leaving the earlier updates applied.
+    pub fn process_batch(&mut self, orders: Vec<Order>, actions: Vec<Submit>) -> PyResult<()> {
+        if orders.len() != actions.len() {
+            return Err(PyValueError::new_err(format!(
+                "process_batch got {} orders but {} actions", orders.len(), actions.len()
+            )))
+        }
+        for (order, action) in orders.into_iter().zip(actions) {
+            self.process(order, action)?;
         }
+        Ok(())
+    }
 
-        iter
+    /// Process an order, returning true only if it changed the best bid or best ask,
+    /// either in price or in size
+    pub fn process_and_report_bbo(&mut self, order: Order, action: Submit) -> PyResult<bool> {
+        let before = self.bbo();
+        self.process(order, action)?;
+        Ok(self.bbo() != before)
     }
-}
 
-pub struct Iter<'a> {
-    side: Side,
-    // current_node: Option<(&'a f64, &'a OrderStack)>,
-    current_node: Option<&'a Node<f64, OrderStack>>,
-    bid_tree_iter: Peekable<avl_tree::Iter<'a, f64, OrderStack, Order>>,
-    ask_tree_iter: Peekable<avl_tree::Iter<'a, f64, OrderStack, Order>>,
-    stack_iter: Option<std::collections::vec_deque::Iter<'a, Order>>,
-}
+    /// Run check() automatically every interval processed items, halting processing if it
+    /// finds errors. Pass None to disable.
+    pub fn set_check_interval(&mut self, interval: Option<usize>) -> PyResult<()> {
+        if interval == Some(0) {
+            return Err(PyValueError::new_err("check interval must be positive"))
+        }
+        self.check_interval = interval;
+        Ok(())
+    }
 
-impl<'a> Iterator for Iter<'a> {
-    type Item = &'a Order;
+    /// Set the smoothing factor of the pressure EMA and the depth its imbalance is measured
+    /// over, restarting the average. Alpha must be in (0, 1]; higher reacts faster.
+    pub fn set_pressure_ema_alpha(&mut self, alpha: f64, depth: usize) -> PyResult<()> {
+        if !(alpha > 0.0 && alpha <= 1.0) {
+            return Err(PyValueError::new_err(format!("pressure EMA alpha must be in (0, 1], got {}", alpha)))
+        }
+        if depth == 0 {
+            return Err(PyValueError::new_err("pressure EMA depth must be positive"))
+        }
+        self.pressure_ema_alpha = alpha;
+        self.pressure_depth = depth;
+        self.pressure_ema = None;
+        Ok(())
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut result;
-        loop {
-            match &self.side {
-                Side::Bids => {
-                    if let Some(_node) = self.current_node {
-                        // let key = &node.key;
-                        // let order_stack = &node.value;
-                        // println!("\ncurrent bid node is {}, with stack length {}", key, stack.len());
-                        result = self.stack_iter.as_mut().unwrap().next();
-                        if result.is_some() {
-                            // println!("result = {:?}", result);
-                            break
-                        } else {
-                            // println!("empty result. iterating to next node");
-                            self.current_node = self.bid_tree_iter.next();
-                            self.stack_iter = match self.current_node {
-                                Some(node) => {
-                                    Some(node.value.0.iter())
-                                },
-                                None => None,
-                            };
-                            continue
-                        }
-                    } else {
-                        // println!("current_node is None. Moving to asks tree...");
-                        self.side = Side::Asks;
-                        self.current_node = self.ask_tree_iter.next();
-                        if let Some(node) = self.current_node {
-                            self.stack_iter = Some(node.value.0.iter());
-                        }
-                    }
-                }
+    /// Set how outliers are detected, with abs_tol the absolute distance from the touch used
+    /// by the Absolute and Both modes. Cutoffs are recomputed from the current top of book.
+    pub fn set_outlier_mode(&mut self, mode: OutlierMode, abs_tol: f64) -> PyResult<()> {
+        if !abs_tol.is_finite() || abs_tol < 0.0 {
+            return Err(PyValueError::new_err(format!("outlier abs_tol must be non-negative, got {}", abs_tol)))
+        }
+        self.outlier_mode = mode;
+        self.outlier_abs_tol = abs_tol;
+        self.reset_cutoffs();
+        Ok(())
+    }
 
-                Side::Asks => {
-                    if let Some(_node) = self.current_node {
-                        // let key = &node.key;
-                        // let order_stack = &node.value;
-                        // println!("\ncurrent ask node is {}, with stack length {}", key, stack.len());
-                        // println!("iterating through stack_iter");
-                        result = self.stack_iter.as_mut().unwrap().next();
-                        if result.is_some() {
-                            // println!("result = {:?}", result);
-                            break
-                        } else {
-                            // println!("empty result. iterating to next node");
-                            self.current_node = self.ask_tree_iter.next();
-                            self.stack_iter = match self.current_node {
-                                Some(node) => {
-                                    Some(node.value.0.iter())
-                                },
-                                None => None,
-                            };
-                            continue
-                        }
-                    } else {
-                        // println!("current_node is None. Iteration complete.");
-                        result = None;
-                        break
-                    }
-                }
+    /// Reject inserts whose size exceeds max_size, e.g. fat-fingered or corrupt orders.
+    /// Pass None to accept any size.
+    pub fn set_max_order_size(&mut self, max_size: Option<f64>) -> PyResult<()> {
+        if let Some(max_size) = max_size {
+            if !max_size.is_finite() || max_size <= 0.0 {
+                return Err(PyValueError::new_err(format!("max order size must be positive, got {}", max_size)))
             }
         }
-        result
+        self.max_order_size = max_size;
+        Ok(())
     }
-}
 
-impl OrderStack {
-    /// Create new order stack instance
-    pub fn new() -> Self {
-        OrderStack( VecDeque::new() )
+    /// Set how long a price level may go without updates before evict_stale removes it.
+    /// Pass None to disable eviction. Errors if the TTL is NaN or negative.
+    pub fn set_level_ttl(&mut self, ttl_secs: Option<f64>) -> PyResult<()> {
+        if let Some(ttl_secs) = ttl_secs {
+            if ttl_secs.is_nan() || ttl_secs < 0.0 {
+                return Err(PyValueError::new_err(format!("level TTL must be non-negative, got {}", ttl_secs)))
+            }
+        }
+        self.level_ttl_secs = ttl_secs;
+        Ok(())
     }
 
-    /// Return true if orderstack is empty
-    pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+    /// Remove every level whose most recent order insert, update or fill is older than the
+    /// level TTL as of now, a timestamp in the orders' format, e.g. 2022-09-27T19:31:00.123456Z.
+    /// Returns count of levels evicted. Levels whose timestamps can't be parsed are kept.
+    /// Does nothing if no level TTL is set.
+    pub fn evict_stale(&mut self, now: String) -> PyResult<usize> {
+        match parse_timestamp(&now) {
+            Some(now) => Ok(self.evict_stale_at(now)),
+            None => Err(PyValueError::new_err(format!("invalid timestamp {:?}", now))),
+        }
     }
 
-    /// Return immutable reference to an order by its order uid
-    pub fn get_order(&self, order_uid: String) -> Option<&Order> {
-        self.0.iter().find(|order| order.uid == order_uid)
+    /// Cap each side at its best max_depth levels, evicting the worst levels beyond it
+    /// as orders arrive. Pass None to remove the cap.
+    pub fn set_max_depth_per_side(&mut self, max_depth: Option<usize>) {
+        self.max_depth_per_side = max_depth;
+        self.trim_depth(&Side::Bids);
+        self.trim_depth(&Side::Asks);
+        self.reset_cutoffs();
+        self.refresh_top_of_book();
     }
 
-    /// Return mutable reference to an order by its order uid
-    pub fn get_order_mut(&mut self, order_uid: String) -> Option<&mut Order> {
-        self.0.iter_mut().find(|order| order.uid == order_uid)
+    /// Keep a price level's node in its tree after its last order leaves, so an order
+    /// arriving at that price again reuses it instead of rebalancing the tree twice.
+    /// Lingering empty levels are invisible to queries and are pruned by compact().
+    /// Disabling removes any lingering levels immediately.
+    pub fn set_linger_empty_levels(&mut self, linger: bool) {
+        self.linger_empty_levels = linger;
+        if !linger {
+            self.prune_empty_levels(|_| true);
+        }
     }
 
-    /// Push order to the back of the stack
-    pub fn push_back(&mut self, order: Order) {
-        self.0.push_back(order);
+    /// Prune empty levels that have lingered for at least one full compaction cycle,
+    /// then start a new cycle. Returns count of levels pruned.
+    pub fn compact(&mut self) -> usize {
+        let generation = self.generation;
+        let pruned = self.prune_empty_levels(|empty_since| empty_since < generation);
+        self.generation += 1;
+        pruned
     }
 
-    /// Pop order from the front of the stack
-    pub fn pop_front(&mut self) -> Option<Order> {
-        self.0.pop_front()
-    }
+    #[getter(halted)]
+    /// Returns true if a periodic check found errors. A halted orderbook rejects all updates
+    /// until clear() resets it.
+    pub fn halted(&self) -> bool { self.halted }
 
-    /// Remove order from the stack, by uid
-    pub fn remove(&mut self, order_uid: String) -> Option<Order> {
-        let index = self.0.iter().position(|order| order.uid == order_uid)?;
-        self.0.remove(index)
+    /// Return a CRC32 of the best 25 levels, computed over the string
+    /// "bid1_price:bid1_size:ask1_price:ask1_size:..." with levels interleaved best first.
+    /// A side that runs out of levels is skipped. Prices and sizes are formatted with Rust's
+    /// shortest round-trip representation, e.g. 100 rather than 100.00.
+    ///
+    /// This is an internal fingerprint for comparing two books built by this crate, e.g. a
+    /// live book against a replica. It won't match checksums published by an exchange, which
+    /// are computed over the feed's own price and size strings.
+    pub fn level_crc32(&self) -> u32 {
+        let mut fields: Vec<String> = Vec::new();
+        let mut bids = self.l2_iter(Side::Bids).take(CHECKSUM_DEPTH);
+        let mut asks = self.l2_iter(Side::Asks).take(CHECKSUM_DEPTH);
+        loop {
+            let (bid, ask) = (bids.next(), asks.next());
+            if bid.is_none() && ask.is_none() {
+                break
+            }
+            for (price, size) in bid.into_iter().chain(ask) {
+                fields.push(format!("{}:{}", price, size));
+            }
+        }
+        crc32(fields.join(":").as_bytes())
     }
 
-    /// Return cumulative order size
-    pub fn size(&self) -> f64 {
-        self.0.iter().fold(0.0, |sum, order| sum + order.size)
+    /// Return a 64-bit FNV-1a hash of every level's price and aggregate size, both sides, in
+    /// book order. Books with equal aggregate state share a fingerprint however their orders
+    /// are split across the level, and it's stable across runs and builds, so it can be
+    /// stored and compared later. Sizes are compared bitwise, so sums that differ by
+    /// rounding, e.g. 0.1 + 0.2 against 0.3, fingerprint differently.
+    pub fn fingerprint(&self) -> u64 {
+        let mut bytes: Vec<u8> = Vec::new();
+        for side in [Side::Bids, Side::Asks] {
+            // tag each side so a level can't hash the same on either side
+            bytes.push(side.clone() as u8);
+            for (price, size) in self.l2_iter(side) {
+                bytes.extend(price.to_bits().to_le_bytes());
+                bytes.extend(size.to_bits().to_le_bytes());
+            }
+        }
+        fnv1a(&bytes)
     }
 
-    /// Return order stack's size
-    pub fn len(&self) -> usize { self.0.len() }
+    /// Compare the book's level_crc32 against one computed by a reference copy of the book.
+    /// Consecutive failures are counted, and a pass resets the count; see needs_resync.
+    pub fn validate_checksum(&mut self, expected: u32) -> bool {
+        let computed = self.level_crc32();
+        let valid = computed == expected;
+        if valid {
+            self.checksum_failures = 0;
+        } else {
+            self.checksum_failures += 1;
+            println!("Warning: checksum mismatch ({} consecutive), expected {}, computed {}",
+                     self.checksum_failures, expected, computed);
+        }
+        valid
+    }
 
-}
+    #[getter(needs_resync)]
+    /// Returns true once validate_checksum has failed max_checksum_failures times in a row,
+    /// meaning the book has diverged from its reference and should be rebuilt from a snapshot
+    pub fn needs_resync(&self) -> bool { self.checksum_failures >= self.max_checksum_failures }
 
-impl Collection for OrderStack { type Item = Order; }
+    /// Set how many consecutive checksum failures flag the book for resync
+    pub fn set_max_checksum_failures(&mut self, max_failures: usize) -> PyResult<()> {
+        if max_failures == 0 {
+            return Err(PyValueError::new_err("max checksum failures must be positive"))
+        }
+        self.max_checksum_failures = max_failures;
+        Ok(())
+    }
 
-impl PushBack for OrderStack {
-    type Output = ();
-    fn push_back(&mut self, element: Self::Item) -> Self::Output {
-        type Item = Order;
-        type Output = ();
-        self.push_back(element)
+    /// Stage a received order that isn't open on the book yet. A later Insert for its uid
+    /// takes any missing price or size from the staged order; a Remove drops it.
+    pub fn stage(&mut self, order: Order) {
+        self.pending.insert(order.uid.clone(), order);
     }
-}
 
-impl New for OrderStack {
-    fn new() -> Self { OrderStack::new() }
-}
+    #[getter(pending_count)]
+    /// Return count of staged orders awaiting open
+    pub fn pending_count(&self) -> usize { self.pending.len() }
 
-impl Len for OrderStack {
-    fn len(&self) -> usize { self.len() }
-}
+    /// Return some notes regarding what has been processed so far
+    pub fn log_notes(&self) -> String {
+        let mut notes_vec: Vec<String> = Vec::new();
 
-#[pymethods]
-impl Order {
-    /// Create order struct out of limit order parameters
-    #[new]
+        if self.error_msgs.len() == 0 {
+            notes_vec.push("No errors encountered".to_string());
+        } else {
+            notes_vec.push("******Errors encountered******".to_string());
+            for msg in &self.error_msgs {
+                notes_vec.push(msg.clone());
+            }
+            notes_vec.push("---------------------------------".to_string());
+        }
+
+        if self.bids.len() > self.avl_tree_size_display_cutoff {
+            let mut msg = format!("Bids AVL Tree too large to display ({} nodes). ", self.bids.len());
+            msg += &*format!("Increase avl_tree_size_display_cutoff ({}) \
+                in orderbook to display larger trees", self.avl_tree_size_display_cutoff);
+            notes_vec.push(msg);
+        } else {
+            let msg = format!("{} bid levels = {:?}", self.bids.len(), self.levels(Side::Bids, None));
+            notes_vec.push(msg);
+            notes_vec.extend(self.display_trees_aux(Side::Bids, false));
+        }
+
+        if self.asks.len() > self.avl_tree_size_display_cutoff {
+            let mut msg = format!("Asks AVL Tree too large to display ({} nodes). ", self.asks.len());
+            msg += &*format!("Increase avl_tree_size_display_cutoff ({}) \
+                in orderbook to display larger trees", self.avl_tree_size_display_cutoff);
+            notes_vec.push(msg);
+        } else {
+            let msg = format!("{} ask levels = {:?}", self.asks.len(), self.levels(Side::Asks, None));
+            notes_vec.push(msg);
+            notes_vec.extend(self.display_trees_aux(Side::Asks, false));
+        }
+
+        notes_vec.push(format!("Items processed by orderbook: {}", self.items_processed));
+        notes_vec.push(format!("Outliers ignored by orderbook: {}", self.outliers));
+        notes_vec.push(format!("Oversized orders rejected by orderbook: {}", self.rejected));
+        notes_vec.join("\n")
+    }
+
+    /// Perform checks, returning any error_msgs found. A locked book gets its own entry,
+    /// which unlike the others doesn't halt a book with a check interval.
+    pub fn check(&mut self) -> HashSet<String> {
+        let mut error_msgs: HashSet<String> = HashSet::new();
+        error_msgs = self.bids.check(error_msgs);
+        error_msgs = self.asks.check(error_msgs);
+
+        let order_count = self.iter().count();
+        if order_count != self.len || self.order_map.len() != self.len {
+            error_msgs.insert(format!(
+                "Order count mismatch: len {}, orders in trees {}, orders in order_map {}",
+                self.len, order_count, self.order_map.len()
+            ));
+        }
+
+        if !self.bids.is_balanced() {
+            error_msgs.insert("Bids are not balanced!".to_string());
+        }
+        if !self.asks.is_balanced() {
+            error_msgs.insert("Asks are not balanced!".to_string());
+        }
+        error_msgs.extend(self.locked_msg());
+        if !self.is_valid {
+            error_msgs.insert(INVALIDATED_MSG.to_string());
+        }
+        self.error_msgs = error_msgs;
+        self.error_msgs.clone()
+    }
+
+    /// Return warnings about the state of the market at the top of book: a locked or crossed
+    /// book. These are anomalies in the feed rather than in the book, so they don't touch
+    /// error_msgs. check reports the same locked warning, but neither halts the book.
+    pub fn market_warnings(&self) -> HashSet<String> {
+        let mut warnings: HashSet<String> = HashSet::new();
+        if let Some(msg) = self.locked_msg() {
+            warnings.insert(msg);
+        } else if let (true, Some(best_bid), Some(best_ask)) = (self.is_crossed(), self.best_bid(), self.best_ask()) {
+            warnings.insert(format!("Book is crossed: best bid {} > best ask {}", best_bid, best_ask));
+        }
+        warnings
+    }
+
+    /// Return true if every level's orders are in time priority, i.e. their parsed timestamps
+    /// are non-decreasing front to back. Each violation is recorded in error_msgs. Orders
+    /// with unparseable timestamps are skipped.
+    pub fn verify_fifo(&mut self) -> bool {
+        let mut violations: Vec<String> = Vec::new();
+        for (side, tree) in [(Side::Bids, &self.bids), (Side::Asks, &self.asks)] {
+            for node in tree.iter() {
+                let mut previous: Option<(&Order, DateTime<Utc>)> = None;
+                for order in node.value.0.iter() {
+                    let time = match order.time() {
+                        Some(time) => time,
+                        None => continue,
+                    };
+                    if let Some((previous_order, previous_time)) = previous {
+                        if time < previous_time {
+                            violations.push(format!(
+                                "FIFO violation at {:?} level {}: order {} ({}) queued behind order {} ({})",
+                                side, node.key, order.uid, order.timestamp(), previous_order.uid, previous_order.timestamp()
+                            ));
+                        }
+                    }
+                    previous = Some((order, time));
+                }
+            }
+        }
+        let valid = violations.is_empty();
+        self.error_msgs.extend(violations);
+        valid
+    }
+
+    /// Serialize every resting order and the orderbook's counters to a JSON string
+    pub fn to_snapshot_json(&self) -> String {
+        serde_json::to_string(&self.snapshot()).unwrap()
+    }
+
+    /// Serialize the best n levels per side to a JSON depth ladder of the form
+    /// {"bids":[[price,size],...],"asks":[[price,size],...],"ts":"..."}, stamped with the current time
+    pub fn top_n_json(&self, n: usize) -> String {
+        let ladder = DepthLadder {
+            bids: self.l2_iter(Side::Bids).take(n).collect(),
+            asks: self.l2_iter(Side::Asks).take(n).collect(),
+            ts: Utc::now().format("%Y-%m-%dT%H:%M:%S.%6fZ").to_string(),
+        };
+        serde_json::to_string(&ladder).unwrap()
+    }
+
+    #[staticmethod]
+    /// Rebuild an orderbook from a JSON string produced by to_snapshot_json,
+    /// restoring its counters
+    pub fn from_snapshot_json(json: String) -> PyResult<LimitOrderbook> {
+        let snapshot: OrderbookSnapshot = serde_json::from_str(&json)
+            .map_err(|e| PyValueError::new_err(format!("invalid orderbook snapshot: {}", e)))?;
+        Ok(Self::from_snapshot(snapshot))
+    }
+
+    /// Serialize every resting order and the orderbook's counters to MessagePack bytes,
+    /// prefixed with a schema version byte. Unlike the JSON snapshot the binary format
+    /// is not self-describing across schema versions, so snapshots are only readable by
+    /// builds whose supported versions, MIN_SNAPSHOT_MSGPACK_VERSION through
+    /// SNAPSHOT_MSGPACK_VERSION, include the one written.
+    pub fn to_snapshot_msgpack<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        PyBytes::new(py, &self.snapshot_msgpack())
+    }
+
+    #[staticmethod]
+    /// Rebuild an orderbook from bytes produced by to_snapshot_msgpack, restoring its counters.
+    /// Snapshots from any version since MIN_SNAPSHOT_MSGPACK_VERSION are accepted.
+    pub fn from_snapshot_msgpack(bytes: &[u8]) -> PyResult<LimitOrderbook> {
+        match bytes.split_first() {
+            Some((version, payload)) if (MIN_SNAPSHOT_MSGPACK_VERSION..=SNAPSHOT_MSGPACK_VERSION).contains(version) => {
+                let snapshot: OrderbookSnapshot = rmp_serde::from_slice(payload)
+                    .map_err(|e| PyValueError::new_err(format!("invalid orderbook snapshot: {}", e)))?;
+                Ok(Self::from_snapshot(snapshot))
+            },
+            Some((version, _)) => Err(PyValueError::new_err(format!(
+                "unsupported orderbook snapshot version {}, expected {} to {}",
+                version, MIN_SNAPSHOT_MSGPACK_VERSION, SNAPSHOT_MSGPACK_VERSION
+            ))),
+            None => Err(PyValueError::new_err("empty orderbook snapshot")),
+        }
+    }
+
+    /// Return (min, mean, max) of the spread observed at each top-of-book change,
+    /// or None if no two-sided, uncrossed top of book has been observed yet
+    pub fn spread_stats(&self) -> Option<(f64, f64, f64)> {
+        self.spread_stats.summary()
+    }
+
+    /// Return up to n of the most recent trades on the trade tape, oldest first, as
+    /// (timestamp, side, price, size) tuples. Side is the side of the resting (maker) order.
+    pub fn recent_trades(&self, n: usize) -> Vec<(String, Side, f64, f64)> {
+        let skip = self.trade_tape.len().saturating_sub(n);
+        self.trade_tape.iter().skip(skip).cloned().collect()
+    }
+
+    /// Set how many trades the trade tape retains, evicting the oldest beyond it
+    pub fn set_trade_tape_capacity(&mut self, capacity: usize) {
+        self.trade_tape_capacity = capacity;
+        while self.trade_tape.len() > self.trade_tape_capacity {
+            self.trade_tape.pop_front();
+        }
+    }
+
+    /// Return up to n of the most recent (timestamp, imbalance) samples, oldest first.
+    /// A sample is taken after each processed message while the book is two-sided.
+    pub fn imbalance_history(&self, n: usize) -> Vec<(String, f64)> {
+        let skip = self.imbalance_history.len().saturating_sub(n);
+        self.imbalance_history.iter().skip(skip).cloned().collect()
+    }
+
+    /// Set how many imbalance samples are retained, evicting the oldest beyond it, and the
+    /// depth new samples are measured over
+    pub fn set_imbalance_history(&mut self, capacity: usize, depth: usize) -> PyResult<()> {
+        if depth == 0 {
+            return Err(PyValueError::new_err("imbalance history depth must be positive"))
+        }
+        self.imbalance_history_capacity = capacity;
+        self.imbalance_history_depth = depth;
+        while self.imbalance_history.len() > self.imbalance_history_capacity {
+            self.imbalance_history.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Zero the items_processed, outliers, rejected, removal reason and message flow counters
+    pub fn reset_counters(&mut self) {
+        self.total_adds = 0;
+        self.total_cancels = 0;
+        self.total_updates = 0;
+        self.items_processed = 0;
+        self.outliers = 0;
+        self.rejected = 0;
+        self.filled_count = 0;
+        self.canceled_count = 0;
+    }
+
+    /// Remove every order on one side of the book, leaving the other side untouched
+    pub fn clear_side(&mut self, side: Side) {
+        self.invalidate_levels();
+        let tree = match side {
+            Side::Bids => &mut self.bids,
+            Side::Asks => &mut self.asks,
+        };
+        let uids: Vec<String> = tree.iter()
+            .flat_map(|node| node.value.0.iter().map(|order| order.uid.clone()))
+            .collect();
+        tree.clear();
+        self.empty_since.retain(|(level_side, _), _| *level_side != side);
+        for uid in &uids {
+            self.order_map.remove(uid);
+        }
+        self.len -= uids.len();
+        match side {
+            Side::Bids => self.bid_cutoff = 0.0,
+            Side::Asks => self.ask_cutoff = 0.0,
+        }
+        self.refresh_top_of_book();
+    }
+
+    /// Apply a relative size change to an order, as sent by feeds that publish size deltas,
+    /// stamped with the delta message's timestamp. The change is processed as an Update, so
+    /// it's subject to the same checks and counters as process. The order is removed once
+    /// its size reaches zero; a delta that would drive the size negative is logged and
+    /// clamped to zero. Errors on a NaN or infinite delta.
+    pub fn update_delta(&mut self, uid: String, delta: f64, timestamp: String) -> PyResult<()> {
+        if !delta.is_finite() {
+            return Err(OrderbookError::InvalidSize { uid, size: delta }.into())
+        }
+        let current = match self.get_order(uid.clone()) {
+            Some(order) => order.size,
+            // process drops any stale entry for an unknown uid
+            None => return self.process(Order::new(uid, None, None, None, timestamp), Submit::Update),
+        };
+        let new_size = current + delta;
+        if new_size < 0.0 {
+            println!("Warning: delta {} exceeds remaining size {} of order {}. Clamping to zero.",
+                     delta, current, uid);
+        }
+        self.process(Order::new(uid, None, None, Some(new_size.max(0.0)), timestamp), Submit::Update)
+    }
+
+    /// Merge price levels within epsilon of each other, e.g. 57.0 and 57.00000000001 left
+    /// behind by a precision change. Each level within epsilon of the lowest price of its run
+    /// is merged into that price, its orders interleaved with the stack there by timestamp.
+    /// Returns count of levels merged away. Errors if epsilon isn't a finite positive number.
+    pub fn coalesce_levels(&mut self, epsilon: f64) -> PyResult<usize> {
+        if !epsilon.is_finite() || epsilon <= 0.0 {
+            return Err(PyValueError::new_err(format!("epsilon must be finite and positive, got {}", epsilon)));
+        }
+        self.invalidate_levels();
+        self.prune_empty_levels(|_| true);
+        Ok(Self::coalesce_tree(&mut self.bids, &mut self.order_map, epsilon)
+            + Self::coalesce_tree(&mut self.asks, &mut self.order_map, epsilon))
+    }
+}
+
+impl LimitOrderbook {
+
+    /// Return a side's levels() as a shared vector. It's built on first use and cached until
+    /// the next mutation, so repeated calls between updates only clone the Arc. Only Rust
+    /// callers get the sharing; levels() copies the vector for Python.
+    pub fn cached_levels(&self, side: Side) -> Arc<Vec<(f64, f64, f64)>> {
+        if !self.is_valid { return Arc::new(Vec::new()) }
+        let mut cache = self.levels_cache.lock();
+        let cached = match side {
+            Side::Bids => &mut cache.0,
+            Side::Asks => &mut cache.1,
+        };
+        cached.get_or_insert_with(|| Arc::new(self.levels_iter(side.clone()).collect())).clone()
+    }
+
+    /// Return the check() entry reporting a locked book, or None if it isn't locked
+    pub(crate) fn locked_msg(&self) -> Option<String> {
+        match (self.is_locked(), self.best_bid(), self.best_ask()) {
+            (true, Some(best_bid), Some(best_ask)) =>
+                Some(format!("Book is locked: best bid {} == best ask {}", best_bid, best_ask)),
+            _ => None,
+        }
+    }
+
+    /// Mark the cached levels dirty. Called by every path that changes the trees or order sizes.
+    fn invalidate_levels(&mut self) {
+        *self.levels_cache.get_mut() = (None, None);
+    }
+
+    /// Return the (price, aggregate size) of the best bid and best ask levels
+    fn bbo(&self) -> (Option<(f64, f64)>, Option<(f64, f64)>) {
+        let top = |side: Side| self.side_iter(&side).next().map(|node| (node.key, node.value.size()));
+        (top(Side::Bids), top(Side::Asks))
+    }
+
+    /// Resolve an incoming order against the staging area: Inserts of a staged order are
+    /// completed from it, and Removes of a staged order abort it. The staged order is kept
+    /// until its Insert is accepted, so process drops it once the order rests on the book.
+    fn resolve_pending(&mut self, mut order: Order, action: &Submit) -> Order {
+        match action {
+            Submit::Insert => {
+                if let Some(staged) = self.pending.get(&order.uid) {
+                    if order.price == 0.0 { order.price = staged.price; }
+                    if order.size == 0.0 { order.size = staged.size; }
+                }
+            },
+            Submit::Remove | Submit::RemoveWithReason => { self.pending.remove(&order.uid); },
+            _ => {}
+        }
+        order
+    }
+
+    /// Remove the lingering empty levels whose empty-since generation passes the filter,
+    /// returning count removed
+    fn prune_empty_levels<F: Fn(u64) -> bool>(&mut self, filter: F) -> usize {
+        let pruned: Vec<(Side, u64)> = self.empty_since.iter()
+            .filter(|(_, generation)| filter(**generation))
+            .map(|(level, _)| level.clone())
+            .collect();
+        for (side, bits) in &pruned {
+            self.empty_since.remove(&(side.clone(), *bits));
+            match side {
+                Side::Bids => self.bids.remove(&f64::from_bits(*bits)),
+                Side::Asks => self.asks.remove(&f64::from_bits(*bits)),
+            };
+        }
+        pruned.len()
+    }
+
+    /// Merge near-duplicate levels of a single tree, remapping merged orders in order_map
+    fn coalesce_tree(tree: &mut AVLTree<f64, OrderStack, Order>,
+                     order_map: &mut HashMap<String, (Side, f64)>, epsilon: f64) -> usize {
+        let prices: Vec<f64> = tree.iter().map(|node| node.key).collect();
+        let mut merged = 0;
+        let mut canonical = match prices.first() { Some(price) => *price, None => return 0 };
+        for &price in &prices[1..] {
+            // measured from the run's anchor so a chain of close levels can't drift
+            if price - canonical >= epsilon {
+                canonical = price;
+                continue
+            }
+            let (_, merged_stack) = tree.remove(&price).unwrap();
+            let order_stack = tree.get_mut(&canonical).unwrap();
+            let mut resting = std::mem::take(&mut order_stack.0).into_iter().peekable();
+            let mut incoming = merged_stack.0.into_iter().map(|mut order| {
+                order.price = canonical;
+                order_map.insert(order.uid.clone(), (order.side.clone(), canonical));
+                order
+            }).peekable();
+            // interleave by timestamp to keep time priority; where timestamps can't be
+            // compared the resting orders stay ahead
+            loop {
+                let incoming_first = match (resting.peek(), incoming.peek()) {
+                    (Some(a), Some(b)) => matches!((a.time(), b.time()), (Some(a), Some(b)) if b < a),
+                    (None, Some(_)) => true,
+                    (_, None) => false,
+                };
+                match if incoming_first { incoming.next() } else { resting.next() } {
+                    Some(order) => order_stack.push_back(order),
+                    None => break,
+                }
+            }
+            merged += 1;
+        }
+        merged
+    }
+
+    /// Return the level changes that turn previous into this book, as (side, price,
+    /// new aggregate size) tuples in book order. Levels that disappeared have size 0.
+    pub fn diff(&self, previous: &LimitOrderbook) -> Vec<(Side, f64, f64)> {
+        let mut changes = Vec::new();
+        for side in [Side::Bids, Side::Asks] {
+            // true if price a comes before price b in book order
+            let ahead = |a: f64, b: f64| match side {
+                Side::Bids => a > b,
+                Side::Asks => a < b,
+            };
+            let mut old_levels = previous.levels_iter(side.clone()).peekable();
+            let mut new_levels = self.levels_iter(side.clone()).peekable();
+            loop {
+                match (old_levels.peek().copied(), new_levels.peek().copied()) {
+                    (None, None) => break,
+                    (Some((old_price, old_size, _)), Some((new_price, new_size, _))) if old_price == new_price => {
+                        if old_size != new_size {
+                            changes.push((side.clone(), new_price, new_size));
+                        }
+                        old_levels.next();
+                        new_levels.next();
+                    },
+                    (Some((old_price, _, _)), new) if new.map_or(true, |(new_price, _, _)| ahead(old_price, new_price)) => {
+                        changes.push((side.clone(), old_price, 0.0));
+                        old_levels.next();
+                    },
+                    (_, Some((new_price, new_size, _))) => {
+                        changes.push((side.clone(), new_price, new_size));
+                        new_levels.next();
+                    },
+                    (_, None) => unreachable!(),
+                }
+            }
+        }
+        changes
+    }
+
+    /// Process updates read one per line from an NDJSON stream, returning count processed.
+    /// Lines are parsed and applied as they're read, so memory stays flat however long the
+    /// stream is. Blank lines are skipped; an unreadable or malformed line stops processing
+    /// with an error naming its line number, leaving earlier updates applied.
+    pub fn process_ndjson_reader<R: BufRead>(&mut self, reader: R) -> PyResult<usize> {
+        let mut processed = 0;
+        for (index, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| PyRuntimeError::new_err(format!("line {}: {}", index + 1, e)))?;
+            if line.trim().is_empty() {
+                continue
+            }
+            let update: NdjsonUpdate = serde_json::from_str(&line)
+                .map_err(|e| PyValueError::new_err(format!("line {}: invalid update: {}", index + 1, e)))?;
+            self.process(update.order, update.action)?;
+            processed += 1;
+        }
+        Ok(processed)
+    }
+
+    /// Consume the orderbook, moving every order out in book order (bids then asks, each
+    /// ascending by price) without cloning. Tree nodes are freed as they're drained.
+    pub fn into_orders(self) -> Vec<Order> {
+        let mut orders = Vec::with_capacity(self.len);
+        for (_, order_stack) in self.bids.into_iter().chain(self.asks.into_iter()) {
+            orders.extend(order_stack.0);
+        }
+        orders
+    }
+
+    /// Capture the orderbook's orders and counters
+    pub(crate) fn snapshot(&self) -> OrderbookSnapshot {
+        OrderbookSnapshot {
+            orders: self.iter().cloned().collect(),
+            items_processed: self.items_processed,
+            outliers: self.outliers,
+            rejected: self.rejected,
+            filled_count: self.filled_count,
+            canceled_count: self.canceled_count,
+            total_adds: self.total_adds,
+            total_cancels: self.total_cancels,
+            total_updates: self.total_updates,
+        }
+    }
+
+    /// Encode a snapshot as the schema version byte followed by MessagePack with named fields
+    pub fn snapshot_msgpack(&self) -> Vec<u8> {
+        let mut bytes = vec![SNAPSHOT_MSGPACK_VERSION];
+        bytes.extend(rmp_serde::to_vec_named(&self.snapshot()).unwrap());
+        bytes
+    }
+
+    /// Rebuild an orderbook from a snapshot. Orders were accepted when the snapshot was taken,
+    /// so they're placed without outlier checks and the cutoffs are derived from the restored top of book.
+    pub(crate) fn from_snapshot(snapshot: OrderbookSnapshot) -> Self {
+        let mut orderbook = LimitOrderbook::new();
+        for order in snapshot.orders {
+            orderbook.place(order);
+        }
+        orderbook.reset_cutoffs();
+        orderbook.items_processed = snapshot.items_processed;
+        orderbook.outliers = snapshot.outliers;
+        orderbook.rejected = snapshot.rejected;
+        orderbook.filled_count = snapshot.filled_count;
+        orderbook.canceled_count = snapshot.canceled_count;
+        orderbook.total_adds = snapshot.total_adds;
+        orderbook.total_cancels = snapshot.total_cancels;
+        orderbook.total_updates = snapshot.total_updates;
+        orderbook
+    }
+
+    /// Apply the effect described by a recorded BookEvent, e.g. when replaying an event log.
+    /// Events record updates the book already accepted, so inserts skip the outlier checks,
+    /// and rejected outliers leave the book unchanged.
+    pub(crate) fn apply_event(&mut self, event: &BookEvent) {
+        match event {
+            BookEvent::Inserted { uid, side, price, size } => {
+                self.place(Order::new(uid.clone(), Some(side.clone()), Some(*price), Some(*size), String::new()));
+            },
+            BookEvent::Removed { uid, .. } => self.remove(uid.clone()),
+            BookEvent::Updated { uid, price, size, .. } => {
+                if self.get_order(uid.clone()).map_or(false, |order| order.price != *price) {
+                    self.move_order(uid.clone(), *price);
+                }
+                self.update(uid.clone(), *size, None);
+            },
+            BookEvent::OutlierRejected { .. } => {},
+        }
+        self.refresh_top_of_book();
+    }
+
+    /// Return an error if the book's contents can't be trusted
+    fn ensure_usable(&self) -> PyResult<()> {
+        if !self.is_valid {
+            return Err(PyRuntimeError::new_err(INVALIDATED_MSG))
+        }
+        if self.halted {
+            return Err(PyRuntimeError::new_err(OrderbookError::Halted.to_string()))
+        }
+        Ok(())
+    }
+
+    /// Recompute the outlier cutoffs from the current best bid and best ask
+    fn reset_cutoffs(&mut self) {
+        self.bid_cutoff = self.best_bid().map_or(0.0, |best_bid| self.bid_cutoff_from(best_bid));
+        self.ask_cutoff = self.best_ask().map_or(0.0, |best_ask| self.ask_cutoff_from(best_ask));
+    }
+
+    /// Return the outlier cutoff for bids given the best bid, under the outlier mode
+    fn bid_cutoff_from(&self, best_bid: f64) -> f64 {
+        let multiplicative = best_bid / self.outlier_factor;
+        let absolute = best_bid - self.outlier_abs_tol;
+        match self.outlier_mode {
+            OutlierMode::Multiplicative => multiplicative,
+            OutlierMode::Absolute => absolute,
+            OutlierMode::Both => multiplicative.max(absolute),
+        }
+    }
+
+    /// Return the outlier cutoff for asks given the best ask, under the outlier mode
+    fn ask_cutoff_from(&self, best_ask: f64) -> f64 {
+        let multiplicative = best_ask * self.outlier_factor;
+        let absolute = best_ask + self.outlier_abs_tol;
+        match self.outlier_mode {
+            OutlierMode::Multiplicative => multiplicative,
+            OutlierMode::Absolute => absolute,
+            OutlierMode::Both => multiplicative.min(absolute),
+        }
+    }
+
+    /// Return (buy volume, sell volume) of the trades on the tape timestamped within window_secs
+    /// before now, split by aggressor. The tape records the resting (maker) side, as Coinbase's
+    /// match messages do, so the aggressor is the opposite side: a filled ask was lifted by a
+    /// buyer and a filled bid was hit by a seller. Trades with unparseable timestamps are skipped.
+    /// A window reaching back further than a DateTime can represent covers the whole tape.
+    pub fn aggressor_volume(&self, window_secs: u64, now: DateTime<Utc>) -> (f64, f64) {
+        // chrono durations hold at most i64::MAX milliseconds; None means unbounded
+        let start = i64::try_from(window_secs).ok()
+            .filter(|secs| *secs <= i64::MAX / 1000)
+            .and_then(|secs| now.checked_sub_signed(chrono::Duration::seconds(secs)));
+        let (mut buy_volume, mut sell_volume) = (0.0, 0.0);
+        for (timestamp, maker_side, _, size) in self.trade_tape.iter() {
+            match parse_timestamp(timestamp) {
+                Some(time) if start.map_or(true, |start| time >= start) && time <= now => {},
+                _ => continue,
+            }
+            match maker_side.opposite() {
+                Side::Bids => buy_volume += size,
+                Side::Asks => sell_volume += size,
+            }
+        }
+        (buy_volume, sell_volume)
+    }
+
+    /// evict_stale, with now as a DateTime
+    pub fn evict_stale_at(&mut self, now: DateTime<Utc>) -> usize {
+        let ttl = match self.level_ttl_secs {
+            Some(ttl) => ttl,
+            None => return 0,
+        };
+        let is_stale = |node: &Node<f64, OrderStack>| {
+            let last_update = node.value.0.iter()
+                .filter_map(|order| order.last_update_time())
+                .max();
+            match last_update {
+                Some(last_update) => (now - last_update).num_milliseconds() as f64 / 1000.0 > ttl,
+                None => false,
+            }
+        };
+        let mut evicted = 0;
+        let mut stale_uids: Vec<String> = Vec::new();
+        for side in [Side::Bids, Side::Asks] {
+            for node in self.side_iter(&side).filter(|node| is_stale(node)) {
+                stale_uids.extend(node.value.0.iter().map(|order| order.uid.clone()));
+                evicted += 1;
+            }
+        }
+        for uid in stale_uids {
+            self.remove(uid);
+        }
+        if evicted > 0 {
+            self.reset_cutoffs();
+            self.refresh_top_of_book();
+        }
+        evicted
+    }
+
+    /// Return the total size resting on a side with each order weighted by
+    /// 0.5^(age / half_life_secs), so older orders count for less.
+    ///
+    /// Returns the depth and the count of orders whose timestamp can't be parsed, which get
+    /// weight 1.0, as do orders dated after now. Errors if half_life_secs isn't positive.
+    pub fn decayed_depth(&self, side: Side, half_life_secs: f64, now: DateTime<Utc>) -> PyResult<(f64, usize)> {
+        if half_life_secs.is_nan() || half_life_secs <= 0.0 {
+            return Err(PyValueError::new_err(format!("half-life must be positive, got {}", half_life_secs)))
+        }
+        let mut unparseable = 0;
+        let depth = self.side_iter(&side)
+            .flat_map(|node| node.value.0.iter())
+            .map(|order| match order.time() {
+                Some(timestamp) => {
+                    let age = (now - timestamp).num_milliseconds().max(0) as f64 / 1000.0;
+                    order.size * 0.5_f64.powf(age / half_life_secs)
+                },
+                None => { unparseable += 1; order.size },
+            })
+            .sum();
+        Ok((depth, unparseable))
+    }
+
+    fn parse_query(order: Order, action: Submit) -> Result<SubmitRust, OrderbookError> {
+        match action {
+            Submit::Insert => {
+                if !order.price.is_finite() {
+                    Err(OrderbookError::InvalidPrice { uid: order.uid, price: order.price })
+                } else if !order.size.is_finite() {
+                    Err(OrderbookError::InvalidSize { uid: order.uid, size: order.size })
+                } else {
+                    Ok(SubmitRust::Insert { order })
+                }
+            },
+            Submit::Remove => Ok(SubmitRust::Remove { uid: order.uid, reason: None }),
+            Submit::RemoveWithReason => {
+                let reason = match order.reason.as_deref() {
+                    Some("filled") => RemovalReason::Filled,
+                    Some("canceled") => RemovalReason::Canceled,
+                    other => {
+                        println!("Warning: unknown done reason {:?} for order {}. Counting as canceled.",
+                                 other, order.uid);
+                        RemovalReason::Canceled
+                    },
+                };
+                Ok(SubmitRust::Remove { uid: order.uid, reason: Some(reason) })
+            },
+            Submit::Update => {
+                if !order.size.is_finite() {
+                    Err(OrderbookError::InvalidSize { uid: order.uid, size: order.size })
+                } else {
+                    Ok(SubmitRust::Update { uid: order.uid, new_size: order.size, timestamp: order.timestamp })
+                }
+            },
+            Submit::Fill => {
+                if !order.size.is_finite() {
+                    Err(OrderbookError::InvalidSize { uid: order.uid, size: order.size })
+                } else {
+                    Ok(SubmitRust::Fill { uid: order.uid, fill_qty: order.size })
+                }
+            },
+            Submit::Move => {
+                if !order.price.is_finite() {
+                    Err(OrderbookError::InvalidPrice { uid: order.uid, price: order.price })
+                } else {
+                    Ok(SubmitRust::Move { uid: order.uid, new_price: order.price })
+                }
+            },
+        }
+    }
+
+    /// Iterate over a side's price level nodes in book order, best price first
+    /// Lingering empty levels are skipped. Yields nothing while the book is invalidated,
+    /// which gates every level reader built on it.
+    fn side_iter(&self, side: &Side) -> Box<dyn Iterator<Item=&Node<f64, OrderStack>> + '_> {
+        if !self.is_valid {
+            return Box::new(std::iter::empty())
+        }
+        match side {
+            Side::Bids => Box::new(self.bids.iter().rev().filter(|node| !node.value.is_empty())),
+            Side::Asks => Box::new(self.asks.iter().filter(|node| !node.value.is_empty())),
+        }
+    }
+
+    /// Lazily iterate over (f64, f64, f64) tuples of price, marginal order size
+    /// and cumulative depth, in book order. Backs levels() without the intermediate vector.
+    pub fn levels_iter(&self, side: Side) -> impl Iterator<Item=(f64, f64, f64)> + '_ {
+        self.side_iter(&side).scan(0.0, |cumsum, node| {
+            *cumsum += node.key * node.value.total_size();
+            Some((node.key, node.value.total_size(), *cumsum))
+        })
+    }
+
+    /// Lazily iterate over (price, aggregate size) pairs in book order, straight off the
+    /// tree without collecting. Sizes include hidden iceberg size, matching levels().
+    pub fn l2_iter(&self, side: Side) -> impl Iterator<Item=(f64, f64)> + '_ {
+        self.side_iter(&side).map(|node| (node.key, node.value.total_size()))
+    }
+
+    /// Compare the top of book against the last observed one and, if it changed,
+    /// update the statistics that are sampled on top-of-book changes
+    fn refresh_top_of_book(&mut self) {
+        let top_of_book = (self.best_bid(), self.best_ask());
+        if top_of_book == self.top_of_book {
+            return
+        }
+        self.top_of_book = top_of_book;
+        if let (Some(best_bid), Some(best_ask)) = top_of_book {
+            if best_ask > best_bid {
+                self.spread_stats.observe(best_ask - best_bid);
+            }
+        }
+        if let Some(imbalance) = self.imbalance(self.pressure_depth) {
+            self.pressure_ema = Some(match self.pressure_ema {
+                Some(ema) => ema + self.pressure_ema_alpha * (imbalance - ema),
+                None => imbalance,
+            });
+        }
+    }
+
+    /// Return the side of the book a parsed query will modify, if any
+    fn touched_side(&self, action: &SubmitRust) -> Option<Side> {
+        match action {
+            SubmitRust::Insert { order } => Some(order.side.clone()),
+            SubmitRust::Remove { uid, .. }
+            | SubmitRust::Update { uid, .. }
+            | SubmitRust::Fill { uid, .. }
+            | SubmitRust::Move { uid, .. } => {
+                self.order_map.get(uid).map(|(side, _)| side.clone())
+            },
+        }
+    }
+
+    /// Set a channel that receives a BookEvent for every processed update. Pass None to unset.
+    pub fn set_event_sink(&mut self, sink: Option<Sender<BookEvent>>) {
+        self.event_sink = sink;
+    }
+
+    /// Send an event to the event sink, if one is set. Send errors are ignored.
+    fn emit(&self, event: BookEvent) {
+        if let Some(sink) = &self.event_sink {
+            let _ = sink.send(event);
+        }
+    }
+
+    /// Return a copy of the order a parsed query refers to, as it is before processing.
+    /// Only captured when an event sink is set.
+    fn event_subject(&self, action: &SubmitRust) -> Option<Order> {
+        self.event_sink.as_ref()?;
+        match action {
+            SubmitRust::Insert { order } => Some(order.clone()),
+            SubmitRust::Remove { uid, .. }
+            | SubmitRust::Update { uid, .. }
+            | SubmitRust::Fill { uid, .. }
+            | SubmitRust::Move { uid, .. } => self.get_order(uid.clone()).cloned(),
+        }
+    }
+
+    /// Get reference to an order in the limit orderbook by its order_uid.
+    /// Returns None if the order_map entry points at a missing level or order.
+    pub fn get_order(&self, order_uid: String) -> Option<&Order> {
+        let (side, key) = self.order_map.get(&*order_uid)?;
+        let order_stack = match side {
+            Side::Bids => self.bids.get(key)?,
+            Side::Asks => self.asks.get(key)?,
+        };
+        order_stack.get_order(order_uid)
+    }
+
+    /// Get mutable reference to an order in the limit orderbook by its order_uid.
+    /// Returns None if the order_map entry points at a missing level or order.
+    fn get_order_mut(&mut self, order_uid: String) -> Option<&mut Order> {
+        let (side, key) = self.order_map.get(&*order_uid)?;
+        let order_stack = match side {
+            Side::Bids => self.bids.get_mut(key)?,
+            Side::Asks => self.asks.get_mut(key)?,
+        };
+        order_stack.get_order_mut(order_uid)
+    }
+
+    /// Drop an order_map entry whose order is missing from the trees, recording the inconsistency
+    fn drop_stale_entry(&mut self, order_uid: &str) {
+        if let Some((side, key)) = self.order_map.remove(order_uid) {
+            let msg = format!("Order {} not found in {:?} level {}, dropped stale order_map entry",
+                              order_uid, side, key);
+            println!("Warning: {}", msg);
+            self.error_msgs.insert(msg);
+        }
+    }
+
+    /// Check if order meets outlier condition.
+    /// If it doesn't, update bid/ask cutoffs.
+    fn handle_outlier(&mut self, order: &Order) -> bool {
+        // best_bid or best_ask being none means tree is empty, so no way to determine if
+        // order has outlier price. Assume it isn't
+        match order.side {
+            Side::Bids => {
+                if !self.has_bids() {
+                    self.bid_cutoff = self.bid_cutoff_from(order.price);
+                    false
+                } else if order.price > self.best_bid().unwrap() {
+                    self.bid_cutoff = self.bid_cutoff_from(order.price);
+                    false
+                } else if order.price > self.bid_cutoff {
+                    false
+                } else {
+                    true
+                }
+            },
+            Side::Asks => {
+                if !self.has_asks() {
+                    self.ask_cutoff = self.ask_cutoff_from(order.price);
+                    false
+                } else if order.price < self.best_ask().unwrap() {
+                    self.ask_cutoff = self.ask_cutoff_from(order.price);
+                    false
+                } else if order.price < self.ask_cutoff {
+                    false
+                } else {
+                    true
+                }
+            }
+        }
+    }
+
+    /// Inserts an order. Returns true if inserted
+    fn insert(&mut self, order: Order) -> bool {
+        if let Some(max_size) = self.max_order_size {
+            // NaN compares false against the cap, so non-finite sizes are rejected explicitly
+            if !order.size.is_finite() || order.size > max_size {
+                self.rejected += 1;
+                self.error_msgs.insert(format!(
+                    "Rejected order {}: size {} exceeds max order size {}", order.uid, order.size, max_size
+                ));
+                return false
+            }
+        }
+        if !self.handle_outlier(&order) {
+            self.place(order);
+            true
+        } else {
+            self.outliers += 1;
+            false
+        }
+    }
+
+    /// Places an order at the back of its price level's stack, without outlier checks.
+    ///
+    /// The order is recorded in order_map before it enters the tree, and the entry is rolled
+    /// back if the tree insert panics, so the trees, order_map and len still agree if a caller
+    /// catches the unwind.
+    fn place(&mut self, order: Order) {
+        self.invalidate_levels();
+        let (side, price, uid) = (order.side.clone(), order.price, order.uid.clone());
+        let previous = self.order_map.insert(uid.clone(), (side.clone(), price));
+        let placed = panic::catch_unwind(AssertUnwindSafe(|| {
+            match side {
+                Side::Bids => self.bids.insert(price, Some(order)),
+                Side::Asks => self.asks.insert(price, Some(order)),
+            };
+        }));
+        if let Err(payload) = placed {
+            match previous {
+                Some(entry) => self.order_map.insert(uid, entry),
+                None => self.order_map.remove(&uid),
+            };
+            panic::resume_unwind(payload);
+        }
+        self.len += 1;
+        self.empty_since.remove(&(side.clone(), price.to_bits()));
+        self.trim_depth(&side);
+    }
+
+    /// Evict the worst levels of a side until it's within max_depth_per_side, or a single
+    /// level in bbo_only mode. Only live levels count towards the depth; lingering empty
+    /// levels are left to compact().
+    fn trim_depth(&mut self, side: &Side) {
+        let max_depth = match (self.bbo_only, self.max_depth_per_side) {
+            (true, _) => 1,
+            (false, Some(max_depth)) => max_depth,
+            (false, None) => return,
+        };
+        let tree = match side {
+            Side::Bids => &self.bids,
+            Side::Asks => &self.asks,
+        };
+        let lingering = self.empty_since.keys().filter(|(level_side, _)| level_side == side).count();
+        let mut live = tree.len() - lingering;
+        while live > max_depth {
+            let worst = match side {
+                Side::Bids => self.bids.iter().find(|node| !node.value.is_empty()),
+                Side::Asks => self.asks.iter().rev().find(|node| !node.value.is_empty()),
+            };
+            let (price, uids): (f64, Vec<String>) = {
+                let node = worst.unwrap();
+                (node.key, node.value.0.iter().map(|order| order.uid.clone()).collect())
+            };
+            for uid in uids {
+                self.remove(uid);
+            }
+            // an evicted level may linger; drop it outright
+            if self.empty_since.remove(&(side.clone(), price.to_bits())).is_some() {
+                match side {
+                    Side::Bids => self.bids.remove(&price),
+                    Side::Asks => self.asks.remove(&price),
+                };
+            }
+            live -= 1;
+        }
+    }
+
+    /// Moves an order to a new price on the same side, keeping its size.
+    /// The order joins the back of the destination level's stack.
+    fn move_order(&mut self, order_uid: String, new_price: f64) {
+        if let Some(order) = self.get_order(order_uid.clone()) {
+            let mut order = order.clone();
+            self.remove(order_uid);
+            order.price = new_price;
+            self.place(order);
+        }
+    }
+
+    /// Removes an order. An order_map entry without a matching order in the trees
+    /// is dropped and recorded in error_msgs.
+    fn remove(&mut self, order_uid: String) {
+        self.invalidate_levels();
+        let (side, key) = match self.order_map.get(&*order_uid) {
+            Some((side, key)) => (side.clone(), *key),
+            None => return,
+        };
+        let tree = match side {
+            Side::Bids => &mut self.bids,
+            Side::Asks => &mut self.asks,
+        };
+        let removed = match tree.get_mut(&key) {
+            Some(order_stack) => {
+                let removed = order_stack.remove(order_uid.clone()).is_some();
+                if order_stack.is_empty() {
+                    if self.linger_empty_levels {
+                        self.empty_since.insert((side, key.to_bits()), self.generation);
+                    } else {
+                        tree.remove(&key); // todo: make a method to remove nodes by reference
+                    }
+                }
+                removed
+            },
+            None => false,
+        };
+        if removed {
+            self.len -= 1;
+            self.order_map.remove(&*order_uid);
+        } else {
+            self.drop_stale_entry(&order_uid);
+        }
+    }
+
+    /// Updates an order, stamping it with the update's timestamp if one is given. An
+    /// order_map entry without a matching order in the trees is dropped and recorded in error_msgs.
+    fn update(&mut self, order_uid: String, new_size: f64, timestamp: Option<String>) {
+        self.invalidate_levels();
+        match self.get_order_mut(order_uid.clone()) {
+            Some(order) => {
+                if new_size == 0.0 {
+                    self.remove(order_uid)
+                } else {
+                    order.size = new_size;
+                    if timestamp.is_some() {
+                        order.last_update = timestamp;
+                    }
+                }
+            },
+            None => self.drop_stale_entry(&order_uid),
+        }
+    }
+
+    /// Reduces an order's size by a matched quantity, removing the order once fully filled.
+    /// Fills larger than the remaining size are clamped to zero.
+    fn fill(&mut self, order_uid: String, fill_qty: f64, timestamp: String) {
+        self.invalidate_levels();
+        if let Some(order) = self.get_order_mut(order_uid.clone()) {
+            let trade = (timestamp, order.side.clone(), order.price, fill_qty.min(order.size));
+            let remaining = order.size - fill_qty;
+            if remaining < 0.0 {
+                println!("Warning: fill of {} exceeds remaining size {} of order {}. Clamping to zero.",
+                         fill_qty, order.size, order_uid);
+            }
+            if remaining <= 0.0 {
+                self.remove(order_uid)
+            } else {
+                order.size = remaining;
+                order.last_update = Some(trade.0.clone());
+            }
+            self.record_trade(trade);
+        }
+    }
+
+    /// Append a trade to the trade tape, evicting the oldest trades beyond its capacity
+    fn record_trade(&mut self, trade: (String, Side, f64, f64)) {
+        self.trade_tape.push_back(trade);
+        while self.trade_tape.len() > self.trade_tape_capacity {
+            self.trade_tape.pop_front();
+        }
+    }
+
+    /// Append a mid-price sample to the history, evicting the oldest samples beyond MID_HISTORY_CAPACITY
+    fn record_mid(&mut self, sample: (DateTime<Utc>, f64)) {
+        self.mid_history.push_back(sample);
+        while self.mid_history.len() > MID_HISTORY_CAPACITY {
+            self.mid_history.pop_front();
+        }
+    }
+
+    /// Append an imbalance sample to the history, evicting the oldest samples beyond its capacity
+    fn record_imbalance(&mut self, sample: (String, f64)) {
+        self.imbalance_history.push_back(sample);
+        while self.imbalance_history.len() > self.imbalance_history_capacity {
+            self.imbalance_history.pop_front();
+        }
+    }
+
+    /// Auxiliary method for displaying trees
+    fn display_trees_aux(&self, side: Side, print: bool) -> Vec<String> {
+        let mut tree_vector: Vec<String> = Vec::new();
+        match side {
+            Side::Bids => {
+                tree_vector.push("\nBids: ".to_string());
+                tree_vector.extend(self.bids.display())
+            },
+            Side::Asks => {
+                tree_vector.push("\nAsks: ".to_string());
+                tree_vector.extend(self.asks.display())
+            }
+        }
+        if print {
+            for line in &tree_vector {
+                println!("{}", line);
+            }
+        }
+        tree_vector
+    }
+
+    /// Iterate over every order in the orderbook.
+    ///
+    /// Does this by iterating over orders in each OrderStack, choosing OrderStacks
+    /// using In-order traversal on first the bid tree and then the ask tree.
+    fn iter(&self) -> Iter {
+        let mut iter = Iter {
+            side: Side::Bids,
+            current_node: None,
+            bid_tree_iter: self.bids.iter().peekable(),
+            ask_tree_iter: self.asks.iter().peekable(),
+            stack_iter: None,
+        };
+
+        if iter.bid_tree_iter.peek().is_some() {
+            iter.current_node = iter.bid_tree_iter.next();
+        } else if iter.ask_tree_iter.peek().is_some() {
+            iter.current_node = iter.ask_tree_iter.next();
+        };
+
+        if let Some(node) = iter.current_node {
+            let order_stack = &node.value;
+            iter.stack_iter = Some(order_stack.0.iter());
+        }
+
+        iter
+    }
+}
+
+pub struct Iter<'a> {
+    side: Side,
+    // current_node: Option<(&'a f64, &'a OrderStack)>,
+    current_node: Option<&'a Node<f64, OrderStack>>,
+    bid_tree_iter: Peekable<avl_tree::Iter<'a, f64, OrderStack, Order>>,
+    ask_tree_iter: Peekable<avl_tree::Iter<'a, f64, OrderStack, Order>>,
+    stack_iter: Option<std::collections::vec_deque::Iter<'a, Order>>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Order;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut result;
+        loop {
+            match &self.side {
+                Side::Bids => {
+                    if let Some(_node) = self.current_node {
+                        // let key = &node.key;
+                        // let order_stack = &node.value;
+                        // println!("\ncurrent bid node is {}, with stack length {}", key, stack.len());
+                        result = self.stack_iter.as_mut().unwrap().next();
+                        if result.is_some() {
+                            // println!("result = {:?}", result);
+                            break
+                        } else {
+                            // println!("empty result. iterating to next node");
+                            self.current_node = self.bid_tree_iter.next();
+                            self.stack_iter = match self.current_node {
+                                Some(node) => {
+                                    Some(node.value.0.iter())
+                                },
+                                None => None,
+                            };
+                            continue
+                        }
+                    } else {
+                        // println!("current_node is None. Moving to asks tree...");
+                        self.side = Side::Asks;
+                        self.current_node = self.ask_tree_iter.next();
+                        if let Some(node) = self.current_node {
+                            self.stack_iter = Some(node.value.0.iter());
+                        }
+                    }
+                }
+
+                Side::Asks => {
+                    if let Some(_node) = self.current_node {
+                        // let key = &node.key;
+                        // let order_stack = &node.value;
+                        // println!("\ncurrent ask node is {}, with stack length {}", key, stack.len());
+                        // println!("iterating through stack_iter");
+                        result = self.stack_iter.as_mut().unwrap().next();
+                        if result.is_some() {
+                            // println!("result = {:?}", result);
+                            break
+                        } else {
+                            // println!("empty result. iterating to next node");
+                            self.current_node = self.ask_tree_iter.next();
+                            self.stack_iter = match self.current_node {
+                                Some(node) => {
+                                    Some(node.value.0.iter())
+                                },
+                                None => None,
+                            };
+                            continue
+                        }
+                    } else {
+                        // println!("current_node is None. Iteration complete.");
+                        result = None;
+                        break
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+impl SpreadStats {
+    fn new() -> Self {
+        SpreadStats { count: 0, sum: 0.0, min: f64::INFINITY, max: f64::NEG_INFINITY }
+    }
+
+    fn observe(&mut self, spread: f64) {
+        self.count += 1;
+        self.sum += spread;
+        self.min = self.min.min(spread);
+        self.max = self.max.max(spread);
+    }
+
+    /// Return (min, mean, max), or None if nothing has been observed
+    fn summary(&self) -> Option<(f64, f64, f64)> {
+        if self.count == 0 { return None }
+        Some((self.min, self.sum / self.count as f64, self.max))
+    }
+}
+
+impl OrderStack {
+    /// Create new order stack instance
+    pub fn new() -> Self {
+        OrderStack( VecDeque::new() )
+    }
+
+    /// Return true if orderstack is empty
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Return immutable reference to an order by its order uid
+    pub fn get_order(&self, order_uid: String) -> Option<&Order> {
+        self.0.iter().find(|order| order.uid == order_uid)
+    }
+
+    /// Return mutable reference to an order by its order uid
+    pub fn get_order_mut(&mut self, order_uid: String) -> Option<&mut Order> {
+        self.0.iter_mut().find(|order| order.uid == order_uid)
+    }
+
+    /// Push order to the back of the stack
+    pub fn push_back(&mut self, order: Order) {
+        self.0.push_back(order);
+    }
+
+    /// Pop order from the front of the stack
+    pub fn pop_front(&mut self) -> Option<Order> {
+        self.0.pop_front()
+    }
+
+    /// Remove order from the stack, by uid
+    pub fn remove(&mut self, order_uid: String) -> Option<Order> {
+        let index = self.0.iter().position(|order| order.uid == order_uid)?;
+        self.0.remove(index)
+    }
+
+    /// Return cumulative visible order size
+    pub fn size(&self) -> f64 {
+        self.visible_size()
+    }
+
+    /// Return cumulative visible order size, excluding hidden size
+    pub fn visible_size(&self) -> f64 {
+        self.0.iter().fold(0.0, |sum, order| sum + order.size)
+    }
+
+    /// Return cumulative order size, including hidden size
+    pub fn total_size(&self) -> f64 {
+        self.0.iter().fold(0.0, |sum, order| sum + order.size + order.hidden_size)
+    }
+
+    /// Return order stack's size
+    pub fn len(&self) -> usize { self.0.len() }
+
+}
+
+impl Collection for OrderStack { type Item = Order; }
+
+impl PushBack for OrderStack {
+    type Output = ();
+    fn push_back(&mut self, element: Self::Item) -> Self::Output {
+        type Item = Order;
+        type Output = ();
+        self.push_back(element)
+    }
+}
+
+impl New for OrderStack {
+    fn new() -> Self { OrderStack::new() }
+}
+
+impl Len for OrderStack {
+    fn len(&self) -> usize { self.len() }
+}
+
+#[pymethods]
+impl Order {
+    /// Create order struct out of limit order parameters
+    #[new]
     pub fn new(uid: String, side: Option<Side>, price: Option<f64>,
                 size: Option<f64>, timestamp: String) -> Order {
         Order {
-            uid,
-            side: side.unwrap_or(Default::default()),
-            price: price.unwrap_or(0.0),
-            size: size.unwrap_or(0.0),
-            timestamp
+            uid,
+            side: side.unwrap_or(Default::default()),
+            price: price.unwrap_or(0.0),
+            size: size.unwrap_or(0.0),
+            hidden_size: 0.0,
+            reason: None,
+            meta: None,
+            last_update: None,
+            parsed_time: ParsedTime::default(),
+            timestamp
+        }
+    }
+
+    /// Create order struct with a uid that's unique within the process run
+    #[staticmethod]
+    pub fn with_auto_uid(side: Side, price: f64, size: f64, timestamp: String) -> Order {
+        let seq = AUTO_UID_SEQ.fetch_add(1, Ordering::Relaxed);
+        Order {
+            uid: format!("auto-{}", seq), side, price, size, timestamp, hidden_size: 0.0, reason: None, meta: None,
+            last_update: None, parsed_time: ParsedTime::default(),
+        }
+    }
+
+    #[getter(timestamp)]
+    /// Return the order's timestamp as received, e.g. 2022-09-27T19:31:00.123456Z
+    pub fn timestamp(&self) -> &str {
+        &self.timestamp
+    }
+
+    #[setter(timestamp)]
+    /// Replace the order's timestamp, resetting its cached time
+    pub fn set_timestamp(&mut self, timestamp: String) {
+        self.timestamp = timestamp;
+        self.parsed_time = ParsedTime::default();
+    }
+
+    pub fn __str__(&self) -> PyResult<String> {
+        Ok(format!(
+            "Order(uid={}, side={:?}, price={}, size={}, timestamp={})",
+            self.uid, self.side, self.price, self.size, self.timestamp
+        ))
+    }
+}
+
+#[pymethods]
+impl Side {
+    /// Return the other side of the book
+    pub fn opposite(&self) -> Side {
+        match self {
+            Side::Bids => Side::Asks,
+            Side::Asks => Side::Bids,
+        }
+    }
+}
+
+impl Default for Side {
+    fn default() -> Self {
+        Side::Bids
+    }
+}
+
+impl Default for Order {
+    fn default() -> Self {
+        Order {
+            uid: "default_uid".to_string(),
+            side: Default::default(),
+            price: 0.0,
+            size: 0.0,
+            timestamp: "default timestamp".to_string(),
+            hidden_size: 0.0,
+            reason: None,
+            meta: None,
+            last_update: None,
+            parsed_time: ParsedTime::default(),
+        }
+    }
+}
+
+impl Order {
+    /// Return the order's timestamp as a DateTime, or None if it isn't a valid
+    /// Coinbase timestamp. It's parsed once and cached until set_timestamp.
+    pub fn time(&self) -> Option<DateTime<Utc>> {
+        *self.parsed_time.0.get_or_init(|| parse_timestamp(&self.timestamp))
+    }
+
+    /// Return when the order last changed: its latest update or fill if that timestamp
+    /// parses, else its own timestamp
+    pub fn last_update_time(&self) -> Option<DateTime<Utc>> {
+        self.last_update.as_deref().and_then(parse_timestamp).or_else(|| self.time())
+    }
+}
+
+/// CRC-32 (IEEE 802.3, as used by zlib) of a byte string
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// 64-bit FNV-1a hash
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+fn is_zero(value: &f64) -> bool {
+    *value == 0.0
+}
+
+/// Parse a Coinbase ISO-8601 timestamp, e.g. 2022-09-27T19:31:00.123456Z
+fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    let naive = NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%dT%H:%M:%S%.fZ").ok()?;
+    Some(Utc.from_utc_datetime(&naive))
+}
+
+/// Seeded generators for reproducing orderbook bugs in tests
+#[cfg(test)]
+pub mod test_utils {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+    use super::*;
+
+    /// Generate a deterministic sequence of n operations from a seed: inserts, plus updates
+    /// and removes of previously inserted orders that are still live. Bids are priced below
+    /// 100 and asks above, so no insert is rejected as an outlier or crosses the book.
+    ///
+    /// The same seed always yields the same sequence, so a failing seed reported by a
+    /// property test can be replayed exactly by passing it back in.
+    pub fn random_ops(seed: u64, n: usize) -> Vec<(Order, Submit)> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut live: Vec<Order> = Vec::new();
+        let mut ops: Vec<(Order, Submit)> = Vec::with_capacity(n);
+        for k in 0..n {
+            let roll: f64 = rng.gen();
+            if live.is_empty() || roll < 0.5 {
+                let side = if rng.gen_bool(0.5) { Side::Bids } else { Side::Asks };
+                let price = match side {
+                    Side::Bids => rng.gen_range(5000..10000) as f64 / 100.0,
+                    Side::Asks => rng.gen_range(10001..15000) as f64 / 100.0,
+                };
+                let size = rng.gen_range(1..10000) as f64 / 100.0;
+                let order = Order::new(k.to_string(), Some(side), Some(price), Some(size), "dummy_datetime".to_string());
+                live.push(order.clone());
+                ops.push((order, Submit::Insert));
+            } else if roll < 0.75 {
+                let index = rng.gen_range(0..live.len());
+                live[index].size = rng.gen_range(1..10000) as f64 / 100.0;
+                ops.push((live[index].clone(), Submit::Update));
+            } else {
+                let index = rng.gen_range(0..live.len());
+                ops.push((live.swap_remove(index), Submit::Remove));
+            }
+        }
+        ops
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, seq::SliceRandom};
+    use super::*;
+
+    fn generate_random_orders(size: usize) -> Vec<Order> {
+        let mut rng = rand::thread_rng();
+
+        let prices: Vec<f64> = (0..size)
+            .into_iter().map(|_k| rng.gen_range(0..10000) as f64 / 100.0).collect();
+        let sizes: Vec<f64> = (0..size)
+            .into_iter().map(|_k| rng.gen_range(0..10000) as f64 / 100.0).collect();
+
+        let vector: Vec<Order>
+            = (0..size).into_iter().map(|k| {
+            Order::new(
+                k.to_string(),
+                if rng.gen_bool(0.5) { Some(Side::Bids) } else { Some(Side::Asks) },
+                Some(prices.choose(&mut rng).unwrap().clone()),
+                Some(sizes.choose(&mut rng).unwrap().clone()),
+                "dummy_datetime".to_string(),
+            )
+        }).collect();
+        vector
+    }
+
+    /// Build an order with a placeholder timestamp
+    fn order<U: ToString>(uid: U, side: Side, price: f64, size: f64) -> Order {
+        Order::new(uid.to_string(), Some(side), Some(price), Some(size), "dummy_datetime".to_string())
+    }
+
+    /// Build an order with the given timestamp
+    fn order_at<U: ToString>(uid: U, side: Side, price: f64, size: f64, timestamp: &str) -> Order {
+        Order::new(uid.to_string(), Some(side), Some(price), Some(size), timestamp.to_string())
+    }
+
+    /// Coinbase timestamp for the given second of a fixed minute
+    fn timestamp(second: u32) -> String {
+        format!("2022-09-27T19:31:{:02}.000000Z", second)
+    }
+
+    fn deserialize_orders_from_json(json_orders: Vec<&str>) -> Vec<Order> {
+        let deserialized_orders: Vec<Order> = json_orders
+            .iter()
+            .map(|json_order| serde_json::from_str(&json_order).unwrap())
+            .collect();
+        deserialized_orders
+    }
+
+    fn outlier_check(order: &Order, factor: f64, top_level: (Option<f64>, Option<f64>)) -> (bool, (Option<f64>, Option<f64>)) {
+        match order.side {
+            Side::Bids => {
+                if let Some(best_bid) = top_level.0 {
+                    if order.price < best_bid / factor {
+                        (true, (Some(best_bid), top_level.1))
+                    } else if order.price > best_bid {
+                        (false, (Some(order.price), top_level.1))
+                    } else {
+                        (false, (Some(best_bid), top_level.1))
+                    }
+                } else {
+                    (false, (Some(order.price), top_level.1))
+                }
+            },
+            Side::Asks => {
+                if let Some(best_ask) = top_level.1 {
+                    if order.price > best_ask * factor {
+                        (true, (top_level.0, Some(best_ask)))
+                    } else if order.price < best_ask {
+                        (false, (top_level.0, Some(order.price)))
+                    } else {
+                        (false, (top_level.0, Some(best_ask)))
+                    }
+                } else {
+                    (false, (top_level.0, Some(order.price)))
+                }
+            },
+        }
+    }
+
+    #[test]
+    fn empty_book() {
+        let mut lob = LimitOrderbook::new();
+        let orders = generate_random_orders(2);
+        assert_eq!(lob.best_ask(), None);
+        assert_eq!(lob.best_bid(), None);
+        assert_eq!(lob.len(), 0);
+        assert_eq!(lob.node_count(), 0);
+        assert_eq!(lob.levels(Side::Bids, None), vec![]);
+        assert_eq!(lob.levels(Side::Asks, None), vec![]);
+        // assert_eq!(lob.liquidity(Side::Bids), vec![]);
+        // assert_eq!(lob.liquidity(Side::Asks), vec![]);
+        assert_eq!(lob.items_processed(), 0);
+        lob.check();
+        assert_eq!(lob.error_msgs(), HashSet::new());
+        lob.process(orders[0].clone(), Submit::Remove).unwrap();
+        lob.process(orders[1].clone(), Submit::Update).unwrap();
+        assert_eq!(lob.len(), 0);
+        println!("Orderbook timestamp: {}", lob.timestamp);
+    }
+
+    #[test]
+    fn basics() {
+        println!("\n-----Testing LOB Order Insertion-----\n");
+        let mut orderbook: LimitOrderbook = LimitOrderbook::new();
+
+
+        let orders = generate_random_orders(20);
+        println!("\nInserting randomly-generated orders into orderbook.\n");
+
+        // let json_orders = vec![
+        //     "{\"uid\":\"0\",\"side\":{\"Asks\":1},\"price\":76.28,\"size\":99.35,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"1\",\"side\":{\"Bids\":0},\"price\":95.55,\"size\":87.12,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"2\",\"side\":{\"Asks\":1},\"price\":53.88,\"size\":30.54,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"3\",\"side\":{\"Asks\":1},\"price\":76.28,\"size\":87.12,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"4\",\"side\":{\"Bids\":0},\"price\":57.0,\"size\":64.08,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"5\",\"side\":{\"Bids\":0},\"price\":44.12,\"size\":60.9,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"6\",\"side\":{\"Asks\":1},\"price\":94.42,\"size\":99.35,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"7\",\"side\":{\"Bids\":0},\"price\":98.79,\"size\":46.21,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"8\",\"side\":{\"Bids\":0},\"price\":7.73,\"size\":28.57,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"9\",\"side\":{\"Asks\":1},\"price\":57.0,\"size\":96.04,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"10\",\"side\":{\"Bids\":0},\"price\":44.12,\"size\":34.79,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"11\",\"side\":{\"Asks\":1},\"price\":47.36,\"size\":43.91,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"12\",\"side\":{\"Asks\":1},\"price\":47.36,\"size\":28.21,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"13\",\"side\":{\"Bids\":0},\"price\":16.8,\"size\":96.04,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"14\",\"side\":{\"Bids\":0},\"price\":7.73,\"size\":34.79,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"15\",\"side\":{\"Asks\":1},\"price\":7.73,\"size\":96.04,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"16\",\"side\":{\"Asks\":1},\"price\":7.73,\"size\":99.35,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"17\",\"side\":{\"Bids\":0},\"price\":60.76,\"size\":37.54,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"18\",\"side\":{\"Bids\":0},\"price\":57.0,\"size\":85.97,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"19\",\"side\":{\"Bids\":0},\"price\":47.36,\"size\":99.05,\"timestamp\":\"dummy_datetime\"}",
+        // ];
+        // let orders = deserialize_orders_from_json(json_orders);
+        // println!("Deserializing orders and inserting into orderbook.");
+
+        // reduce generated orders simulating orderbook's outlier-checking behavior
+        let mut outlier_n_best = (false, (None, None));
+        let mut orders_adj: Vec<Order> = Vec::new();
+        for order in &orders {
+            // let serialized_order = serde_json::to_string(&order).unwrap();
+            // println!("{:?},", serialized_order);
+            // println!("{:?}", order);
+            let inserted = orderbook.insert(order.clone());
+            outlier_n_best = outlier_check(&order, 2.0, outlier_n_best.1);
+            assert_eq!(inserted, !outlier_n_best.0);
+            if !outlier_n_best.0 {
+                orders_adj.push(order.clone());
+            }
+        }
+        let orders = orders_adj;
+
+        assert_eq!(orderbook.len(), orders.len());
+        orderbook.display_trees_aux(Side::Bids, true);
+        println!("Best bid: {:?}", orderbook.best_bid());
+        println!("Buy-side levels: {:?}", orderbook.levels(Side::Bids, None));
+        // println!("Buy-side cumulative order depth: {:?}", orderbook.liquidity(Side::Bids));
+        orderbook.display_trees_aux(Side::Asks, true);
+        println!("Best ask: {:?}", orderbook.best_ask());
+        println!("Sell-side levels {:?}", orderbook.levels(Side::Asks, None));
+        // println!("Sell-side cumulative order depth: {:?}", orderbook.liquidity(Side::Asks));
+
+        // test updates
+        let mut rng = rand::thread_rng();
+        let updated_order = orders.choose(&mut rng).unwrap();
+        let new_size = 1000.0;
+        println!("\n------------Updating order {} with new order size {}------------\n", updated_order.uid, new_size);
+        orderbook.update(updated_order.uid.clone(), new_size, None);
+        let updated_order = orderbook.get_order(updated_order.uid.clone()).unwrap().clone();
+        println!("{:?}", updated_order);
+        assert_eq!(updated_order.size, 1000.0);
+        orderbook.display_trees_aux(Side::Bids, true);
+        orderbook.display_trees_aux(Side::Asks, true);
+        assert_eq!(orderbook.len(), orders.len());
+
+
+        let deleted: Vec<&Order> = orders.choose_multiple(&mut rng, 5).collect();
+        let deleted_uids: Vec<String> = deleted.iter().map(|order| order.uid.clone()).collect();
+        println!("\nDeleting orders {:?}", deleted_uids);
+        for i in 0..deleted.len() {
+            let serialized_order = serde_json::to_string(&deleted[i]).unwrap();
+            println!("{:?}", serialized_order);
+            orderbook.remove(deleted_uids[i].clone());
+        }
+
+        // let delete_orders = vec![
+        //     "{\"uid\":\"13\",\"side\":{\"Bids\":0},\"price\":16.8,\"size\":96.04,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"9\",\"side\":{\"Asks\":1},\"price\":57.0,\"size\":96.04,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"4\",\"side\":{\"Bids\":0},\"price\":57.0,\"size\":64.08,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"12\",\"side\":{\"Asks\":1},\"price\":47.36,\"size\":28.21,\"timestamp\":\"dummy_datetime\"}",
+        //     "{\"uid\":\"0\",\"side\":{\"Asks\":1},\"price\":76.28,\"size\":99.35,\"timestamp\":\"dummy_datetime\"}",
+        // ];
+        // let deleted = deserialize_orders_from_json(delete_orders);
+        // let deleted_uids: Vec<String> = deleted.iter().map(|order| order.uid.clone()).collect();
+        // println!("\n-----------------Deleting orders {:?}-----------------\n", deleted_uids);
+        // for order in &deleted {
+        //     orderbook.remove(order.uid.clone());
+        // }
+
+
+        orderbook.display_trees_aux(Side::Bids, true);
+        orderbook.display_trees_aux(Side::Asks, true);
+        assert_eq!(orderbook.len(), orders.len() - deleted.len());
+
+        // create vector of expected orders, accounting for updated order and deletions
+        let remaining: Vec<&Order> = orders
+            .iter()
+            .filter(|&order| !deleted_uids.contains(&order.uid))
+            .map(|order| {if *order.uid == updated_order.uid { &updated_order} else { order } })
+            .collect();
+        println!("\nExpected Remaining orders:\n");
+        for order in &remaining {
+            print!("{:?}", order);
+            println!(" Orderbook has this? {}", orderbook.has(order.uid.clone()));
+        }
+
+        println!("\nRemaining orders in LOB:\n");
+        let mut orderbook_iter = orderbook.iter();
+        let mut order_vector: Vec<&Order> = Vec::new();
+        let mut i: usize = 0;
+        while let Some(order) = orderbook_iter.next() {
+            println!("{:?}", order);
+            order_vector.push(order);
+            i += 1;
+            if i > remaining.len() {
+                break;
+            }
+        }
+
+        println!("\nRemaining orders in LOB, sorted by uid:\n");
+        order_vector.sort_by_key(|order| order.uid.clone().parse::<usize>().unwrap());
+        let mut i: usize = 0;
+        for order in &order_vector {
+            println!("{:?}", order);
+            assert_eq!(remaining[i], *order);
+            i += 1;
+        }
+
+    }
+
+    #[test]
+    fn fills() {
+        let mut lob = LimitOrderbook::new();
+        let order = order("0", Side::Asks, 10.0, 5.0);
+        lob.process(order.clone(), Submit::Insert).unwrap();
+
+        // partial fill
+        lob.process(Order { size: 2.0, ..order.clone() }, Submit::Fill).unwrap();
+        assert_eq!(lob.get_order("0".to_string()).unwrap().size, 3.0);
+        assert_eq!(lob.levels(Side::Asks, None), vec![(10.0, 3.0, 30.0)]);
+        assert_eq!(lob.len(), 1);
+
+        // full fill
+        lob.process(Order { size: 3.0, ..order.clone() }, Submit::Fill).unwrap();
+        assert!(!lob.has("0".to_string()));
+        assert_eq!(lob.len(), 0);
+        assert_eq!(lob.node_count(), 0);
+
+        // overfill clamps to zero and removes the order
+        lob.process(order.clone(), Submit::Insert).unwrap();
+        lob.process(Order { size: 7.5, ..order }, Submit::Fill).unwrap();
+        assert!(!lob.has("0".to_string()));
+        assert_eq!(lob.len(), 0);
+    }
+
+    #[test]
+    fn side_update_timestamps() {
+        let mut lob = LimitOrderbook::new();
+        assert_eq!(lob.last_bid_update(), None);
+        assert_eq!(lob.last_ask_update(), None);
+
+        let bid = order_at("0", Side::Bids, 9.0, 1.0, "t0");
+        lob.process(bid.clone(), Submit::Insert).unwrap();
+        assert_eq!(lob.last_bid_update(), Some("t0".to_string()));
+        assert_eq!(lob.last_ask_update(), None);
+
+        let ask = order_at("1", Side::Asks, 11.0, 1.0, "t1");
+        lob.process(ask, Submit::Insert).unwrap();
+        assert_eq!(lob.last_bid_update(), Some("t0".to_string()));
+        assert_eq!(lob.last_ask_update(), Some("t1".to_string()));
+
+        lob.process(order_at("0", Side::Bids, 9.0, 2.0, "t2"), Submit::Update).unwrap();
+        assert_eq!(lob.last_bid_update(), Some("t2".to_string()));
+        assert_eq!(lob.last_ask_update(), Some("t1".to_string()));
+
+        // unknown uids don't touch either side
+        lob.process(Order::new("x".to_string(), None, None, None, "t3".to_string()), Submit::Remove).unwrap();
+        assert_eq!(lob.last_bid_update(), Some("t2".to_string()));
+        assert_eq!(lob.last_ask_update(), Some("t1".to_string()));
+
+        lob.process(order_at("0", Side::Bids, 9.0, 2.0, "t4"), Submit::Remove).unwrap();
+        assert_eq!(lob.last_bid_update(), Some("t4".to_string()));
+        assert_eq!(lob.last_ask_update(), Some("t1".to_string()));
+    }
+
+    #[test]
+    fn depth_to_price() {
+        let mut lob = LimitOrderbook::new();
+        let orders = vec![
+            order("0", Side::Bids, 10.0, 1.0),
+            order("1", Side::Bids, 9.0, 2.0),
+            order("2", Side::Bids, 9.0, 0.5),
+            order("3", Side::Bids, 8.0, 4.0),
+            order("4", Side::Asks, 11.0, 1.0),
+            order("5", Side::Asks, 12.0, 2.0),
+            order("6", Side::Asks, 13.0, 3.0),
+        ];
+        for order in orders {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+
+        // at levels
+        assert_eq!(lob.depth_to_price(Side::Bids, 10.0), 1.0);
+        assert_eq!(lob.depth_to_price(Side::Bids, 9.0), 3.5);
+        assert_eq!(lob.depth_to_price(Side::Asks, 12.0), 3.0);
+        assert_eq!(lob.depth_to_price(Side::Asks, 13.0), 6.0);
+
+        // between levels
+        assert_eq!(lob.depth_to_price(Side::Bids, 8.5), 3.5);
+        assert_eq!(lob.depth_to_price(Side::Asks, 11.5), 1.0);
+
+        // beyond the top of book on the wrong side
+        assert_eq!(lob.depth_to_price(Side::Bids, 10.5), 0.0);
+        assert_eq!(lob.depth_to_price(Side::Asks, 10.5), 0.0);
+
+        // beyond the bottom of the book
+        assert_eq!(lob.depth_to_price(Side::Bids, 1.0), 7.5);
+        assert_eq!(lob.depth_to_price(Side::Asks, 100.0), 6.0);
+    }
+
+    #[test]
+    fn book_events() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut lob = LimitOrderbook::new();
+        lob.set_event_sink(Some(sender));
+
+        let bid = order("0", Side::Bids, 10.0, 2.0);
+        let ask = order("1", Side::Asks, 11.0, 3.0);
+        let outlier = order("2", Side::Asks, 100.0, 1.0);
+        lob.process(bid.clone(), Submit::Insert).unwrap();
+        lob.process(ask.clone(), Submit::Insert).unwrap();
+        lob.process(outlier, Submit::Insert).unwrap();
+        lob.process(Order { size: 1.5, ..bid.clone() }, Submit::Update).unwrap();
+        lob.process(Order { size: 1.0, ..ask.clone() }, Submit::Fill).unwrap();
+        lob.process(bid, Submit::Remove).unwrap();
+        lob.process(Order::new("x".to_string(), None, None, None, "dummy_datetime".to_string()), Submit::Remove).unwrap();
+        drop(lob);
+
+        let events: Vec<BookEvent> = receiver.iter().collect();
+        assert_eq!(events, vec![
+            BookEvent::Inserted { uid: "0".to_string(), side: Side::Bids, price: 10.0, size: 2.0 },
+            BookEvent::Inserted { uid: "1".to_string(), side: Side::Asks, price: 11.0, size: 3.0 },
+            BookEvent::OutlierRejected { uid: "2".to_string(), side: Side::Asks, price: 100.0, size: 1.0 },
+            BookEvent::Updated { uid: "0".to_string(), side: Side::Bids, price: 10.0, size: 1.5 },
+            BookEvent::Updated { uid: "1".to_string(), side: Side::Asks, price: 11.0, size: 2.0 },
+            BookEvent::Removed { uid: "0".to_string(), side: Side::Bids, price: 10.0, size: 1.5 },
+        ]);
+    }
+
+    #[test]
+    fn aggregated_levels() {
+        let mut lob = LimitOrderbook::new();
+        let orders = vec![
+            order("0", Side::Bids, 10.25, 1.0),
+            order("1", Side::Bids, 10.1, 2.0),
+            order("2", Side::Bids, 9.75, 4.0),
+            order("3", Side::Asks, 10.5, 1.0),
+            order("4", Side::Asks, 10.75, 2.0),
+            order("5", Side::Asks, 11.5, 4.0),
+        ];
+        for order in orders {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+
+        assert_eq!(lob.aggregated_levels(Side::Bids, 0.5).unwrap(), vec![(10.5, 3.0), (10.0, 4.0)]);
+        assert_eq!(lob.aggregated_levels(Side::Asks, 0.5).unwrap(), vec![(10.5, 3.0), (11.5, 4.0)]);
+        assert_eq!(lob.aggregated_levels(Side::Asks, 2.0).unwrap(), vec![(10.0, 7.0)]);
+        assert!(lob.aggregated_levels(Side::Bids, 0.0).is_err());
+        assert!(lob.aggregated_levels(Side::Bids, -1.0).is_err());
+    }
+
+    #[test]
+    fn move_order() {
+        let mut lob = LimitOrderbook::new();
+        let orders = vec![
+            order("0", Side::Bids, 10.0, 1.0),
+            order("1", Side::Bids, 10.0, 2.0),
+            order("2", Side::Bids, 9.0, 4.0),
+            order("3", Side::Bids, 8.0, 8.0),
+        ];
+        for order in &orders {
+            lob.process(order.clone(), Submit::Insert).unwrap();
+        }
+
+        // move across levels, joining the back of the destination queue
+        lob.process(Order { price: 9.0, ..orders[0].clone() }, Submit::Move).unwrap();
+        assert_eq!(lob.levels(Side::Bids, None), vec![(10.0, 2.0, 20.0), (9.0, 5.0, 65.0), (8.0, 8.0, 129.0)]);
+        assert_eq!(lob.get_order("0".to_string()).unwrap().price, 9.0);
+        assert_eq!(lob.order_map.get("0"), Some(&(Side::Bids, 9.0)));
+        let stack: Vec<String> = lob.bids.get(&9.0).unwrap().0.iter().map(|order| order.uid.clone()).collect();
+        assert_eq!(stack, vec!["2".to_string(), "0".to_string()]);
+
+        // move to a new level, dropping the emptied source level
+        lob.process(Order { price: 7.0, ..orders[3].clone() }, Submit::Move).unwrap();
+        assert_eq!(lob.levels(Side::Bids, None), vec![(10.0, 2.0, 20.0), (9.0, 5.0, 65.0), (7.0, 8.0, 121.0)]);
+        assert_eq!(lob.len(), 4);
+        assert_eq!(lob.node_count(), 3);
+        lob.check();
+        assert!(lob.error_msgs().is_empty());
+    }
+
+    #[test]
+    fn tree_diagnostics() {
+        let mut lob = LimitOrderbook::new();
+        assert_eq!(lob.bid_tree_height(), 0);
+        assert_eq!(lob.ask_tree_height(), 0);
+        assert!(lob.is_balanced());
+
+        for order in generate_random_orders(1000) {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+        assert!(lob.is_balanced());
+
+        // an AVL tree's height is bounded by roughly 1.44 * log2(n + 2)
+        for (height, nodes) in [(lob.bid_tree_height(), lob.bids.len()), (lob.ask_tree_height(), lob.asks.len())] {
+            let log_n = ((nodes + 2) as f64).log2();
+            println!("height {} for {} nodes", height, nodes);
+            assert!(height as f64 >= log_n.floor() - 1.0);
+            assert!(height as f64 <= 1.45 * log_n);
+        }
+    }
+
+    #[test]
+    fn depth_curve() {
+        let mut lob = LimitOrderbook::new();
+        assert_eq!(lob.depth_curve(vec![10.0, 100.0]), vec![]);
+
+        let orders = vec![
+            order("0", Side::Bids, 99.0, 1.0),
+            order("1", Side::Bids, 98.0, 2.0),
+            order("2", Side::Bids, 98.0, 0.5),
+            order("3", Side::Asks, 101.0, 3.0),
+            order("4", Side::Asks, 102.0, 4.0),
+        ];
+        for order in orders {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+        assert_eq!(lob.mid_price(), Some(100.0));
+        assert_eq!(
+            lob.depth_curve(vec![0.0, 50.0, 150.0, 250.0]),
+            vec![(0.0, 0.0, 0.0), (50.0, 0.0, 0.0), (150.0, 1.0, 3.0), (250.0, 3.5, 7.0)]
+        );
+    }
+
+    #[test]
+    fn snapshot_counters() {
+        let mut lob = LimitOrderbook::new();
+        let orders = vec![
+            order("0", Side::Bids, 10.0, 1.0),
+            order("1", Side::Bids, 10.0, 2.0),
+            order("2", Side::Asks, 11.0, 3.0),
+            order("3", Side::Asks, 100.0, 3.0),
+        ];
+        for order in orders {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+        lob.set_max_order_size(Some(10.0)).unwrap();
+        let done = |uid: &str, reason: &str| Order { reason: Some(reason.to_string()), ..order(uid, Side::Bids, 10.0, 0.0) };
+        lob.process(order("4", Side::Bids, 10.0, 100.0), Submit::Insert).unwrap();
+        lob.process(order("5", Side::Bids, 10.0, 1.0), Submit::Insert).unwrap();
+        lob.process(order("6", Side::Bids, 10.0, 1.0), Submit::Insert).unwrap();
+        lob.process(order("5", Side::Bids, 10.0, 0.5), Submit::Update).unwrap();
+        lob.process(done("5", "filled"), Submit::RemoveWithReason).unwrap();
+        lob.process(done("6", "canceled"), Submit::RemoveWithReason).unwrap();
+        assert_eq!(lob.items_processed(), 10);
+        assert_eq!(lob.outliers(), 1);
+        assert_eq!(lob.rejected(), 1);
+
+        let restored = LimitOrderbook::from_snapshot_json(lob.to_snapshot_json()).unwrap();
+        assert_eq!(restored.items_processed(), 10);
+        assert_eq!(restored.outliers(), 1);
+        assert_eq!(restored.rejected(), 1);
+        assert_eq!((restored.filled_count(), restored.canceled_count()), (1, 1));
+        assert_eq!((restored.total_adds(), restored.total_cancels(), restored.total_updates()), (5, 1, 1));
+        assert_eq!(restored.len(), 3);
+        assert_eq!(restored.levels(Side::Bids, None), lob.levels(Side::Bids, None));
+        assert_eq!(restored.levels(Side::Asks, None), lob.levels(Side::Asks, None));
+        assert_eq!(restored.iter().collect::<Vec<&Order>>(), lob.iter().collect::<Vec<&Order>>());
+        assert_eq!((restored.bid_cutoff, restored.ask_cutoff), (lob.bid_cutoff, lob.ask_cutoff));
+
+        lob.reset_counters();
+        assert_eq!(lob.items_processed(), 0);
+        assert_eq!(lob.outliers(), 0);
+        assert_eq!(lob.len(), 3);
+    }
+
+    #[test]
+    fn trade_tape() {
+        let mut lob = LimitOrderbook::new();
+        lob.set_trade_tape_capacity(3);
+        let bid = order_at("0", Side::Bids, 10.0, 5.0, "t0");
+        let ask = order_at("1", Side::Asks, 11.0, 5.0, "t0");
+        lob.process(bid.clone(), Submit::Insert).unwrap();
+        lob.process(ask.clone(), Submit::Insert).unwrap();
+
+        lob.process(order_at("0", Side::Bids, 10.0, 1.0, "t1"), Submit::Fill).unwrap();
+        lob.process(order_at("1", Side::Asks, 11.0, 2.0, "t2"), Submit::Fill).unwrap();
+        // unknown uids don't trade
+        lob.process(Order::new("x".to_string(), None, None, Some(1.0), "tx".to_string()), Submit::Fill).unwrap();
+        assert_eq!(lob.recent_trades(10), vec![
+            ("t1".to_string(), Side::Bids, 10.0, 1.0),
+            ("t2".to_string(), Side::Asks, 11.0, 2.0),
+        ]);
+
+        // overfills are recorded at the remaining size, and the oldest trade gets evicted
+        lob.process(order_at("0", Side::Bids, 10.0, 0.5, "t3"), Submit::Fill).unwrap();
+        lob.process(order_at("1", Side::Asks, 11.0, 9.0, "t4"), Submit::Fill).unwrap();
+        assert_eq!(lob.recent_trades(10), vec![
+            ("t2".to_string(), Side::Asks, 11.0, 2.0),
+            ("t3".to_string(), Side::Bids, 10.0, 0.5),
+            ("t4".to_string(), Side::Asks, 11.0, 3.0),
+        ]);
+        assert_eq!(lob.recent_trades(1), vec![("t4".to_string(), Side::Asks, 11.0, 3.0)]);
+
+        lob.set_trade_tape_capacity(1);
+        assert_eq!(lob.recent_trades(10), vec![("t4".to_string(), Side::Asks, 11.0, 3.0)]);
+    }
+
+    #[test]
+    fn spread_stats() {
+        let mut lob = LimitOrderbook::new();
+
+        // one-sided book isn't sampled
+        lob.process(order("0", Side::Bids, 10.0, 1.0), Submit::Insert).unwrap();
+        assert_eq!(lob.spread_stats(), None);
+
+        lob.process(order("1", Side::Asks, 14.0, 1.0), Submit::Insert).unwrap();  // spread 4
+        lob.process(order("2", Side::Asks, 12.0, 1.0), Submit::Insert).unwrap();  // spread 2
+        lob.process(order("3", Side::Asks, 13.0, 1.0), Submit::Insert).unwrap();  // deep level, no change
+        lob.process(order("4", Side::Bids, 11.0, 1.0), Submit::Insert).unwrap();  // spread 1
+        // crossed book isn't sampled
+        lob.process(order("5", Side::Bids, 12.5, 1.0), Submit::Insert).unwrap();
+        lob.process(order("5", Side::Bids, 12.5, 1.0), Submit::Remove).unwrap();  // spread 1 again
+        assert_eq!(lob.spread_stats(), Some((1.0, 2.0, 4.0)));
+    }
+
+    #[test]
+    fn queue_ahead() {
+        let mut lob = LimitOrderbook::new();
+        let sizes = [1.0, 2.0, 4.0, 8.0];
+        for (i, size) in sizes.iter().enumerate() {
+            let order = order(i, Side::Asks, 10.0, *size);
+            lob.process(order, Submit::Insert).unwrap();
+        }
+        lob.process(order("other", Side::Asks, 11.0, 16.0), Submit::Insert).unwrap();
+
+        assert_eq!(lob.queue_ahead("0".to_string()), Some(0.0));
+        assert_eq!(lob.queue_ahead("1".to_string()), Some(1.0));
+        assert_eq!(lob.queue_ahead("2".to_string()), Some(3.0));
+        assert_eq!(lob.queue_ahead("3".to_string()), Some(7.0));
+        assert_eq!(lob.queue_ahead("other".to_string()), Some(0.0));
+        assert_eq!(lob.queue_ahead("missing".to_string()), None);
+
+        lob.process(Order::new("1".to_string(), None, None, None, "dummy_datetime".to_string()), Submit::Remove).unwrap();
+        assert_eq!(lob.queue_ahead("3".to_string()), Some(5.0));
+    }
+
+    #[test]
+    fn levels_iter() {
+        let mut lob = LimitOrderbook::new();
+        for order in generate_random_orders(200) {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+        for side in [Side::Bids, Side::Asks] {
+            let streamed: Vec<(f64, f64, f64)> = lob.levels_iter(side.clone()).collect();
+            assert_eq!(streamed, lob.levels(side.clone(), None));
+            assert_eq!(lob.levels_iter(side.clone()).take(3).count(), streamed.len().min(3));
+        }
+    }
+
+    #[test]
+    fn l2_iter() {
+        let mut lob = LimitOrderbook::new();
+        for order in generate_random_orders(200) {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+        for side in [Side::Bids, Side::Asks] {
+            let streamed: Vec<(f64, f64)> = lob.l2_iter(side.clone()).collect();
+            let levels: Vec<(f64, f64)> = lob.levels(side.clone(), None).into_iter()
+                .map(|(price, size, _)| (price, size))
+                .collect();
+            assert_eq!(streamed, levels);
+        }
+    }
+
+    #[test]
+    fn python_protocols() {
+        let mut lob = LimitOrderbook::new();
+        assert_eq!(lob.__len__(), 0);
+        lob.process(order("1", Side::Bids, 9.0, 1.0), Submit::Insert).unwrap();
+        lob.process(order("2", Side::Asks, 11.0, 1.0), Submit::Insert).unwrap();
+        assert_eq!(lob.__len__(), 2);
+        assert!(lob.__contains__("1".to_string()));
+        assert!(!lob.__contains__("3".to_string()));
+        assert_eq!(
+            lob.__repr__().unwrap(),
+            "LimitOrderbook(orders=2, bid_levels=1, ask_levels=1, best_bid=Some(9.0), best_ask=Some(11.0))"
+        );
+    }
+
+    #[test]
+    fn auto_uids() {
+        let uids: HashSet<String> = (0..10_000)
+            .map(|i| Order::with_auto_uid(Side::Bids, i as f64, 1.0, "dummy_datetime".to_string()).uid)
+            .collect();
+        assert_eq!(uids.len(), 10_000);
+    }
+
+    #[test]
+    fn coalesce_levels() {
+        let mut lob = LimitOrderbook::new();
+        let orders = vec![
+            order("0", Side::Bids, 57.0, 1.0),
+            order("1", Side::Bids, 57.0 + 1e-10, 2.0),
+            order("2", Side::Bids, 56.0, 4.0),
+            order("3", Side::Asks, 58.0 - 1e-10, 8.0),
+            order("4", Side::Asks, 58.0, 16.0),
+            order("5", Side::Asks, 58.0 + 1e-10, 32.0),
+        ];
+        for order in orders {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+        assert_eq!(lob.node_count(), 6);
+
+        assert_eq!(lob.coalesce_levels(1e-6).unwrap(), 3);
+        assert_eq!(lob.node_count(), 3);
+        assert_eq!(lob.len(), 6);
+        assert_eq!(lob.levels(Side::Bids, None).iter().map(|l| (l.0, l.1)).collect::<Vec<_>>(), vec![(57.0, 3.0), (56.0, 4.0)]);
+        assert_eq!(lob.levels(Side::Asks, None).iter().map(|l| (l.0, l.1)).collect::<Vec<_>>(), vec![(58.0 - 1e-10, 56.0)]);
+        assert_eq!(lob.order_map.get("5"), Some(&(Side::Asks, 58.0 - 1e-10)));
+        assert_eq!(lob.get_order("1".to_string()).unwrap().price, 57.0);
+
+        // merged orders can still be removed
+        lob.process(Order::new("5".to_string(), None, None, None, "dummy_datetime".to_string()), Submit::Remove).unwrap();
+        assert_eq!(lob.len(), 5);
+        lob.check();
+        assert!(lob.error_msgs().is_empty());
+
+        // levels are measured from the run's lowest price, so a chain 0.9 epsilon apart
+        // splits, and merged orders queue by timestamp
+        let mut lob = LimitOrderbook::new();
+        for (uid, price, second) in [("a", 10.0, 1), ("b", 10.0, 4), ("c", 10.9, 2), ("d", 11.8, 3), ("e", 12.7, 5)] {
+            lob.process(order_at(uid, Side::Asks, price, 1.0, &timestamp(second)), Submit::Insert).unwrap();
+        }
+        assert_eq!(lob.coalesce_levels(1.0).unwrap(), 2);
+        assert_eq!(lob.levels(Side::Asks, None).iter().map(|l| (l.0, l.1)).collect::<Vec<_>>(), vec![(10.0, 3.0), (11.8, 2.0)]);
+        let uids = |price: f64| -> Vec<String> {
+            lob.orders_at_price(Side::Asks, price).iter().map(|order| order.uid.clone()).collect()
+        };
+        assert_eq!(uids(10.0), vec!["a", "c", "b"]);
+        assert_eq!(uids(11.8), vec!["d", "e"]);
+
+        // epsilon must be finite and positive, and a rejected call leaves the book alone
+        for epsilon in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(lob.coalesce_levels(epsilon).is_err());
+        }
+        assert_eq!(lob.node_count(), 2);
+    }
+
+    #[test]
+    fn oldest_orders() {
+        let mut lob = LimitOrderbook::new();
+        let timestamps = [
+            "2022-09-27T19:31:05.000000Z",
+            "2022-09-27T19:30:59.999999Z",
+            "not a timestamp",
+            "2022-09-27T19:31:00.500000Z",
+            "2022-09-26T23:59:59.000000Z",
+        ];
+        for (i, timestamp) in timestamps.iter().enumerate() {
+            let side = if i % 2 == 0 { Side::Bids } else { Side::Asks };
+            let price = if i % 2 == 0 { 10.0 - i as f64 } else { 20.0 + i as f64 };
+            lob.process(order_at(i, side, price, 1.0, timestamp), Submit::Insert).unwrap();
+        }
+
+        let uids: Vec<String> = lob.oldest_orders(3).into_iter().map(|order| order.uid).collect();
+        assert_eq!(uids, vec!["4".to_string(), "1".to_string(), "3".to_string()]);
+        assert_eq!(lob.oldest_orders(10).len(), 4);
+        assert!(lob.error_msgs().contains("1 orders have unparseable timestamps"));
+    }
+
+    #[test]
+    fn clear_side() {
+        let mut lob = LimitOrderbook::new();
+        for order in generate_random_orders(200) {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+        let bids = lob.levels(Side::Bids, None);
+        let bid_count = lob.iter().filter(|order| order.side == Side::Bids).count();
+        let bid_cutoff = lob.bid_cutoff;
+
+        lob.clear_side(Side::Asks);
+        assert_eq!(lob.best_ask(), None);
+        assert_eq!(lob.ask_cutoff, 0.0);
+        assert_eq!(lob.levels(Side::Bids, None), bids);
+        assert_eq!(lob.bid_cutoff, bid_cutoff);
+        assert_eq!(lob.len(), bid_count);
+        assert_eq!(lob.order_map.len(), bid_count);
+        assert!(lob.order_map.values().all(|(side, _)| *side == Side::Bids));
+        lob.check();
+        assert!(lob.error_msgs().is_empty());
+    }
+
+    #[test]
+    fn as_maps() {
+        let mut lob = LimitOrderbook::new();
+        for order in generate_random_orders(200) {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+        let (bids, asks) = lob.as_maps();
+        for (side, map) in [(Side::Bids, bids), (Side::Asks, asks)] {
+            let levels = lob.levels(side, None);
+            assert_eq!(map.len(), levels.len());
+            for (price, size, _) in levels {
+                assert_eq!(map.get(&price.to_string()), Some(&size));
+            }
+        }
+    }
+
+    #[test]
+    fn diff() {
+        let mut previous = LimitOrderbook::new();
+        for order in [
+            order("0", Side::Bids, 10.0, 1.0),
+            order("1", Side::Bids, 9.0, 2.0),
+            order("2", Side::Bids, 8.0, 4.0),
+            order("3", Side::Asks, 11.0, 1.0),
+            order("4", Side::Asks, 12.0, 2.0),
+        ] {
+            previous.process(order, Submit::Insert).unwrap();
+        }
+        let mut lob = LimitOrderbook::from_snapshot(previous.snapshot());
+        assert!(lob.diff(&previous).is_empty());
+
+        lob.process(order("1", Side::Bids, 9.0, 3.0), Submit::Update).unwrap();    // changed
+        lob.process(order("2", Side::Bids, 8.0, 0.0), Submit::Remove).unwrap();    // disappeared
+        lob.process(order("5", Side::Bids, 8.5, 1.0), Submit::Insert).unwrap();    // appeared
+        lob.process(order("6", Side::Asks, 11.0, 1.0), Submit::Insert).unwrap();   // changed
+        lob.process(order("7", Side::Asks, 13.0, 5.0), Submit::Insert).unwrap();   // appeared
+        lob.process(order("8", Side::Asks, 11.5, 1.0), Submit::Insert).unwrap();   // added then removed
+        lob.process(order("8", Side::Asks, 11.5, 0.0), Submit::Remove).unwrap();
+
+        assert_eq!(lob.diff(&previous), vec![
+            (Side::Bids, 9.0, 3.0),
+            (Side::Bids, 8.5, 1.0),
+            (Side::Bids, 8.0, 0.0),
+            (Side::Asks, 11.0, 2.0),
+            (Side::Asks, 13.0, 5.0),
+        ]);
+    }
+
+    #[test]
+    fn top_level_orders() {
+        let mut lob = LimitOrderbook::new();
+        assert!(lob.top_level_orders(Side::Asks).is_empty());
+        for (uid, price) in [("0", 11.0), ("1", 10.0), ("2", 10.0), ("3", 12.0), ("4", 10.0)] {
+            lob.process(order(uid, Side::Asks, price, 1.0), Submit::Insert).unwrap();
+        }
+        let uids: Vec<String> = lob.top_level_orders(Side::Asks).into_iter().map(|order| order.uid).collect();
+        assert_eq!(uids, vec!["1".to_string(), "2".to_string(), "4".to_string()]);
+        assert!(lob.top_level_orders(Side::Bids).is_empty());
+    }
+
+    #[test]
+    fn check() {
+        let mut lob = LimitOrderbook::new();
+        for order in generate_random_orders(200) {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+        assert!(lob.check().is_empty());
+
+        lob.len += 1;
+        let error_msgs = lob.check();
+        assert_eq!(error_msgs.len(), 1);
+        assert!(error_msgs.iter().next().unwrap().starts_with("Order count mismatch"));
+        assert_eq!(lob.error_msgs(), error_msgs);
+        lob.len -= 1;
+    }
+
+    #[test]
+    fn pending_orders() {
+        let mut lob = LimitOrderbook::new();
+
+        // received -> open -> done
+        lob.stage(order("0", Side::Bids, 10.0, 2.0));
+        assert_eq!(lob.pending_count(), 1);
+        assert!(!lob.has("0".to_string()));
+        lob.process(Order::new("0".to_string(), Some(Side::Bids), None, None, "dummy_datetime".to_string()), Submit::Insert).unwrap();
+        assert_eq!(lob.pending_count(), 0);
+        let opened = lob.get_order("0".to_string()).unwrap();
+        assert_eq!((opened.price, opened.size), (10.0, 2.0));
+        lob.process(Order::new("0".to_string(), None, None, None, "dummy_datetime".to_string()), Submit::Remove).unwrap();
+        assert!(!lob.has("0".to_string()));
+
+        // open fields take precedence over staged ones
+        lob.stage(order("1", Side::Asks, 12.0, 1.0));
+        lob.process(Order::new("1".to_string(), Some(Side::Asks), Some(11.0), None, "dummy_datetime".to_string()), Submit::Insert).unwrap();
+        let opened = lob.get_order("1".to_string()).unwrap();
+        assert_eq!((opened.price, opened.size), (11.0, 1.0));
+
+        // received -> done
+        lob.stage(order("2", Side::Asks, 12.0, 1.0));
+        lob.process(Order::new("2".to_string(), None, None, None, "dummy_datetime".to_string()), Submit::Remove).unwrap();
+        assert_eq!(lob.pending_count(), 0);
+        assert_eq!(lob.len(), 1);
+
+        // a rejected open leaves the order staged
+        lob.stage(order("3", Side::Bids, 9.0, 3.0));
+        assert!(lob.process(Order::new("3".to_string(), Some(Side::Bids), Some(f64::NAN), None, "dummy_datetime".to_string()), Submit::Insert).is_err());
+        assert_eq!(lob.pending_count(), 1);
+        lob.process(Order::new("3".to_string(), Some(Side::Bids), None, None, "dummy_datetime".to_string()), Submit::Insert).unwrap();
+        assert_eq!(lob.pending_count(), 0);
+        assert_eq!(lob.get_order("3".to_string()).unwrap().size, 3.0);
+    }
+
+    #[test]
+    fn cumulative_notional() {
+        let mut lob = LimitOrderbook::new();
+        for (uid, price, size) in [("0", 10.0, 1.0), ("1", 9.0, 2.0), ("2", 8.0, 4.0)] {
+            lob.process(order(uid, Side::Bids, price, size), Submit::Insert).unwrap();
+        }
+        assert_eq!(lob.cumulative_notional(Side::Bids), vec![(10.0, 10.0), (9.0, 28.0), (8.0, 60.0)]);
+
+        // cumulative size counts units, not value
+        let cumulative_size: Vec<f64> = lob.levels(Side::Bids, None).iter()
+            .scan(0.0, |sum, (_, size, _)| { *sum += size; Some(*sum) })
+            .collect();
+        assert_eq!(cumulative_size, vec![1.0, 3.0, 7.0]);
+        assert!(lob.cumulative_notional(Side::Asks).is_empty());
+    }
+
+    #[test]
+    fn process_and_report_bbo() {
+        let mut lob = LimitOrderbook::new();
+        assert!(lob.process_and_report_bbo(order("0", Side::Bids, 10.0, 1.0), Submit::Insert).unwrap());
+        assert!(lob.process_and_report_bbo(order("1", Side::Asks, 11.0, 1.0), Submit::Insert).unwrap());
+        // deep levels
+        assert!(!lob.process_and_report_bbo(order("2", Side::Bids, 9.0, 1.0), Submit::Insert).unwrap());
+        assert!(!lob.process_and_report_bbo(order("3", Side::Asks, 12.0, 1.0), Submit::Insert).unwrap());
+        assert!(!lob.process_and_report_bbo(order("2", Side::Bids, 9.0, 5.0), Submit::Update).unwrap());
+        assert!(!lob.process_and_report_bbo(order("3", Side::Asks, 12.0, 0.0), Submit::Remove).unwrap());
+        // size at the touch
+        assert!(lob.process_and_report_bbo(order("4", Side::Bids, 10.0, 2.0), Submit::Insert).unwrap());
+        assert!(lob.process_and_report_bbo(order("1", Side::Asks, 11.0, 3.0), Submit::Update).unwrap());
+        // price at the touch
+        assert!(lob.process_and_report_bbo(order("5", Side::Asks, 10.5, 1.0), Submit::Insert).unwrap());
+        assert!(lob.process_and_report_bbo(order("5", Side::Asks, 10.5, 0.0), Submit::Remove).unwrap());
+    }
+
+    #[test]
+    fn resting_vwap() {
+        let mut lob = LimitOrderbook::new();
+        assert_eq!(lob.resting_vwap(), None);
+        for (uid, side, price, size) in [
+            ("0", Side::Bids, 10.0, 6.0),
+            ("1", Side::Bids, 9.0, 2.0),
+            ("2", Side::Asks, 11.0, 1.0),
+            ("3", Side::Asks, 12.0, 1.0),
+        ] {
+            lob.process(order(uid, side, price, size), Submit::Insert).unwrap();
+        }
+        // (60 + 18 + 11 + 12) / 10, pulled below the 10.5 mid by the heavier bids
+        assert_eq!(lob.resting_vwap(), Some(10.1));
+        assert_eq!(lob.mid_price(), Some(10.5));
+    }
+
+    #[test]
+    fn liquidity_in_band() {
+        let mut lob = LimitOrderbook::new();
+        assert_eq!(lob.liquidity_in_band(Side::Bids, 100.0, None), (0.0, 0));
+        for (uid, price, size) in [("0", 100.0, 1.0), ("1", 100.0, 1.0), ("2", 99.5, 2.0), ("3", 99.0, 4.0), ("4", 90.0, 8.0)] {
+            lob.process(order(uid, Side::Bids, price, size), Submit::Insert).unwrap();
+        }
+        assert_eq!(lob.liquidity_in_band(Side::Bids, 0.0, None), (2.0, 1));
+        assert_eq!(lob.liquidity_in_band(Side::Bids, 10.0, None), (2.0, 1));
+        assert_eq!(lob.liquidity_in_band(Side::Bids, 100.0, None), (8.0, 3));
+        assert_eq!(lob.liquidity_in_band(Side::Bids, 10_000.0, None), (16.0, 4));
+        assert_eq!(lob.liquidity_in_band(Side::Asks, 10_000.0, None), (0.0, 0));
+    }
+
+    #[test]
+    fn simulate_fill() {
+        let mut lob = LimitOrderbook::new();
+        for (uid, price, size) in [("0", 10.0, 1.0), ("1", 10.0, 3.0), ("2", 10.0, 6.0), ("3", 11.0, 5.0)] {
+            lob.process(order(uid, Side::Asks, price, size), Submit::Insert).unwrap();
+        }
+        let fills = |qty: f64, policy: MatchPolicy| -> Vec<(String, f64, f64)> { lob.simulate_fill(Side::Asks, qty, policy) };
+
+        assert_eq!(fills(5.0, MatchPolicy::Fifo), vec![
+            ("0".to_string(), 10.0, 1.0), ("1".to_string(), 10.0, 3.0), ("2".to_string(), 10.0, 1.0),
+        ]);
+        assert_eq!(fills(5.0, MatchPolicy::ProRata), vec![
+            ("0".to_string(), 10.0, 0.5), ("1".to_string(), 10.0, 1.5), ("2".to_string(), 10.0, 3.0),
+        ]);
+
+        // a consumed level is filled in full under both policies, and the rest walks on
+        for policy in [MatchPolicy::Fifo, MatchPolicy::ProRata] {
+            assert_eq!(fills(12.0, policy), vec![
+                ("0".to_string(), 10.0, 1.0), ("1".to_string(), 10.0, 3.0),
+                ("2".to_string(), 10.0, 6.0), ("3".to_string(), 11.0, 2.0),
+            ]);
+        }
+        assert_eq!(lob.levels(Side::Asks, None), vec![(10.0, 10.0, 100.0), (11.0, 5.0, 155.0)]);
+        assert!(lob.simulate_fill(Side::Bids, 5.0, MatchPolicy::Fifo).is_empty());
+    }
+
+    #[test]
+    fn process_errors() {
+        let mut lob = LimitOrderbook::new();
+        lob.process(order("0", Side::Bids, 10.0, 1.0), Submit::Insert).unwrap();
+
+        assert!(lob.process(order("1", Side::Bids, f64::NAN, 1.0), Submit::Insert).is_err());
+        assert!(lob.process(order("1", Side::Bids, 10.0, f64::INFINITY), Submit::Insert).is_err());
+        assert!(lob.process(order("0", Side::Bids, 10.0, f64::NAN), Submit::Update).is_err());
+        assert!(lob.process(order("0", Side::Bids, 10.0, f64::NEG_INFINITY), Submit::Fill).is_err());
+        assert!(lob.process(order("0", Side::Bids, f64::INFINITY, 1.0), Submit::Move).is_err());
+        assert_eq!(
+            LimitOrderbook::parse_query(order("1", Side::Bids, f64::INFINITY, 1.0), Submit::Insert).err(),
+            Some(OrderbookError::InvalidPrice { uid: "1".to_string(), price: f64::INFINITY })
+        );
+
+        // rejected updates leave the book and counters untouched
+        assert_eq!(lob.levels(Side::Bids, None), vec![(10.0, 1.0, 10.0)]);
+        assert_eq!(lob.items_processed(), 1);
+    }
+
+    #[test]
+    fn periodic_check() {
+        let mut lob = LimitOrderbook::new();
+        assert!(lob.set_check_interval(Some(0)).is_err());
+        lob.set_check_interval(Some(5)).unwrap();
+        let orders = generate_random_orders(20);
+        for order in &orders[..10] {
+            lob.process(order.clone(), Submit::Insert).unwrap();
+        }
+        assert!(!lob.halted());
+
+        // corrupt the order count; the next periodic check catches it
+        lob.len += 1;
+        for order in &orders[10..14] {
+            lob.process(order.clone(), Submit::Insert).unwrap();
+        }
+        assert!(!lob.halted());
+        lob.process(orders[14].clone(), Submit::Insert).unwrap();
+        assert!(lob.halted());
+        assert!(!lob.error_msgs().is_empty());
+
+        assert!(lob.process(orders[15].clone(), Submit::Insert).is_err());
+        assert_eq!(lob.items_processed(), 15);
+    }
+
+    #[test]
+    fn clear_lifts_halt() {
+        let mut lob = LimitOrderbook::new();
+        lob.set_check_interval(Some(2)).unwrap();
+        let ops = test_utils::random_ops(6, 8);
+        lob.len += 1;
+        for (order, action) in &ops[..2] {
+            lob.process(order.clone(), action.clone()).unwrap();
+        }
+        assert!(lob.halted());
+        assert!(lob.process(ops[2].0.clone(), ops[2].1.clone()).is_err());
+
+        // resync from scratch; the next periodic checks pass
+        lob.clear();
+        assert!(!lob.halted());
+        for (order, action) in &ops[2..] {
+            lob.process(order.clone(), action.clone()).unwrap();
+        }
+        assert!(!lob.halted());
+        assert!(lob.error_msgs().is_empty());
+    }
+
+    #[test]
+    fn evict_stale() {
+        let mut lob = LimitOrderbook::new();
+        let levels = [
+            ("0", Side::Bids, 10.0, "2022-09-27T19:30:00.000000Z"),
+            ("1", Side::Bids, 9.0, "2022-09-27T19:31:30.000000Z"),
+            ("2", Side::Asks, 11.0, "2022-09-27T19:30:30.000000Z"),
+            ("3", Side::Asks, 11.0, "2022-09-27T19:31:45.000000Z"),  // refreshes the 11.0 level
+            ("4", Side::Asks, 12.0, "2022-09-27T19:29:00.000000Z"),
+            ("5", Side::Asks, 13.0, "not a timestamp"),
+        ];
+        for (uid, side, price, timestamp) in levels {
+            lob.process(order_at(uid, side, price, 1.0, timestamp), Submit::Insert).unwrap();
+        }
+        let now = "2022-09-27T19:32:00.000000Z".to_string();
+        assert_eq!(lob.evict_stale(now.clone()).unwrap(), 0);
+
+        assert!(lob.set_level_ttl(Some(f64::NAN)).is_err());
+        assert!(lob.set_level_ttl(Some(-1.0)).is_err());
+        assert_eq!(lob.evict_stale(now.clone()).unwrap(), 0);
+        lob.set_level_ttl(Some(60.0)).unwrap();
+        assert!(lob.evict_stale("19:32".to_string()).is_err());
+        assert_eq!(lob.evict_stale(now).unwrap(), 2);
+        assert_eq!(lob.levels(Side::Bids, None), vec![(9.0, 1.0, 9.0)]);
+        assert_eq!(lob.levels(Side::Asks, None).iter().map(|l| l.0).collect::<Vec<f64>>(), vec![11.0, 13.0]);
+        assert_eq!(lob.len(), 4);
+        assert!(!lob.has("0".to_string()) && !lob.has("4".to_string()));
+        assert_eq!(lob.bid_cutoff, 9.0 / lob.outlier_factor);
+        assert!(lob.check().is_empty());
+
+        // updates and fills refresh a level, so only the untouched one goes stale
+        let mut lob = LimitOrderbook::new();
+        lob.set_level_ttl(Some(60.0)).unwrap();
+        for (uid, price) in [("0", 10.0), ("1", 9.0), ("2", 8.0)] {
+            lob.process(order_at(uid, Side::Bids, price, 2.0, "2022-09-27T19:30:00.000000Z"), Submit::Insert).unwrap();
+        }
+        lob.process(order_at("0", Side::Bids, 10.0, 3.0, "2022-09-27T19:31:30.000000Z"), Submit::Update).unwrap();
+        lob.process(order_at("1", Side::Bids, 9.0, 1.0, "2022-09-27T19:31:40.000000Z"), Submit::Fill).unwrap();
+        assert_eq!(lob.get_order("0".to_string()).unwrap().last_update.as_deref(), Some("2022-09-27T19:31:30.000000Z"));
+        assert_eq!(lob.evict_stale("2022-09-27T19:32:00.000000Z".to_string()).unwrap(), 1);
+        assert_eq!(lob.levels(Side::Bids, None).iter().map(|l| l.0).collect::<Vec<f64>>(), vec![10.0, 9.0]);
+    }
+
+    #[test]
+    fn order_counts_top() {
+        let mut lob = LimitOrderbook::new();
+        let mut uid = 0;
+        for (price, count) in [(10.0, 3), (9.0, 1), (8.0, 4), (7.0, 2)] {
+            for _ in 0..count {
+                lob.process(order(uid, Side::Bids, price, 1.0), Submit::Insert).unwrap();
+                uid += 1;
+            }
+        }
+        assert_eq!(lob.order_counts_top(Side::Bids, 3), vec![(10.0, 3), (9.0, 1), (8.0, 4)]);
+        assert_eq!(lob.order_counts_top(Side::Bids, 10).len(), 4);
+        assert!(lob.order_counts_top(Side::Asks, 3).is_empty());
+    }
+
+    #[test]
+    fn decayed_depth() {
+        let mut lob = LimitOrderbook::new();
+        let orders = [
+            ("0", 10.0, 4.0, "2022-09-27T19:32:00.000000Z"),  // age 0
+            ("1", 10.0, 2.0, "2022-09-27T19:31:00.000000Z"),  // age 60, one half-life
+            ("2", 9.0, 8.0, "2022-09-27T19:30:00.000000Z"),   // age 120, two half-lives
+            ("3", 9.0, 1.0, "2022-09-27T19:31:30.000000Z"),   // age 30, half a half-life
+        ];
+        for (uid, price, size, timestamp) in orders {
+            lob.process(order_at(uid, Side::Bids, price, size, timestamp), Submit::Insert).unwrap();
         }
+        let now = "2022-09-27T19:32:00.000000Z".parse::<DateTime<Utc>>().unwrap();
+        let expected = 4.0 + 2.0 * 0.5 + 8.0 * 0.25 + 1.0 * 0.5_f64.sqrt();
+        let (depth, unparseable) = lob.decayed_depth(Side::Bids, 60.0, now).unwrap();
+        assert!((depth - expected).abs() < 1e-12);
+        assert_eq!(unparseable, 0);
+
+        // unparseable and future-dated orders count at full size
+        lob.process(order_at("4", Side::Bids, 8.0, 3.0, "not a timestamp"), Submit::Insert).unwrap();
+        lob.process(order_at("5", Side::Bids, 8.0, 5.0, "2022-09-27T19:33:00.000000Z"), Submit::Insert).unwrap();
+        let (depth, unparseable) = lob.decayed_depth(Side::Bids, 60.0, now).unwrap();
+        assert!((depth - (expected + 8.0)).abs() < 1e-12);
+        assert_eq!(unparseable, 1);
+        assert!(lob.error_msgs().is_empty());
+        assert_eq!(lob.decayed_depth(Side::Asks, 60.0, now).unwrap(), (0.0, 0));
+
+        for half_life in [0.0, -60.0, f64::NAN] {
+            assert!(lob.decayed_depth(Side::Bids, half_life, now).is_err());
+        }
+    }
+
+    #[test]
+    fn process_batch() {
+        let mut rng = rand::thread_rng();
+        let mut orders = generate_random_orders(300);
+        let mut actions: Vec<Submit> = orders.iter().map(|_| Submit::Insert).collect();
+        for _ in 0..100 {
+            let mut order = orders[rng.gen_range(0..300)].clone();
+            if rng.gen_bool(0.5) {
+                order.size = rng.gen_range(1..100) as f64;
+                actions.push(Submit::Update);
+            } else {
+                actions.push(Submit::Remove);
+            }
+            orders.push(order);
+        }
+
+        let mut individual = LimitOrderbook::new();
+        for (order, action) in orders.iter().zip(actions.iter()) {
+            individual.process(order.clone(), action.clone()).unwrap();
+        }
+        let mut batched = LimitOrderbook::new();
+        batched.process_batch(orders, actions).unwrap();
+
+        for side in [Side::Bids, Side::Asks] {
+            assert_eq!(batched.levels(side.clone(), None), individual.levels(side, None));
+        }
+        assert_eq!(batched.items_processed(), individual.items_processed());
+        assert!(batched.process_batch(vec![Order::default()], vec![]).is_err());
+    }
+
+    #[test]
+    fn sequence_gap_invalidation() {
+        let mut lob = LimitOrderbook::new();
+        lob.process_sequenced(order("0", Side::Bids, 10.0, 1.0), Submit::Insert, 100).unwrap();
+        lob.process_sequenced(order("1", Side::Asks, 11.0, 1.0), Submit::Insert, 101).unwrap();
+        // stale message is ignored
+        lob.process_sequenced(order("2", Side::Asks, 12.0, 1.0), Submit::Insert, 101).unwrap();
+        assert!(!lob.has("2".to_string()));
+        assert_eq!((lob.best_bid(), lob.best_ask()), (Some(10.0), Some(11.0)));
+
+        // gap invalidates the book
+        assert!(lob.process_sequenced(order("3", Side::Bids, 10.5, 1.0), Submit::Insert, 103).is_err());
+        assert!(!lob.is_valid());
+        assert_eq!((lob.best_bid(), lob.best_ask()), (None, None));
+        assert!(lob.levels(Side::Bids, None).is_empty());
+        assert!(lob.error_msgs().contains(INVALIDATED_MSG));
+        // every level and order reader sees an empty book, while the orders are kept
+        assert_eq!(lob.levels_iter(Side::Asks).count(), 0);
+        assert_eq!(lob.as_maps(), (HashMap::new(), HashMap::new()));
+        assert_eq!(lob.depth_to_price(Side::Bids, 0.0), 0.0);
+        assert!(lob.cumulative_notional(Side::Bids).is_empty());
+        assert!(lob.depth_curve(vec![100.0]).is_empty());
+        assert!(lob.top_level_orders(Side::Asks).is_empty());
+        assert!(lob.orders_at_price(Side::Bids, 10.0).is_empty());
+        assert!(!lob.has("0".to_string()) && !lob.has_bids());
+        assert_eq!((lob.locate("0".to_string()), lob.queue_ahead("0".to_string())), (None, None));
+        assert_eq!((lob.resting_vwap(), lob.microprice(), lob.imbalance(5)), (None, None, None));
+        assert!(lob.oldest_orders(5).is_empty());
+        assert_eq!(lob.level_crc32(), LimitOrderbook::new().level_crc32());
+        assert_eq!(lob.len(), 2);
+        assert!(!lob.is_empty());
+        // checking an invalidated book keeps it flagged
+        assert!(lob.check().contains(INVALIDATED_MSG));
+        assert!(lob.error_msgs().contains(INVALIDATED_MSG));
+        assert!(lob.process_sequenced(order("4", Side::Bids, 10.5, 1.0), Submit::Insert, 104).is_err());
+        assert!(lob.process(order("4", Side::Bids, 10.5, 1.0), Submit::Insert).is_err());
+
+        // resync
+        lob.clear();
+        assert!(lob.is_valid());
+        assert!(lob.error_msgs().is_empty());
+        assert_eq!(lob.len(), 0);
+        lob.process_sequenced(order("5", Side::Bids, 9.0, 1.0), Submit::Insert, 200).unwrap();
+        lob.process_sequenced(order("6", Side::Asks, 9.5, 1.0), Submit::Insert, 201).unwrap();
+        assert_eq!((lob.best_bid(), lob.best_ask()), (Some(9.0), Some(9.5)));
+        assert!(lob.check().is_empty());
+    }
+
+    #[test]
+    fn order_time() {
+        let time = order_at("0", Side::Bids, 0.0, 0.0, "2022-09-27T19:31:05Z").time().unwrap();
+        assert_eq!(time.timestamp(), 1664307065);
+
+        let time = order_at("0", Side::Bids, 0.0, 0.0, "2022-09-27T19:31:05.123456Z").time().unwrap();
+        assert_eq!(time.timestamp(), 1664307065);
+        assert_eq!(time.timestamp_subsec_micros(), 123456);
+
+        for malformed in ["dummy_datetime", "2022-09-27 19:31:05", "2022-13-27T19:31:05.000000Z", ""] {
+            assert_eq!(order_at("0", Side::Bids, 0.0, 0.0, malformed).time(), None);
+        }
+
+        // setting the timestamp resets the cached time, and the cache doesn't affect equality
+        let mut moved = order_at("0", Side::Bids, 0.0, 0.0, "2022-09-27T19:31:05.5Z");
+        assert_eq!(moved.time().unwrap().timestamp(), 1664307065);
+        moved.set_timestamp("2022-09-27T19:32:00Z".to_string());
+        assert_eq!(moved.timestamp(), "2022-09-27T19:32:00Z");
+        assert_eq!(moved.time(), order_at("0", Side::Bids, 0.0, 0.0, "2022-09-27T19:32:00.000000Z").time());
+        assert_eq!(moved, order_at("0", Side::Bids, 0.0, 0.0, "2022-09-27T19:32:00Z"));
+        let restored: Order = serde_json::from_str(&serde_json::to_string(&moved).unwrap()).unwrap();
+        assert_eq!(restored.time(), moved.time());
+    }
+
+    #[test]
+    fn spread_bps() {
+        let mut lob = LimitOrderbook::new();
+        lob.process(order("0", Side::Bids, 99.5, 1.0), Submit::Insert).unwrap();
+        assert_eq!(lob.spread_bps(), None);
+        lob.process(order("1", Side::Asks, 100.5, 1.0), Submit::Insert).unwrap();
+        assert!((lob.spread_bps().unwrap() - 100.0).abs() < 1e-9);
+
+        // crossed
+        lob.process(order("2", Side::Bids, 101.0, 1.0), Submit::Insert).unwrap();
+        assert_eq!(lob.spread_bps(), None);
+    }
+
+    #[test]
+    fn max_depth_per_side() {
+        let mut lob = LimitOrderbook::new();
+        lob.set_max_depth_per_side(Some(3));
+        let mut uid = 0;
+        for price in [10.0, 9.0, 8.0, 7.0, 9.5, 6.0, 10.0] {
+            lob.process(order(uid, Side::Bids, price, 1.0), Submit::Insert).unwrap();
+            lob.process(order(uid + 1, Side::Asks, price + 5.0, 1.0), Submit::Insert).unwrap();
+            uid += 2;
+        }
+        let prices = |side: Side| lob.levels(side, None).iter().map(|level| level.0).collect::<Vec<f64>>();
+        assert_eq!(prices(Side::Bids), vec![10.0, 9.5, 9.0]);
+        assert_eq!(prices(Side::Asks), vec![11.0, 12.0, 13.0]);
+        assert_eq!(lob.len(), 7);
+        assert_eq!(lob.order_map.len(), 7);
+
+        // lowering the cap trims immediately
+        lob.set_max_depth_per_side(Some(1));
+        assert_eq!(lob.node_count(), 2);
+        assert_eq!(lob.len(), 3);
+        assert!(lob.check().is_empty());
+
+        // lingering empty levels neither count towards the cap nor get pruned by it
+        let mut lob = LimitOrderbook::new();
+        lob.set_linger_empty_levels(true);
+        lob.set_max_depth_per_side(Some(2));
+        lob.process(order("0", Side::Bids, 10.0, 1.0), Submit::Insert).unwrap();
+        lob.process(order("1", Side::Bids, 9.0, 1.0), Submit::Insert).unwrap();
+        lob.process(order("1", Side::Bids, 9.0, 1.0), Submit::Remove).unwrap();
+        lob.process(order("2", Side::Bids, 8.0, 1.0), Submit::Insert).unwrap();
+        assert_eq!(lob.bids.len(), 3);
+        lob.process(order("3", Side::Bids, 7.0, 1.0), Submit::Insert).unwrap();
+        assert_eq!(lob.levels(Side::Bids, None).iter().map(|level| level.0).collect::<Vec<f64>>(), vec![10.0, 8.0]);
+        assert_eq!(lob.bids.len(), 3);
+        assert_eq!(lob.empty_since.len(), 1);
+        assert!(lob.check().is_empty());
+    }
+
+    #[test]
+    fn locate() {
+        let mut lob = LimitOrderbook::new();
+        lob.process(order("0", Side::Bids, 10.0, 1.0), Submit::Insert).unwrap();
+        lob.process(order("1", Side::Asks, 11.0, 1.0), Submit::Insert).unwrap();
+        assert_eq!(lob.locate("0".to_string()), Some((Side::Bids, 10.0)));
+        assert_eq!(lob.locate("1".to_string()), Some((Side::Asks, 11.0)));
+        assert_eq!(lob.locate("2".to_string()), None);
+
+        lob.process(Order::new("1".to_string(), None, Some(12.0), None, "dummy_datetime".to_string()), Submit::Move).unwrap();
+        assert_eq!(lob.locate("1".to_string()), Some((Side::Asks, 12.0)));
+    }
+
+    /// Applies seeded random operation sequences and checks book integrity. If a seed
+    /// fails, rerun it alone by replacing the seed range with that seed, e.g. 1234..1235.
+    #[test]
+    fn random_ops_integrity() {
+        for seed in 0..20 {
+            let ops = test_utils::random_ops(seed, 2000);
+            assert_eq!(format!("{:?}", ops), format!("{:?}", test_utils::random_ops(seed, 2000)));
+
+            let mut lob = LimitOrderbook::new();
+            for (order, action) in ops {
+                lob.process(order, action).unwrap();
+            }
+            let error_msgs = lob.check();
+            assert!(error_msgs.is_empty(), "seed {} failed integrity checks: {:?}", seed, error_msgs);
+            assert_eq!(lob.outliers(), 0);
+        }
+    }
+
+    #[test]
+    fn linger_empty_levels() {
+        let mut lob = LimitOrderbook::new();
+        lob.set_linger_empty_levels(true);
+        for i in 0..15 {
+            let price = 90.0 + i as f64;
+            lob.process(order(i, Side::Bids, price, 1.0), Submit::Insert).unwrap();
+        }
+        let structure = lob.tree_structure(Side::Bids);
+
+        // emptied level lingers, invisible to queries
+        lob.process(Order::new("14".to_string(), None, None, None, "dummy_datetime".to_string()), Submit::Remove).unwrap();
+        lob.process(Order::new("3".to_string(), None, None, None, "dummy_datetime".to_string()), Submit::Remove).unwrap();
+        assert_eq!(lob.tree_structure(Side::Bids), structure);
+        assert_eq!(lob.best_bid(), Some(103.0));
+        assert_eq!(lob.node_count(), 13);
+        assert_eq!(lob.levels(Side::Bids, None).len(), 13);
+
+        // reusing a lingering level doesn't touch the tree's shape
+        lob.process(order("15", Side::Bids, 104.0, 2.0), Submit::Insert).unwrap();
+        assert_eq!(lob.tree_structure(Side::Bids), structure);
+        assert_eq!(lob.best_bid(), Some(104.0));
+        assert_eq!(lob.node_count(), 14);
+
+        // lingering levels survive one full compaction cycle
+        assert_eq!(lob.compact(), 0);
+        assert_eq!(lob.tree_structure(Side::Bids).len(), 15);
+        assert_eq!(lob.compact(), 1);
+        assert_eq!(lob.tree_structure(Side::Bids).len(), 14);
+        assert_eq!(lob.node_count(), 14);
+        assert!(lob.check().is_empty());
+
+        // without lingering, emptied levels leave the tree immediately
+        lob.set_linger_empty_levels(false);
+        lob.process(Order::new("15".to_string(), None, None, None, "dummy_datetime".to_string()), Submit::Remove).unwrap();
+        assert_eq!(lob.tree_structure(Side::Bids).len(), 13);
+    }
+
+    #[test]
+    fn hidden_size() {
+        let mut lob = LimitOrderbook::new();
+        for (uid, price, size) in [("0", 10.0, 1.0), ("1", 10.0, 2.0), ("2", 9.0, 4.0)] {
+            lob.process(order(uid, Side::Bids, price, size), Submit::Insert).unwrap();
+        }
+        // no hidden size: visible and total agree
+        assert_eq!(lob.levels_visible(Side::Bids), lob.levels(Side::Bids, None));
+        assert_eq!(lob.levels(Side::Bids, None), vec![(10.0, 3.0, 30.0), (9.0, 4.0, 66.0)]);
+
+        let iceberg = Order { hidden_size: 5.0, ..order("3", Side::Bids, 9.0, 1.0) };
+        lob.process(iceberg, Submit::Insert).unwrap();
+        assert_eq!(lob.levels_visible(Side::Bids), vec![(10.0, 3.0, 30.0), (9.0, 5.0, 75.0)]);
+        assert_eq!(lob.levels(Side::Bids, None), vec![(10.0, 3.0, 30.0), (9.0, 10.0, 120.0)]);
+        let order_stack = lob.bids.get(&9.0).unwrap();
+        assert_eq!((order_stack.visible_size(), order_stack.total_size()), (5.0, 10.0));
+    }
+
+    #[test]
+    fn can_fill() {
+        let mut lob = LimitOrderbook::new();
+        assert!(!lob.can_fill(Side::Asks, 1.0));
+        for (uid, price, size) in [("0", 10.0, 1.0), ("1", 11.0, 2.0), ("2", 12.0, 4.0)] {
+            lob.process(order(uid, Side::Asks, price, size), Submit::Insert).unwrap();
+        }
+        assert!(lob.can_fill(Side::Asks, 0.5));
+        assert!(lob.can_fill(Side::Asks, 3.0));
+        assert!(lob.can_fill(Side::Asks, 7.0));  // exactly the whole side
+        assert!(!lob.can_fill(Side::Asks, 7.5));
+        assert!(!lob.can_fill(Side::Asks, f64::NAN));
+        // the bids are empty
+        assert!(!lob.can_fill(Side::Bids, 0.5));
+        assert!(lob.can_fill(Side::Bids, 0.0));
+    }
+
+    #[test]
+    fn effective_spread() {
+        let mut lob = LimitOrderbook::new();
+        let levels = [
+            ("0", Side::Bids, 99.0, 1.0), ("1", Side::Bids, 98.0, 1.0), ("2", Side::Bids, 96.0, 2.0),
+            ("3", Side::Asks, 101.0, 1.0), ("4", Side::Asks, 102.0, 1.0), ("5", Side::Asks, 104.0, 2.0),
+        ];
+        for (uid, side, price, size) in levels {
+            lob.process(order(uid, side, price, size), Submit::Insert).unwrap();
+        }
+        assert_eq!(lob.vwap(Side::Asks, 2.0), Some(101.5));
+        assert_eq!(lob.vwap(Side::Bids, 4.0), Some(97.25));
+        assert_eq!(lob.vwap(Side::Asks, 5.0), None);
+        assert_eq!(lob.vwap(Side::Asks, 0.0), None);
+
+        assert_eq!(lob.effective_spread(1.0), Some(2.0));
+        assert_eq!(lob.effective_spread(2.0), Some(3.0));
+        assert_eq!(lob.effective_spread(4.0), Some(5.5));
+        assert_eq!(lob.effective_spread(4.5), None);
+    }
+
+    #[test]
+    fn snapshot_msgpack() {
+        let mut lob = LimitOrderbook::new();
+        for order in generate_random_orders(200) {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+
+        let bytes = lob.snapshot_msgpack();
+        assert_eq!(bytes[0], SNAPSHOT_MSGPACK_VERSION);
+        assert!(bytes.len() < lob.to_snapshot_json().len());
+
+        let restored = LimitOrderbook::from_snapshot_msgpack(&bytes).unwrap();
+        assert_eq!(restored.items_processed(), lob.items_processed());
+        assert_eq!(restored.outliers(), lob.outliers());
+        assert_eq!(restored.total_adds(), lob.total_adds());
+        assert_eq!(restored.iter().collect::<Vec<&Order>>(), lob.iter().collect::<Vec<&Order>>());
+
+        let mut bad_version = bytes.clone();
+        bad_version[0] = SNAPSHOT_MSGPACK_VERSION + 1;
+        assert!(LimitOrderbook::from_snapshot_msgpack(&bad_version).is_err());
+        bad_version[0] = 0;
+        assert!(LimitOrderbook::from_snapshot_msgpack(&bad_version).is_err());
+        assert!(LimitOrderbook::from_snapshot_msgpack(&[]).is_err());
+
+        // version 1 snapshots, written before the activity counters, still load
+        #[derive(Serialize)]
+        struct SnapshotV1<'a> {
+            orders: Vec<&'a Order>,
+            items_processed: usize,
+            outliers: usize,
+        }
+        let v1 = SnapshotV1 { orders: lob.iter().collect(), items_processed: lob.items_processed(), outliers: lob.outliers() };
+        let mut v1_bytes = vec![1];
+        v1_bytes.extend(rmp_serde::to_vec_named(&v1).unwrap());
+        let restored = LimitOrderbook::from_snapshot_msgpack(&v1_bytes).unwrap();
+        assert_eq!(restored.items_processed(), lob.items_processed());
+        assert_eq!(restored.total_adds(), 0);
+        assert_eq!(restored.iter().collect::<Vec<&Order>>(), lob.iter().collect::<Vec<&Order>>());
+    }
+
+    #[test]
+    fn desynced_order_map() {
+        let mut lob = LimitOrderbook::new();
+        lob.process(order("1", Side::Bids, 10.0, 1.0), Submit::Insert).unwrap();
+        // entries pointing at a missing level and at a level without the order
+        lob.order_map.insert("ghost".to_string(), (Side::Bids, 42.0));
+        lob.order_map.insert("stray".to_string(), (Side::Bids, 10.0));
+        assert!(lob.get_order("ghost".to_string()).is_none());
+        assert!(lob.get_order("stray".to_string()).is_none());
+
+        lob.update("ghost".to_string(), 2.0, None);
+        lob.remove("stray".to_string());
+        assert!(!lob.order_map.contains_key("ghost"));
+        assert!(!lob.order_map.contains_key("stray"));
+        assert_eq!(lob.error_msgs.len(), 2);
+        assert_eq!(lob.len(), 1);
+        assert_eq!(lob.get_order("1".to_string()).unwrap().size, 1.0);
+    }
+
+    #[test]
+    fn weighted_mid() {
+        let mut lob = LimitOrderbook::new();
+        lob.process(order("0", Side::Bids, 100.0, 3.0), Submit::Insert).unwrap();
+        assert_eq!(lob.weighted_mid(0.5), None);
+        lob.process(order("1", Side::Asks, 102.0, 1.0), Submit::Insert).unwrap();
+
+        // (100 * 1 + 102 * 3) / 4
+        assert_eq!(lob.microprice(), Some(101.5));
+        assert_eq!(lob.weighted_mid(0.0), lob.mid_price());
+        assert_eq!(lob.weighted_mid(1.0), lob.microprice());
+        assert_eq!(lob.weighted_mid(0.5), Some(101.25));
+        assert_eq!(lob.weighted_mid(-1.0), lob.mid_price());
+        assert_eq!(lob.weighted_mid(2.0), lob.microprice());
+    }
+
+    #[test]
+    fn top_n_json() {
+        let mut lob = LimitOrderbook::new();
+        let orders = vec![
+            order("0", Side::Bids, 10.0, 1.5),
+            order("1", Side::Bids, 10.0, 0.5),
+            order("2", Side::Bids, 9.5, 2.0),
+            order("3", Side::Bids, 9.0, 0.25),
+            order("4", Side::Asks, 10.5, 3.0),
+            order("5", Side::Asks, 11.0, 1.0),
+        ];
+        for order in orders {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+        let ladder: DepthLadder = serde_json::from_str(&lob.top_n_json(2)).unwrap();
+        assert_eq!(ladder.bids, vec![(10.0, 2.0), (9.5, 2.0)]);
+        assert_eq!(ladder.asks, vec![(10.5, 3.0), (11.0, 1.0)]);
+
+        let ladder: DepthLadder = serde_json::from_str(&lob.top_n_json(5)).unwrap();
+        assert_eq!(ladder.bids.len(), 3);
+        assert_eq!(ladder.asks.len(), 2);
+    }
+
+    #[test]
+    fn update_delta() {
+        let mut lob = LimitOrderbook::new();
+        lob.process(order("0", Side::Bids, 10.0, 2.0), Submit::Insert).unwrap();
+
+        lob.update_delta("0".to_string(), 1.5, "t1".to_string()).unwrap();
+        assert_eq!(lob.get_order("0".to_string()).unwrap().size, 3.5);
+        assert_eq!(lob.last_bid_update(), Some("t1".to_string()));
+
+        lob.update_delta("0".to_string(), -1.0, "t2".to_string()).unwrap();
+        assert_eq!(lob.get_order("0".to_string()).unwrap().size, 2.5);
+        assert_eq!(lob.items_processed(), 3);
+
+        // non-finite deltas are rejected, leaving the order alone
+        assert!(lob.update_delta("0".to_string(), f64::NAN, "t3".to_string()).is_err());
+        assert!(lob.update_delta("0".to_string(), f64::NEG_INFINITY, "t3".to_string()).is_err());
+        assert_eq!(lob.get_order("0".to_string()).unwrap().size, 2.5);
+        assert_eq!(lob.items_processed(), 3);
+
+        // over-decrement clamps to zero and removes the order
+        lob.update_delta("0".to_string(), -4.0, "t4".to_string()).unwrap();
+        assert!(lob.get_order("0".to_string()).is_none());
+        assert_eq!(lob.len(), 0);
+        assert_eq!(lob.best_bid(), None);
+
+        // unknown uids are ignored
+        lob.update_delta("1".to_string(), 1.0, "t5".to_string()).unwrap();
+        assert_eq!(lob.len(), 0);
+
+        // a halted book rejects deltas like any other update
+        lob.halted = true;
+        assert!(lob.update_delta("1".to_string(), 1.0, "t6".to_string()).is_err());
+    }
+
+    #[test]
+    fn into_orders() {
+        let mut lob = LimitOrderbook::new();
+        for order in generate_random_orders(200) {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+        let expected: Vec<Order> = lob.iter().cloned().collect();
+        let orders = lob.into_orders();
+        assert_eq!(orders, expected);
+    }
+
+    #[test]
+    fn pressure_ema() {
+        let mut lob = LimitOrderbook::new();
+        lob.set_pressure_ema_alpha(0.2, 1).unwrap();
+        assert!(lob.set_pressure_ema_alpha(0.0, 1).is_err());
+        assert!(lob.set_pressure_ema_alpha(0.2, 0).is_err());
+
+        let bid = order("0", Side::Bids, 100.0, 1.0);
+        lob.process(bid.clone(), Submit::Insert).unwrap();
+        // one-sided, so no sample yet
+        assert_eq!(lob.pressure_ema(), None);
+
+        lob.process(order("1", Side::Asks, 102.0, 1.0), Submit::Insert).unwrap();
+        assert_eq!(lob.pressure_ema(), Some(0.0));
+
+        // bid size triples, so each top-of-book change samples an imbalance of 0.5
+        lob.process(Order { size: 3.0, ..bid.clone() }, Submit::Update).unwrap();
+        assert_eq!(lob.imbalance(1), Some(0.5));
+        let mut previous = 0.0;
+        for i in 0..50 {
+            let price = if i % 2 == 0 { 100.5 } else { 100.0 };
+            lob.process(Order { price, ..bid.clone() }, Submit::Move).unwrap();
+            let ema = lob.pressure_ema().unwrap();
+            assert!(ema > previous && ema < 0.5);
+            previous = ema;
+        }
+        assert!((previous - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn removal_reasons() {
+        let mut lob = LimitOrderbook::new();
+        let orders: Vec<Order> = (0..4)
+            .map(|i| order(i, Side::Bids, 10.0 + i as f64, 1.0))
+            .collect();
+        for order in &orders {
+            lob.process(order.clone(), Submit::Insert).unwrap();
+        }
+        let done = |order: &Order, reason: Option<&str>| Order { reason: reason.map(str::to_string), ..order.clone() };
+
+        lob.process(done(&orders[0], Some("filled")), Submit::RemoveWithReason).unwrap();
+        lob.process(done(&orders[1], Some("canceled")), Submit::RemoveWithReason).unwrap();
+        // unknown reasons count as canceled
+        lob.process(done(&orders[2], Some("expired")), Submit::RemoveWithReason).unwrap();
+        // plain removes and removes of unknown orders aren't counted
+        lob.process(orders[3].clone(), Submit::Remove).unwrap();
+        lob.process(done(&orders[0], Some("filled")), Submit::RemoveWithReason).unwrap();
+
+        assert_eq!(lob.len(), 0);
+        assert_eq!(lob.filled_count(), 1);
+        assert_eq!(lob.canceled_count(), 2);
+
+        lob.reset_counters();
+        assert_eq!((lob.filled_count(), lob.canceled_count()), (0, 0));
+    }
+
+    #[test]
+    fn with_capacity() {
+        let mut reserved = LimitOrderbook::with_capacity(10_000);
+        let mut lob = LimitOrderbook::new();
+        assert!(reserved.order_map.capacity() >= 10_000);
+        assert_eq!(reserved.avl_tree_size_display_cutoff, 10_000);
+        assert_eq!(LimitOrderbook::with_capacity(10).avl_tree_size_display_cutoff, lob.avl_tree_size_display_cutoff);
+        for (order, action) in test_utils::random_ops(7, 500) {
+            reserved.process(order.clone(), action.clone()).unwrap();
+            lob.process(order, action).unwrap();
+        }
+        assert_eq!(reserved.len(), lob.len());
+        assert_eq!(reserved.levels(Side::Bids, None), lob.levels(Side::Bids, None));
+        assert_eq!(reserved.levels(Side::Asks, None), lob.levels(Side::Asks, None));
+        assert_eq!(reserved.iter().collect::<Vec<&Order>>(), lob.iter().collect::<Vec<&Order>>());
+    }
+
+    #[test]
+    fn levels_are_ordered() {
+        let mut lob = LimitOrderbook::new();
+        assert!(lob.levels_are_ordered(Side::Bids));
+        for (order, action) in test_utils::random_ops(11, 500) {
+            lob.process(order, action).unwrap();
+        }
+        assert!(lob.levels_are_ordered(Side::Bids));
+        assert!(lob.levels_are_ordered(Side::Asks));
+    }
+
+    #[test]
+    fn levels_columns() {
+        let mut lob = LimitOrderbook::new();
+        for order in generate_random_orders(200) {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+        for side in [Side::Bids, Side::Asks] {
+            let levels = lob.levels(side.clone(), None);
+            let (prices, sizes, cumulative) = lob.levels_columns(side);
+            assert_eq!(prices.len(), levels.len());
+            assert_eq!(sizes.len(), levels.len());
+            assert_eq!(cumulative.len(), levels.len());
+            for (i, level) in levels.into_iter().enumerate() {
+                assert_eq!((prices[i], sizes[i], cumulative[i]), level);
+            }
+        }
+    }
+
+    #[test]
+    fn imbalance_history() {
+        let mut lob = LimitOrderbook::new();
+        lob.set_imbalance_history(3, 1).unwrap();
+        assert!(lob.set_imbalance_history(3, 0).is_err());
+
+        // one-sided, so no sample
+        lob.process(order_at("0", Side::Bids, 10.0, 1.0, "t0"), Submit::Insert).unwrap();
+        assert!(lob.imbalance_history(10).is_empty());
+
+        lob.process(order_at("1", Side::Asks, 11.0, 1.0, "t1"), Submit::Insert).unwrap();   // 0
+        lob.process(order_at("0", Side::Bids, 10.0, 3.0, "t2"), Submit::Update).unwrap();   // 0.5
+        lob.process(order_at("1", Side::Asks, 11.0, 3.0, "t3"), Submit::Update).unwrap();   // 0
+        lob.process(order_at("2", Side::Asks, 12.0, 5.0, "t4"), Submit::Insert).unwrap();   // depth 1, still 0
+        lob.process(order_at("0", Side::Bids, 10.0, 1.0, "t5"), Submit::Update).unwrap();   // -0.5
+
+        // capacity 3 evicts the oldest samples
+        assert_eq!(lob.imbalance_history(10), vec![
+            ("t3".to_string(), 0.0), ("t4".to_string(), 0.0), ("t5".to_string(), -0.5),
+        ]);
+        assert_eq!(lob.imbalance_history(1), vec![("t5".to_string(), -0.5)]);
+
+        lob.set_imbalance_history(1, 1).unwrap();
+        assert_eq!(lob.imbalance_history(10), vec![("t5".to_string(), -0.5)]);
+    }
+
+    #[test]
+    fn sweep_price() {
+        let mut lob = LimitOrderbook::new();
+        lob.process(order("0", Side::Asks, 101.0, 1.0), Submit::Insert).unwrap();
+        lob.process(order("1", Side::Asks, 102.0, 2.0), Submit::Insert).unwrap();
+        lob.process(order("2", Side::Bids, 99.0, 1.0), Submit::Insert).unwrap();
+        lob.process(order("3", Side::Bids, 98.0, 1.0), Submit::Insert).unwrap();
+
+        // stops mid-level
+        assert_eq!(lob.sweep_price(Side::Asks, 0.5), Some(101.0));
+        assert_eq!(lob.sweep_price(Side::Asks, 2.0), Some(102.0));
+        // exactly at a level boundary
+        assert_eq!(lob.sweep_price(Side::Asks, 1.0), Some(101.0));
+        assert_eq!(lob.sweep_price(Side::Asks, 3.0), Some(102.0));
+        assert_eq!(lob.sweep_price(Side::Bids, 2.0), Some(98.0));
+        // insufficient depth
+        assert_eq!(lob.sweep_price(Side::Asks, 3.5), None);
+        assert_eq!(lob.sweep_price(Side::Bids, 0.0), None);
+    }
+
+    #[test]
+    fn checksum_resync() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let mut lob = LimitOrderbook::new();
+        lob.process(order("0", Side::Bids, 99.5, 2.0), Submit::Insert).unwrap();
+        lob.process(order("1", Side::Bids, 99.0, 1.0), Submit::Insert).unwrap();
+        lob.process(order("2", Side::Asks, 100.0, 1.5), Submit::Insert).unwrap();
+        let checksum = crc32(b"99.5:2:100:1.5:99:1");
+        assert_eq!(lob.level_crc32(), checksum);
+
+        lob.set_max_checksum_failures(3).unwrap();
+        assert!(!lob.validate_checksum(checksum + 1));
+        assert!(!lob.validate_checksum(checksum + 1));
+        assert!(!lob.needs_resync());
+        // a pass resets the run of failures
+        assert!(lob.validate_checksum(checksum));
+        for _ in 0..3 {
+            assert!(!lob.validate_checksum(checksum + 1));
+        }
+        assert!(lob.needs_resync());
+
+        lob.clear();
+        assert!(!lob.needs_resync());
+    }
+
+    #[test]
+    fn levels_cache() {
+        let mut lob = LimitOrderbook::new();
+        lob.process(order("0", Side::Bids, 10.0, 1.0), Submit::Insert).unwrap();
+        lob.process(order("1", Side::Asks, 11.0, 1.0), Submit::Insert).unwrap();
+
+        // hits share the same vector
+        let first = lob.cached_levels(Side::Bids);
+        assert!(Arc::ptr_eq(&first, &lob.cached_levels(Side::Bids)));
+        assert_eq!(lob.levels(Side::Bids, None), *first);
+
+        let expect_invalidated = |lob: &mut LimitOrderbook, order: Order, action: Submit| {
+            let before = lob.cached_levels(Side::Bids);
+            lob.process(order, action).unwrap();
+            let after = lob.cached_levels(Side::Bids);
+            assert!(!Arc::ptr_eq(&before, &after));
+            assert_eq!(*after, lob.levels_iter(Side::Bids).collect::<Vec<(f64, f64, f64)>>());
+        };
+        expect_invalidated(&mut lob, order("2", Side::Bids, 9.0, 2.0), Submit::Insert);
+        expect_invalidated(&mut lob, order("2", Side::Bids, 9.0, 3.0), Submit::Update);
+        expect_invalidated(&mut lob, order("2", Side::Bids, 9.0, 1.0), Submit::Fill);
+        expect_invalidated(&mut lob, order("2", Side::Bids, 9.5, 0.0), Submit::Move);
+        expect_invalidated(&mut lob, order("2", Side::Bids, 0.0, 0.0), Submit::Remove);
+        assert_eq!(*lob.cached_levels(Side::Bids), vec![(10.0, 1.0, 10.0)]);
+
+        // a rejected outlier leaves the book, and so the cache, as it was
+        let before = lob.cached_levels(Side::Bids);
+        lob.process(order("3", Side::Bids, 1.0, 1.0), Submit::Insert).unwrap();
+        assert_eq!(lob.outliers(), 1);
+        assert_eq!(*lob.cached_levels(Side::Bids), *before);
+
+        lob.clear_side(Side::Bids);
+        assert!(lob.cached_levels(Side::Bids).is_empty());
+    }
+
+    #[test]
+    fn order_meta() {
+        let mut lob = LimitOrderbook::new();
+        let meta = Some(r#"{"strategy": "mm-1", "parent": "p-42"}"#.to_string());
+        let order = Order {
+            meta: meta.clone(),
+            ..order("0", Side::Bids, 10.0, 2.0)
+        };
+        lob.process(order.clone(), Submit::Insert).unwrap();
+        assert_eq!(lob.get_order("0".to_string()).unwrap().meta, meta);
+
+        // updates carry no metadata of their own and leave it intact
+        lob.process(Order { size: 1.0, meta: None, ..order.clone() }, Submit::Update).unwrap();
+        lob.process(Order { price: 9.0, meta: None, ..order.clone() }, Submit::Move).unwrap();
+        let stored = lob.get_order("0".to_string()).unwrap();
+        assert_eq!((stored.size, stored.price), (1.0, 9.0));
+        assert_eq!(stored.meta, meta);
+    }
+
+    #[test]
+    fn both_levels() {
+        let mut lob = LimitOrderbook::new();
+        for order in generate_random_orders(200) {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+        let (bids, asks) = lob.both_levels();
+        assert_eq!(bids, lob.levels(Side::Bids, None));
+        assert_eq!(asks, lob.levels(Side::Asks, None));
+    }
+
+    #[test]
+    fn outlier_modes() {
+        // (mode, bid at or beyond the cutoff, bid just inside it, ask at or beyond, ask just inside)
+        let cases = [
+            (OutlierMode::Multiplicative, 50.0, 50.01, 200.0, 199.99),
+            (OutlierMode::Absolute, 95.0, 95.01, 105.0, 104.99),
+            (OutlierMode::Both, 95.0, 95.01, 105.0, 104.99),
+        ];
+        for (mode, bid_out, bid_in, ask_out, ask_in) in cases {
+            let mut lob = LimitOrderbook::new();
+            lob.set_outlier_mode(mode.clone(), 5.0).unwrap();
+            lob.process(order("b", Side::Bids, 100.0, 1.0), Submit::Insert).unwrap();
+            lob.process(order("a", Side::Asks, 100.0, 1.0), Submit::Insert).unwrap();
+
+            lob.process(order("0", Side::Bids, bid_out, 1.0), Submit::Insert).unwrap();
+            lob.process(order("1", Side::Bids, bid_in, 1.0), Submit::Insert).unwrap();
+            lob.process(order("2", Side::Asks, ask_out, 1.0), Submit::Insert).unwrap();
+            lob.process(order("3", Side::Asks, ask_in, 1.0), Submit::Insert).unwrap();
+            assert_eq!(lob.outliers(), 2, "{:?}", mode);
+            assert!(lob.__contains__("1".to_string()) && lob.__contains__("3".to_string()));
+        }
+
+        // with a wide absolute tolerance, Both is still bounded by the factor
+        let mut lob = LimitOrderbook::new();
+        lob.set_outlier_mode(OutlierMode::Both, 80.0).unwrap();
+        lob.process(order("b", Side::Bids, 100.0, 1.0), Submit::Insert).unwrap();
+        lob.process(order("0", Side::Bids, 50.0, 1.0), Submit::Insert).unwrap();
+        assert_eq!(lob.outliers(), 1);
+        assert!(lob.set_outlier_mode(OutlierMode::Absolute, -1.0).is_err());
+    }
+
+    #[test]
+    fn size_units() {
+        let mut lob = LimitOrderbook::new();
+        for order in generate_random_orders(200) {
+            lob.process(order, Submit::Insert).unwrap();
+        }
+        for side in [Side::Bids, Side::Asks] {
+            let base = lob.levels(side.clone(), None);
+            assert_eq!(lob.levels(side.clone(), Some(SizeUnit::Base)), base);
+            let quote = lob.levels(side.clone(), Some(SizeUnit::Quote));
+            assert_eq!(quote.len(), base.len());
+            for (&(price, size, cumulative), &(quote_price, quote_size, quote_cumulative)) in base.iter().zip(&quote) {
+                assert_eq!((quote_price, quote_size, quote_cumulative), (price, price * size, cumulative));
+            }
+        }
+
+        let mut lob = LimitOrderbook::new();
+        lob.process(order("0", Side::Bids, 100.0, 2.0), Submit::Insert).unwrap();
+        lob.process(order("1", Side::Bids, 99.0, 1.0), Submit::Insert).unwrap();
+        assert_eq!(lob.liquidity_in_band(Side::Bids, 200.0, None), (3.0, 2));
+        assert_eq!(lob.liquidity_in_band(Side::Bids, 200.0, Some(SizeUnit::Quote)), (299.0, 2));
+    }
+
+    #[test]
+    fn bbo_only() {
+        let mut lob = LimitOrderbook::with_bbo_only();
+        assert!(lob.bbo_only());
+        lob.process(order("0", Side::Bids, 99.0, 1.0), Submit::Insert).unwrap();
+        lob.process(order("1", Side::Bids, 98.0, 1.0), Submit::Insert).unwrap();  // discarded
+        lob.process(order("2", Side::Bids, 99.0, 2.0), Submit::Insert).unwrap();  // joins the best
+        lob.process(order("3", Side::Bids, 99.5, 1.0), Submit::Insert).unwrap();  // new best, 99 discarded
+        lob.process(order("4", Side::Asks, 101.0, 1.0), Submit::Insert).unwrap();
+        lob.process(order("5", Side::Asks, 100.5, 3.0), Submit::Insert).unwrap();
+        assert_eq!(lob.levels(Side::Bids, None), vec![(99.5, 1.0, 99.5)]);
+        assert_eq!(lob.levels(Side::Asks, None), vec![(100.5, 3.0, 301.5)]);
+        assert_eq!(lob.len(), 2);
+
+        // churn on the surviving levels keeps the BBO in step
+        lob.process(order("3", Side::Bids, 99.5, 4.0), Submit::Update).unwrap();
+        assert_eq!(lob.bbo(), (Some((99.5, 4.0)), Some((100.5, 3.0))));
+        lob.process(order("5", Side::Asks, 100.5, 0.0), Submit::Remove).unwrap();
+        assert_eq!(lob.best_ask(), None);
+        lob.process(order("6", Side::Asks, 100.75, 1.0), Submit::Insert).unwrap();
+        assert_eq!(lob.bbo(), (Some((99.5, 4.0)), Some((100.75, 1.0))));
+        // updates to discarded orders are ignored
+        lob.process(order("1", Side::Bids, 98.0, 5.0), Submit::Update).unwrap();
+        assert_eq!(lob.levels(Side::Bids, None).len(), 1);
+        lob.check();
+        assert!(lob.error_msgs().is_empty());
+    }
+
+    #[test]
+    fn locked_and_crossed() {
+        let book = |bid: f64, ask: f64| {
+            let mut lob = LimitOrderbook::new();
+            lob.process(order("0", Side::Bids, bid, 1.0), Submit::Insert).unwrap();
+            lob.process(order("1", Side::Asks, ask, 1.0), Submit::Insert).unwrap();
+            lob
+        };
+
+        let mut normal = book(99.0, 100.0);
+        assert!(!normal.is_locked() && !normal.is_crossed());
+        assert!(normal.market_warnings().is_empty());
+        assert!(normal.check().is_empty());
+
+        let mut locked = book(100.0, 100.0);
+        let locked_msg = "Book is locked: best bid 100 == best ask 100".to_string();
+        assert!(locked.is_locked() && !locked.is_crossed());
+        assert_eq!(locked.market_warnings(), HashSet::from([locked_msg.clone()]));
+        assert_eq!(locked.check(), HashSet::from([locked_msg.clone()]));
+        assert!(locked.error_msgs().contains(&locked_msg));
+
+        // a locked book doesn't halt periodic checks, but a structural error still does
+        let mut checked = LimitOrderbook::new();
+        checked.set_check_interval(Some(1)).unwrap();
+        checked.process(order("0", Side::Bids, 100.0, 1.0), Submit::Insert).unwrap();
+        checked.process(order("1", Side::Asks, 100.0, 1.0), Submit::Insert).unwrap();
+        assert!(!checked.halted());
+        assert!(checked.error_msgs().contains(&locked_msg));
+        checked.len += 1;
+        checked.process(order("2", Side::Bids, 99.0, 1.0), Submit::Insert).unwrap();
+        assert!(checked.halted());
+
+        let mut crossed = book(100.5, 100.0);
+        assert!(!crossed.is_locked() && crossed.is_crossed());
+        assert_eq!(crossed.market_warnings(), HashSet::from(["Book is crossed: best bid 100.5 > best ask 100".to_string()]));
+        assert!(crossed.check().is_empty());
+
+        assert!(!LimitOrderbook::new().is_locked());
+        assert!(LimitOrderbook::new().market_warnings().is_empty());
+    }
+
+    #[test]
+    fn verify_fifo() {
+        let mut lob = LimitOrderbook::new();
+        lob.place(order_at("0", Side::Bids, 10.0, 1.0, "2022-09-27T19:31:00.000000Z"));
+        lob.place(order_at("1", Side::Bids, 10.0, 1.0, "not a timestamp"));
+        lob.place(order_at("2", Side::Bids, 10.0, 1.0, "2022-09-27T19:31:00.000000Z"));
+        lob.place(order_at("3", Side::Bids, 10.0, 1.0, "2022-09-27T19:31:01.000000Z"));
+        lob.place(order_at("4", Side::Bids, 9.0, 1.0, "2022-09-27T19:32:00.000000Z"));
+        assert!(lob.verify_fifo());
+        assert!(lob.error_msgs().is_empty());
+
+        // queued behind a later order on the same level
+        lob.place(order_at("5", Side::Bids, 10.0, 1.0, "2022-09-27T19:30:59.000000Z"));
+        assert!(!lob.verify_fifo());
+        assert_eq!(lob.error_msgs().len(), 1);
+        assert!(lob.error_msgs().iter().next().unwrap().contains("order 5"));
+    }
+
+    #[test]
+    fn size_gini() {
+        let mut lob = LimitOrderbook::new();
+        assert_eq!(lob.size_gini(Side::Bids), None);
+        lob.place(order(0, Side::Bids, 100.0, 2.0));
+        assert_eq!(lob.size_gini(Side::Bids), None);
+
+        // uniform sizes across levels
+        for uid in 1..10 {
+            lob.place(order(uid, Side::Bids, 100.0 - uid as f64, 2.0));
+        }
+        assert!(lob.size_gini(Side::Bids).unwrap().abs() < 1e-12);
+
+        // one order holds nearly all the liquidity
+        for uid in 0..1000 {
+            lob.place(order(uid, Side::Asks, 101.0 + (uid % 7) as f64, 1e-6));
+        }
+        lob.place(order(1000, Side::Asks, 105.0, 1e6));
+        let gini = lob.size_gini(Side::Asks).unwrap();
+        assert!(gini > 0.99 && gini < 1.0);
+    }
+
+    #[test]
+    fn place_rolls_back_on_panic() {
+        let mut lob = LimitOrderbook::new();
+        lob.set_linger_empty_levels(true);
+        lob.place(order("0", Side::Bids, 100.0, 1.0));
+        lob.place(order("1", Side::Bids, 99.0, 1.0));
+        lob.remove("1".to_string());
+        let before = lob.levels(Side::Bids, None);
+
+        // a NaN key can't be ordered against the tree's keys, so the tree insert panics
+        let result = panic::catch_unwind(AssertUnwindSafe(|| lob.place(order("2", Side::Bids, f64::NAN, 1.0))));
+        assert!(result.is_err());
+        assert!(!lob.has("2".to_string()));
+        assert_eq!(lob.len(), 1);
+        assert_eq!(lob.levels(Side::Bids, None), before);
+        assert!(lob.check().is_empty());
+
+        // a uid already in order_map keeps its entry
+        let result = panic::catch_unwind(AssertUnwindSafe(|| lob.place(order("0", Side::Bids, f64::NAN, 1.0))));
+        assert!(result.is_err());
+        assert_eq!(lob.locate("0".to_string()), Some((Side::Bids, 100.0)));
+        assert_eq!(lob.len(), 1);
+        assert_eq!(lob.bids.len(), 2);
+        assert!(lob.check().is_empty());
+
+        lob.place(order("2", Side::Bids, 98.0, 1.0));
+        assert_eq!(lob.len(), 2);
+        assert!(lob.check().is_empty());
     }
 
-    pub fn __str__(&self) -> PyResult<String> {
-        Ok(format!(
-            "Order(uid={}, side={:?}, price={}, size={}, timestamp={})",
-            self.uid, self.side, self.price, self.size, self.timestamp
-        ))
+    #[test]
+    fn max_order_size() {
+        let mut lob = LimitOrderbook::new();
+        assert!(lob.set_max_order_size(Some(-1.0)).is_err());
+        lob.set_max_order_size(Some(1000.0)).unwrap();
+
+        lob.process(order("0", Side::Bids, 100.0, 1e9), Submit::Insert).unwrap();
+        assert!(!lob.has("0".to_string()));
+        assert_eq!(lob.rejected(), 1);
+        assert_eq!(lob.outliers(), 0);
+        assert_eq!(lob.error_msgs().len(), 1);
+
+        lob.process(order("1", Side::Bids, 100.0, 1000.0), Submit::Insert).unwrap();
+        assert!(lob.has("1".to_string()));
+        assert_eq!(lob.rejected(), 1);
+        assert_eq!(lob.items_processed(), 2);
+
+        // process rejects non-finite sizes up front, but insert guards the cap by itself
+        assert!(!lob.insert(order("nan", Side::Bids, 100.0, f64::NAN)));
+        assert!(!lob.has("nan".to_string()));
+        assert_eq!(lob.rejected(), 2);
+
+        // the cap only applies to inserts
+        lob.process(order("1", Side::Bids, 100.0, 5000.0), Submit::Update).unwrap();
+        assert_eq!(lob.get_order("1".to_string()).unwrap().size, 5000.0);
+
+        lob.set_max_order_size(None).unwrap();
+        lob.process(order("2", Side::Bids, 100.0, 1e9), Submit::Insert).unwrap();
+        assert!(lob.has("2".to_string()));
+        lob.reset_counters();
+        assert_eq!(lob.rejected(), 0);
     }
-}
 
-impl Default for Side {
-    fn default() -> Self {
-        Side::Bids
+    #[test]
+    fn orders_at_price() {
+        let mut lob = LimitOrderbook::new();
+        lob.place(order("0", Side::Bids, 100.0, 1.0));
+        lob.place(order("1", Side::Bids, 99.0, 2.0));
+        lob.place(order("2", Side::Bids, 99.0, 3.0));
+        lob.place(order("3", Side::Asks, 99.0, 9.0));
+        lob.place(order("4", Side::Bids, 99.0, 4.0));
+        lob.remove("2".to_string());
+
+        let queue = lob.orders_at_price(Side::Bids, 99.0);
+        assert_eq!(queue.iter().map(|order| order.uid.as_str()).collect::<Vec<&str>>(), vec!["1", "4"]);
+        assert_eq!(queue.iter().map(|order| order.size).collect::<Vec<f64>>(), vec![2.0, 4.0]);
+        assert_eq!(lob.orders_at_price(Side::Asks, 99.0), vec![order("3", Side::Asks, 99.0, 9.0)]);
+        assert!(lob.orders_at_price(Side::Bids, 98.0).is_empty());
     }
-}
 
-impl Default for Order {
-    fn default() -> Self {
-        Order {
-            uid: "default_uid".to_string(),
-            side: Default::default(),
-            price: 0.0,
-            size: 0.0,
-            timestamp: "default timestamp".to_string(),
+    #[test]
+    fn flow_ratios() {
+        let mut lob = LimitOrderbook::new();
+        assert_eq!(lob.flow_ratios(), (0.0, 0.0));
+
+        for uid in 0..8 {
+            lob.process(order(uid, Side::Bids, 100.0, 1.0), Submit::Insert).unwrap();
+        }
+        for uid in 0..6 {
+            lob.process(order(uid, Side::Bids, 100.0, 0.0), Submit::Remove).unwrap();
         }
+        for uid in 6..8 {
+            lob.process(order(uid, Side::Bids, 100.0, 2.0), Submit::Update).unwrap();
+        }
+        // fills and moves are neither
+        lob.process(order(7, Side::Bids, 100.0, 0.5), Submit::Fill).unwrap();
+        // only effective events count: an outlier or oversized insert adds nothing, and an
+        // update or cancel of an unknown uid changes nothing
+        lob.set_max_order_size(Some(10.0)).unwrap();
+        lob.process(order(8, Side::Bids, 100.0, 50.0), Submit::Insert).unwrap();
+        lob.process(order(9, Side::Bids, 1.0, 1.0), Submit::Insert).unwrap();
+        lob.process(order("unknown", Side::Bids, 100.0, 2.0), Submit::Update).unwrap();
+        lob.process(order("unknown", Side::Bids, 100.0, 0.0), Submit::Remove).unwrap();
+        assert_eq!((lob.rejected(), lob.outliers()), (1, 1));
+        assert_eq!((lob.total_adds(), lob.total_cancels(), lob.total_updates()), (8, 6, 2));
+        assert_eq!(lob.flow_ratios(), (0.75, 0.25));
+
+        lob.reset_counters();
+        assert_eq!(lob.flow_ratios(), (0.0, 0.0));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use rand::{Rng, seq::SliceRandom};
-    use super::*;
+    #[test]
+    fn total_cancels_counts_live_cancels() {
+        let done = |uid: &str, reason: &str| Order { reason: Some(reason.to_string()), ..order(uid, Side::Bids, 100.0, 1.0) };
+        let mut lob = LimitOrderbook::new();
+        for uid in ["0", "1", "2"] {
+            lob.process(order(uid, Side::Bids, 100.0, 1.0), Submit::Insert).unwrap();
+        }
 
-    fn generate_random_orders(size: usize) -> Vec<Order> {
-        let mut rng = rand::thread_rng();
+        // a filled order leaving the book isn't a cancel
+        lob.process(done("0", "filled"), Submit::RemoveWithReason).unwrap();
+        assert_eq!(lob.total_cancels(), 0);
 
-        let prices: Vec<f64> = (0..size)
-            .into_iter().map(|_k| rng.gen_range(0..10000) as f64 / 100.0).collect();
-        let sizes: Vec<f64> = (0..size)
-            .into_iter().map(|_k| rng.gen_range(0..10000) as f64 / 100.0).collect();
+        // nor is a remove of an order the book doesn't hold
+        lob.process(order("0", Side::Bids, 100.0, 1.0), Submit::Remove).unwrap();
+        lob.process(done("unknown", "canceled"), Submit::RemoveWithReason).unwrap();
+        assert_eq!(lob.total_cancels(), 0);
 
-        let vector: Vec<Order>
-            = (0..size).into_iter().map(|k| {
-            Order::new(
-                k.to_string(),
-                if rng.gen_bool(0.5) { Some(Side::Bids) } else { Some(Side::Asks) },
-                Some(prices.choose(&mut rng).unwrap().clone()),
-                Some(sizes.choose(&mut rng).unwrap().clone()),
-                "dummy_datetime".to_string(),
-            )
-        }).collect();
-        vector
+        lob.process(done("1", "canceled"), Submit::RemoveWithReason).unwrap();
+        lob.process(order("2", Side::Bids, 100.0, 1.0), Submit::Remove).unwrap();
+        assert_eq!(lob.total_cancels(), 2);
+        assert_eq!((lob.filled_count(), lob.canceled_count()), (1, 1));
     }
 
-    fn deserialize_orders_from_json(json_orders: Vec<&str>) -> Vec<Order> {
-        let deserialized_orders: Vec<Order> = json_orders
-            .iter()
-            .map(|json_order| serde_json::from_str(&json_order).unwrap())
-            .collect();
-        deserialized_orders
+    #[test]
+    fn process_ndjson_reader() {
+        let lines = [
+            r#"{"uid":"0","side":"Bids","price":100.0,"size":1.0,"timestamp":"2022-09-27T19:31:00.000000Z","action":"Insert"}"#,
+            r#"{"uid":"1","side":"Asks","price":101.0,"size":2.0,"timestamp":"2022-09-27T19:31:01.000000Z","action":"Insert"}"#,
+            "",
+            r#"{"uid":"0","side":"Bids","price":100.0,"size":3.0,"timestamp":"2022-09-27T19:31:02.000000Z","action":"Update"}"#,
+        ];
+        let mut lob = LimitOrderbook::new();
+        let processed = lob.process_ndjson_reader(std::io::Cursor::new(lines.join("\n"))).unwrap();
+        assert_eq!(processed, 3);
+        assert_eq!(lob.len(), 2);
+        assert_eq!(lob.get_order("0".to_string()).unwrap().size, 3.0);
+
+        // a bad line stops processing, keeping the updates before it
+        let lines = [
+            r#"{"uid":"2","side":"Bids","price":99.0,"size":1.0,"timestamp":"2022-09-27T19:31:03.000000Z","action":"Insert"}"#,
+            r#"{"uid":"3","side":"Bids","price":98.0,"size":1.0,"timestamp":"2022-09-27T19:31:04.000000Z","action":"Teleport"}"#,
+            r#"{"uid":"4","side":"Bids","price":97.0,"size":1.0,"timestamp":"2022-09-27T19:31:05.000000Z","action":"Insert"}"#,
+        ];
+        assert!(lob.process_ndjson_reader(std::io::Cursor::new(lines.join("\n"))).is_err());
+        assert!(lob.has("2".to_string()));
+        assert!(!lob.has("4".to_string()));
     }
 
-    fn outlier_check(order: &Order, factor: f64, top_level: (Option<f64>, Option<f64>)) -> (bool, (Option<f64>, Option<f64>)) {
-        match order.side {
-            Side::Bids => {
-                if let Some(best_bid) = top_level.0 {
-                    if order.price < best_bid / factor {
-                        (true, (Some(best_bid), top_level.1))
-                    } else if order.price > best_bid {
-                        (false, (Some(order.price), top_level.1))
-                    } else {
-                        (false, (Some(best_bid), top_level.1))
-                    }
-                } else {
-                    (false, (Some(order.price), top_level.1))
-                }
-            },
-            Side::Asks => {
-                if let Some(best_ask) = top_level.1 {
-                    if order.price > best_ask * factor {
-                        (true, (top_level.0, Some(best_ask)))
-                    } else if order.price < best_ask {
-                        (false, (top_level.0, Some(order.price)))
-                    } else {
-                        (false, (top_level.0, Some(best_ask)))
-                    }
-                } else {
-                    (false, (top_level.0, Some(order.price)))
-                }
-            },
+    #[test]
+    fn realized_vol() {
+        let mut lob = LimitOrderbook::new();
+        lob.process(order_at("b", Side::Bids, 99.0, 1.0, &timestamp(0)), Submit::Insert).unwrap();
+        assert_eq!(lob.realized_vol(60), None);
+
+        // mids 100, 101, 100, 102 at seconds 0, 1, 2, 3
+        lob.process(order_at("a", Side::Asks, 101.0, 1.0, &timestamp(0)), Submit::Insert).unwrap();
+        assert_eq!(lob.realized_vol(60), None);
+        lob.process(order_at("a", Side::Asks, 103.0, 1.0, &timestamp(1)), Submit::Move).unwrap();
+        lob.process(order_at("a", Side::Asks, 101.0, 1.0, &timestamp(2)), Submit::Move).unwrap();
+        // same top of book, no sample
+        lob.process(order_at("b", Side::Bids, 99.0, 1.0, &timestamp(2)), Submit::Update).unwrap();
+        lob.process(order_at("a", Side::Asks, 105.0, 1.0, &timestamp(3)), Submit::Move).unwrap();
+
+        let returns = [(101.0_f64 / 100.0).ln(), (100.0_f64 / 101.0).ln(), (102.0_f64 / 100.0).ln()];
+        let expected = (returns.iter().map(|r| r * r).sum::<f64>() * SECONDS_PER_YEAR / 3.0).sqrt();
+        assert!((lob.realized_vol(60).unwrap() - expected).abs() < 1e-9);
+
+        // a one second window only covers the last return
+        let expected = (returns[2] * returns[2] * SECONDS_PER_YEAR).sqrt();
+        assert!((lob.realized_vol(1).unwrap() - expected).abs() < 1e-9);
+        assert_eq!(lob.realized_vol(0), None);
+
+        // windows beyond chrono's range cover the whole history instead of panicking
+        let full = lob.realized_vol(60).unwrap();
+        for window in [i64::MAX as u64 / 1000 + 1, i64::MAX as u64, u64::MAX] {
+            assert_eq!(lob.realized_vol(window), Some(full));
         }
     }
 
     #[test]
-    fn empty_book() {
+    fn empty_sides() {
         let mut lob = LimitOrderbook::new();
-        let orders = generate_random_orders(2);
-        assert_eq!(lob.best_ask(), None);
-        assert_eq!(lob.best_bid(), None);
-        assert_eq!(lob.len(), 0);
-        assert_eq!(lob.node_count(), 0);
-        assert_eq!(lob.levels(Side::Bids), vec![]);
-        assert_eq!(lob.levels(Side::Asks), vec![]);
-        // assert_eq!(lob.liquidity(Side::Bids), vec![]);
-        // assert_eq!(lob.liquidity(Side::Asks), vec![]);
-        assert_eq!(lob.items_processed(), 0);
-        lob.check();
-        assert_eq!(lob.error_msgs(), HashSet::new());
-        lob.process(orders[0].clone(), Submit::Remove);
-        lob.process(orders[1].clone(), Submit::Update);
-        assert_eq!(lob.len(), 0);
-        println!("Orderbook timestamp: {}", lob.timestamp);
+        assert!(!lob.has_bids() && !lob.has_asks() && lob.is_empty());
+
+        lob.place(order("0", Side::Bids, 99.0, 1.0));
+        assert!(lob.has_bids() && !lob.has_asks() && !lob.is_empty());
+
+        lob.place(order("1", Side::Asks, 101.0, 1.0));
+        assert!(lob.has_bids() && lob.has_asks() && !lob.is_empty());
+
+        // a lingering empty level leaves its side empty
+        lob.set_linger_empty_levels(true);
+        lob.remove("0".to_string());
+        assert!(!lob.has_bids() && lob.has_asks());
+        assert!(!lob.bids.is_empty());
+        lob.remove("1".to_string());
+        assert!(lob.is_empty());
     }
 
     #[test]
-    fn basics() {
-        println!("\n-----Testing LOB Order Insertion-----\n");
-        let mut orderbook: LimitOrderbook = LimitOrderbook::new();
+    fn level_consumption_estimate() {
+        let mut lob = LimitOrderbook::new();
+        lob.place(order("0", Side::Asks, 101.0, 3.0));
+        lob.place(order("1", Side::Asks, 101.0, 5.0));
+        lob.place(order("2", Side::Asks, 102.0, 4.0));
 
+        assert_eq!(lob.level_consumption_estimate(Side::Asks, 101.0, 2.0), Some(0.25));
+        assert_eq!(lob.level_consumption_estimate(Side::Asks, 102.0, 1.0), Some(0.25));
+        assert_eq!(lob.level_consumption_estimate(Side::Asks, 101.0, 20.0), Some(1.0));
+        assert_eq!(lob.level_consumption_estimate(Side::Asks, 101.0, 0.0), Some(0.0));
+        assert_eq!(lob.level_consumption_estimate(Side::Asks, 103.0, 1.0), None);
+        assert_eq!(lob.level_consumption_estimate(Side::Bids, 101.0, 1.0), None);
+    }
 
-        let orders = generate_random_orders(20);
-        println!("\nInserting randomly-generated orders into orderbook.\n");
+    #[test]
+    fn side_opposite() {
+        assert_eq!(Side::Bids.opposite(), Side::Asks);
+        assert_eq!(Side::Asks.opposite(), Side::Bids);
+        assert!(Side::Bids < Side::Asks);
 
-        // let json_orders = vec![
-        //     "{\"uid\":\"0\",\"side\":{\"Asks\":1},\"price\":76.28,\"size\":99.35,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"1\",\"side\":{\"Bids\":0},\"price\":95.55,\"size\":87.12,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"2\",\"side\":{\"Asks\":1},\"price\":53.88,\"size\":30.54,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"3\",\"side\":{\"Asks\":1},\"price\":76.28,\"size\":87.12,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"4\",\"side\":{\"Bids\":0},\"price\":57.0,\"size\":64.08,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"5\",\"side\":{\"Bids\":0},\"price\":44.12,\"size\":60.9,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"6\",\"side\":{\"Asks\":1},\"price\":94.42,\"size\":99.35,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"7\",\"side\":{\"Bids\":0},\"price\":98.79,\"size\":46.21,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"8\",\"side\":{\"Bids\":0},\"price\":7.73,\"size\":28.57,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"9\",\"side\":{\"Asks\":1},\"price\":57.0,\"size\":96.04,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"10\",\"side\":{\"Bids\":0},\"price\":44.12,\"size\":34.79,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"11\",\"side\":{\"Asks\":1},\"price\":47.36,\"size\":43.91,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"12\",\"side\":{\"Asks\":1},\"price\":47.36,\"size\":28.21,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"13\",\"side\":{\"Bids\":0},\"price\":16.8,\"size\":96.04,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"14\",\"side\":{\"Bids\":0},\"price\":7.73,\"size\":34.79,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"15\",\"side\":{\"Asks\":1},\"price\":7.73,\"size\":96.04,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"16\",\"side\":{\"Asks\":1},\"price\":7.73,\"size\":99.35,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"17\",\"side\":{\"Bids\":0},\"price\":60.76,\"size\":37.54,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"18\",\"side\":{\"Bids\":0},\"price\":57.0,\"size\":85.97,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"19\",\"side\":{\"Bids\":0},\"price\":47.36,\"size\":99.05,\"timestamp\":\"dummy_datetime\"}",
-        // ];
-        // let orders = deserialize_orders_from_json(json_orders);
-        // println!("Deserializing orders and inserting into orderbook.");
+        let mut sizes: HashMap<Side, f64> = HashMap::new();
+        for (side, size) in [(Side::Bids, 1.0), (Side::Asks, 2.0), (Side::Bids, 3.0)] {
+            *sizes.entry(side).or_insert(0.0) += size;
+        }
+        assert_eq!(sizes[&Side::Bids], 4.0);
+        assert_eq!(sizes[&Side::Asks.opposite().opposite()], 2.0);
+    }
 
-        // reduce generated orders simulating orderbook's outlier-checking behavior
-        let mut outlier_n_best = (false, (None, None));
-        let mut orders_adj: Vec<Order> = Vec::new();
-        for order in &orders {
-            // let serialized_order = serde_json::to_string(&order).unwrap();
-            // println!("{:?},", serialized_order);
-            // println!("{:?}", order);
-            let inserted = orderbook.insert(order.clone());
-            outlier_n_best = outlier_check(&order, 2.0, outlier_n_best.1);
-            assert_eq!(inserted, !outlier_n_best.0);
-            if !outlier_n_best.0 {
-                orders_adj.push(order.clone());
+    #[test]
+    fn fingerprint() {
+        let mut one = LimitOrderbook::new();
+        one.place(order("0", Side::Bids, 99.0, 3.0));
+        one.place(order("1", Side::Asks, 101.0, 1.0));
+        one.place(order("2", Side::Asks, 101.0, 1.5));
+
+        let mut other = LimitOrderbook::new();
+        other.place(order("a", Side::Asks, 101.0, 2.5));
+        other.place(order("b", Side::Bids, 99.0, 1.0));
+        other.place(order("c", Side::Bids, 99.0, 2.0));
+        assert_eq!(one.fingerprint(), other.fingerprint());
+
+        other.update("c".to_string(), 2.25, None);
+        assert_ne!(one.fingerprint(), other.fingerprint());
+
+        // the same level on the other side
+        let mut flipped = LimitOrderbook::new();
+        flipped.place(order("0", Side::Asks, 99.0, 3.0));
+        let mut unflipped = LimitOrderbook::new();
+        unflipped.place(order("0", Side::Bids, 99.0, 3.0));
+        assert_ne!(flipped.fingerprint(), unflipped.fingerprint());
+        assert_ne!(LimitOrderbook::new().fingerprint(), unflipped.fingerprint());
+    }
+
+    #[test]
+    fn levels_page() {
+        let mut lob = LimitOrderbook::new();
+        for (order, action) in test_utils::random_ops(11, 500) {
+            lob.process(order, action).unwrap();
+        }
+        for side in [Side::Bids, Side::Asks] {
+            let levels: Vec<(f64, f64)> = lob.levels(side.clone(), None).into_iter()
+                .map(|(price, size, _)| (price, size))
+                .collect();
+            assert!(levels.len() > 30);
+            for page_size in [1, 7, 50] {
+                let mut pages: Vec<(f64, f64)> = Vec::new();
+                for page in 0.. {
+                    let window = lob.levels_page(side.clone(), page * page_size, page_size);
+                    if window.is_empty() { break }
+                    assert!(window.len() <= page_size);
+                    pages.extend(window);
+                }
+                assert_eq!(pages, levels);
             }
+            assert!(lob.levels_page(side.clone(), levels.len(), 10).is_empty());
+            assert!(lob.levels_page(side, 0, 0).is_empty());
         }
-        let orders = orders_adj;
+    }
 
-        assert_eq!(orderbook.len(), orders.len());
-        orderbook.display_trees_aux(Side::Bids, true);
-        println!("Best bid: {:?}", orderbook.best_bid());
-        println!("Buy-side levels: {:?}", orderbook.levels(Side::Bids));
-        // println!("Buy-side cumulative order depth: {:?}", orderbook.liquidity(Side::Bids));
-        orderbook.display_trees_aux(Side::Asks, true);
-        println!("Best ask: {:?}", orderbook.best_ask());
-        println!("Sell-side levels {:?}", orderbook.levels(Side::Asks));
-        // println!("Sell-side cumulative order depth: {:?}", orderbook.liquidity(Side::Asks));
+    #[test]
+    fn would_match() {
+        let mut lob = LimitOrderbook::new();
+        lob.place(order("b0", Side::Bids, 99.0, 1.0));
+        lob.place(order("b1", Side::Bids, 98.0, 2.0));
+        lob.place(order("a0", Side::Asks, 101.0, 1.0));
+        lob.place(order("a1", Side::Asks, 101.0, 2.0));
+        lob.place(order("a2", Side::Asks, 102.0, 5.0));
+        let before = lob.fingerprint();
 
-        // test updates
-        let mut rng = rand::thread_rng();
-        let updated_order = orders.choose(&mut rng).unwrap();
-        let new_size = 1000.0;
-        println!("\n------------Updating order {} with new order size {}------------\n", updated_order.uid, new_size);
-        orderbook.update(updated_order.uid.clone(), new_size);
-        let updated_order = orderbook.get_order(updated_order.uid.clone()).unwrap().clone();
-        println!("{:?}", updated_order);
-        assert_eq!(updated_order.size, 1000.0);
-        orderbook.display_trees_aux(Side::Bids, true);
-        orderbook.display_trees_aux(Side::Asks, true);
-        assert_eq!(orderbook.len(), orders.len());
+        // marketable buy walks the asks in price-time priority
+        assert_eq!(lob.would_match(&order("x", Side::Bids, 102.5, 4.0)),
+                   vec![("a0".to_string(), 1.0), ("a1".to_string(), 2.0), ("a2".to_string(), 1.0)]);
+        // marketable sell stops at its limit
+        assert_eq!(lob.would_match(&order("x", Side::Asks, 98.5, 10.0)), vec![("b0".to_string(), 1.0)]);
+        // exactly touching the best price crosses it
+        assert_eq!(lob.would_match(&order("x", Side::Bids, 101.0, 1.5)),
+                   vec![("a0".to_string(), 1.0), ("a1".to_string(), 0.5)]);
+        assert_eq!(lob.would_match(&order("x", Side::Asks, 99.0, 0.5)), vec![("b0".to_string(), 0.5)]);
+        // inside the spread
+        assert!(lob.would_match(&order("x", Side::Bids, 100.0, 1.0)).is_empty());
+        assert!(lob.would_match(&order("x", Side::Asks, 100.0, 1.0)).is_empty());
+        assert_eq!(lob.fingerprint(), before);
+    }
 
+    #[test]
+    fn aggressor_volume() {
+        let mut lob = LimitOrderbook::new();
+        lob.process(order_at("b", Side::Bids, 99.0, 100.0, &timestamp(0)), Submit::Insert).unwrap();
+        lob.process(order_at("a", Side::Asks, 101.0, 100.0, &timestamp(0)), Submit::Insert).unwrap();
 
-        let deleted: Vec<&Order> = orders.choose_multiple(&mut rng, 5).collect();
-        let deleted_uids: Vec<String> = deleted.iter().map(|order| order.uid.clone()).collect();
-        println!("\nDeleting orders {:?}", deleted_uids);
-        for i in 0..deleted.len() {
-            let serialized_order = serde_json::to_string(&deleted[i]).unwrap();
-            println!("{:?}", serialized_order);
-            orderbook.remove(deleted_uids[i].clone());
-        }
+        // buyers lift the ask, sellers hit the bid
+        lob.process(order_at("a", Side::Asks, 101.0, 1.0, &timestamp(10)), Submit::Fill).unwrap();
+        lob.process(order_at("b", Side::Bids, 99.0, 2.0, &timestamp(20)), Submit::Fill).unwrap();
+        lob.process(order_at("a", Side::Asks, 101.0, 4.0, &timestamp(30)), Submit::Fill).unwrap();
+        lob.process(order_at("b", Side::Bids, 99.0, 8.0, &timestamp(40)), Submit::Fill).unwrap();
+        lob.process(order_at("a", Side::Asks, 101.0, 16.0, &timestamp(50)), Submit::Fill).unwrap();
 
-        // let delete_orders = vec![
-        //     "{\"uid\":\"13\",\"side\":{\"Bids\":0},\"price\":16.8,\"size\":96.04,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"9\",\"side\":{\"Asks\":1},\"price\":57.0,\"size\":96.04,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"4\",\"side\":{\"Bids\":0},\"price\":57.0,\"size\":64.08,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"12\",\"side\":{\"Asks\":1},\"price\":47.36,\"size\":28.21,\"timestamp\":\"dummy_datetime\"}",
-        //     "{\"uid\":\"0\",\"side\":{\"Asks\":1},\"price\":76.28,\"size\":99.35,\"timestamp\":\"dummy_datetime\"}",
-        // ];
-        // let deleted = deserialize_orders_from_json(delete_orders);
-        // let deleted_uids: Vec<String> = deleted.iter().map(|order| order.uid.clone()).collect();
-        // println!("\n-----------------Deleting orders {:?}-----------------\n", deleted_uids);
-        // for order in &deleted {
-        //     orderbook.remove(order.uid.clone());
-        // }
+        let now = parse_timestamp("2022-09-27T19:31:50.000000Z").unwrap();
+        assert_eq!(lob.aggressor_volume(60, now), (21.0, 10.0));
+        assert_eq!(lob.aggressor_volume(20, now), (20.0, 8.0));
+        assert_eq!(lob.aggressor_volume(0, now), (16.0, 0.0));
+        // windows too large for a Duration or a DateTime cover the whole tape
+        assert_eq!(lob.aggressor_volume(u64::MAX, now), (21.0, 10.0));
+        assert_eq!(lob.aggressor_volume(i64::MAX as u64, now), (21.0, 10.0));
+        assert_eq!(lob.aggressor_volume((i64::MAX / 1000) as u64, now), (21.0, 10.0));
+        // trades after now are excluded
+        let earlier = parse_timestamp("2022-09-27T19:31:25.000000Z").unwrap();
+        assert_eq!(lob.aggressor_volume(10, earlier), (0.0, 2.0));
+    }
 
+    #[test]
+    fn try_accessors() {
+        let mut lob = LimitOrderbook::new();
+        assert_eq!(lob.try_best_bid().unwrap(), None);
+        lob.process_sequenced(order("0", Side::Bids, 99.0, 1.0), Submit::Insert, 1).unwrap();
+        lob.process_sequenced(order("1", Side::Asks, 101.0, 1.0), Submit::Insert, 2).unwrap();
+        assert_eq!(lob.try_mid_price().unwrap(), Some(100.0));
 
-        orderbook.display_trees_aux(Side::Bids, true);
-        orderbook.display_trees_aux(Side::Asks, true);
-        assert_eq!(orderbook.len(), orders.len() - deleted.len());
+        // a sequence gap invalidates the book
+        assert!(lob.process_sequenced(order("2", Side::Bids, 98.0, 1.0), Submit::Insert, 4).is_err());
+        assert!(lob.try_best_bid().is_err());
+        assert!(lob.try_best_ask().is_err());
+        assert!(lob.try_mid_price().is_err());
+        // the plain getters still return None
+        assert_eq!(lob.best_bid(), None);
 
-        // create vector of expected orders, accounting for updated order and deletions
-        let remaining: Vec<&Order> = orders
-            .iter()
-            .filter(|&order| !deleted_uids.contains(&order.uid))
-            .map(|order| {if *order.uid == updated_order.uid { &updated_order} else { order } })
-            .collect();
-        println!("\nExpected Remaining orders:\n");
-        for order in &remaining {
-            print!("{:?}", order);
-            println!(" Orderbook has this? {}", orderbook.has(order.uid.clone()));
-        }
+        // resync
+        lob.clear();
+        lob.process_sequenced(order("3", Side::Bids, 97.0, 1.0), Submit::Insert, 10).unwrap();
+        lob.process_sequenced(order("4", Side::Asks, 103.0, 1.0), Submit::Insert, 11).unwrap();
+        assert_eq!(lob.try_best_bid().unwrap(), Some(97.0));
+        assert_eq!(lob.try_best_ask().unwrap(), Some(103.0));
+        assert_eq!(lob.try_mid_price().unwrap(), Some(100.0));
+    }
 
-        println!("\nRemaining orders in LOB:\n");
-        let mut orderbook_iter = orderbook.iter();
-        let mut order_vector: Vec<&Order> = Vec::new();
-        let mut i: usize = 0;
-        while let Some(order) = orderbook_iter.next() {
-            println!("{:?}", order);
-            order_vector.push(order);
-            i += 1;
-            if i > remaining.len() {
-                break;
-            }
-        }
+    #[test]
+    fn try_accessors_halted() {
+        let mut lob = LimitOrderbook::new();
+        lob.set_check_interval(Some(1)).unwrap();
+        lob.process(order("0", Side::Bids, 99.0, 1.0), Submit::Insert).unwrap();
+        lob.process(order("1", Side::Asks, 101.0, 1.0), Submit::Insert).unwrap();
 
-        println!("\nRemaining orders in LOB, sorted by uid:\n");
-        order_vector.sort_by_key(|order| order.uid.clone().parse::<usize>().unwrap());
-        let mut i: usize = 0;
-        for order in &order_vector {
-            println!("{:?}", order);
-            assert_eq!(remaining[i], *order);
-            i += 1;
-        }
+        // corrupt the order count; the next check halts the book
+        lob.len += 1;
+        lob.process(order("2", Side::Bids, 98.0, 1.0), Submit::Insert).unwrap();
+        assert!(lob.halted());
+        assert!(lob.try_best_bid().is_err());
+        assert!(lob.try_best_ask().is_err());
+        assert!(lob.try_mid_price().is_err());
+        // the plain getters still read the book
+        assert_eq!(lob.best_bid(), Some(99.0));
 
+        // clear lifts the halt
+        lob.clear();
+        lob.process(order("3", Side::Bids, 97.0, 1.0), Submit::Insert).unwrap();
+        lob.process(order("4", Side::Asks, 103.0, 1.0), Submit::Insert).unwrap();
+        assert_eq!(lob.try_best_bid().unwrap(), Some(97.0));
+        assert_eq!(lob.try_best_ask().unwrap(), Some(103.0));
+        assert_eq!(lob.try_mid_price().unwrap(), Some(100.0));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,236 @@
+#![allow(dead_code)]
+
+// Standard Library
+use std::collections::{BTreeMap, HashSet};
+// Crates
+use pyo3::prelude::*;
+use pyo3::exceptions::PyValueError;
+// Homebrew
+use crate::orderbook::{BookEvent, LimitOrderbook, OrderbookSnapshot};
+
+/// Steps through a recorded log of BookEvents, rebuilding the book up to a cursor, so a
+/// feed can be paused, rewound and inspected to bisect to the message that corrupts the book.
+///
+/// Events are numbered from 1 in load order, and sequence n is the book after applying
+/// event n; sequence 0 is the empty book. Snapshots are taken every snapshot_interval
+/// events while stepping forward, so seeking backwards restarts from the nearest prior
+/// snapshot instead of from the beginning.
+pub struct Replayer {
+    events: Vec<BookEvent>,
+    cursor: usize,
+    book: LimitOrderbook,
+    snapshot_interval: usize,
+    snapshots: BTreeMap<usize, OrderbookSnapshot>,
+    validate_every: Option<usize>,
+}
+
+/// Integrity errors check() found after applying the event at sequence
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationFailure {
+    pub sequence: usize,
+    pub errors: HashSet<String>,
+}
+
+impl Replayer {
+    /// Create a replayer positioned at sequence 0. Errors if snapshot_interval is 0.
+    pub fn new(events: Vec<BookEvent>, snapshot_interval: usize) -> PyResult<Self> {
+        if snapshot_interval == 0 {
+            return Err(PyValueError::new_err("snapshot interval must be positive"))
+        }
+        Ok(Replayer {
+            events,
+            cursor: 0,
+            book: LimitOrderbook::new(),
+            snapshot_interval,
+            snapshots: BTreeMap::new(),
+            validate_every: None,
+        })
+    }
+
+    /// Run LimitOrderbook::check() every validate_every events applied by replay_to,
+    /// or never with None, the default. Errors if validate_every is Some(0).
+    pub fn set_validate_every(&mut self, validate_every: Option<usize>) -> PyResult<()> {
+        if validate_every == Some(0) {
+            return Err(PyValueError::new_err("validation interval must be positive"))
+        }
+        self.validate_every = validate_every;
+        Ok(())
+    }
+
+    /// Return the book as of the cursor
+    pub fn book(&self) -> &LimitOrderbook {
+        &self.book
+    }
+
+    /// Return the sequence of the last applied event, 0 if none
+    pub fn sequence(&self) -> usize {
+        self.cursor
+    }
+
+    /// Return count of loaded events
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Return true if no events are loaded
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Apply the next event, returning it, or None at the end of the log
+    pub fn step(&mut self) -> Option<&BookEvent> {
+        if self.cursor >= self.events.len() {
+            return None
+        }
+        self.book.apply_event(&self.events[self.cursor]);
+        self.cursor += 1;
+        if self.cursor % self.snapshot_interval == 0 {
+            self.snapshots.entry(self.cursor).or_insert_with(|| self.book.snapshot());
+        }
+        Some(&self.events[self.cursor - 1])
+    }
+
+    /// Apply up to n events, returning count applied
+    pub fn step_n(&mut self, n: usize) -> usize {
+        let mut applied = 0;
+        while applied < n && self.step().is_some() {
+            applied += 1;
+        }
+        applied
+    }
+
+    /// Step forward to a sequence, clamped to the end of the log, returning count applied.
+    /// With validation on, the book is checked after every sequence that's a multiple of
+    /// validate_every, and the replay stops at the first one that fails, leaving the cursor
+    /// on it. A check interval of 1 pins the exact event that corrupts the book, e.g. an
+    /// Inserted event for a uid already resting, which leaves two orders in the trees under
+    /// one order_map entry and fails the order count check.
+    ///
+    /// A locked book isn't treated as a failure. Stepping with step, step_n or
+    /// seek_to_sequence doesn't validate.
+    pub fn replay_to(&mut self, sequence: usize) -> Result<usize, ValidationFailure> {
+        let mut applied = 0;
+        while self.cursor < sequence && self.step().is_some() {
+            applied += 1;
+            if let Some(every) = self.validate_every {
+                if self.cursor % every == 0 {
+                    // a locked book is a feed anomaly, not corruption, as for periodic checks
+                    let locked = self.book.locked_msg();
+                    let mut errors = self.book.check();
+                    errors.retain(|error| Some(error) != locked.as_ref());
+                    if !errors.is_empty() {
+                        return Err(ValidationFailure { sequence: self.cursor, errors })
+                    }
+                }
+            }
+        }
+        Ok(applied)
+    }
+
+    /// Move the cursor to a sequence, clamped to the end of the log. Seeking backwards
+    /// rebuilds the book from the nearest snapshot at or before the sequence.
+    pub fn seek_to_sequence(&mut self, sequence: usize) {
+        let target = sequence.min(self.events.len());
+        if target < self.cursor {
+            match self.snapshots.range(..=target).next_back() {
+                Some((&snapshot_sequence, snapshot)) => {
+                    self.book = LimitOrderbook::from_snapshot(snapshot.clone());
+                    self.cursor = snapshot_sequence;
+                },
+                None => {
+                    self.book = LimitOrderbook::new();
+                    self.cursor = 0;
+                },
+            }
+        }
+        self.step_n(target - self.cursor);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{Side, test_utils};
+
+    /// Process seeded operations with an event sink, returning the book and its events
+    fn record(seed: u64, n: usize) -> (LimitOrderbook, Vec<BookEvent>) {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut lob = LimitOrderbook::new();
+        lob.set_event_sink(Some(sender));
+        for (order, action) in test_utils::random_ops(seed, n) {
+            lob.process(order, action).unwrap();
+        }
+        lob.set_event_sink(None);
+        (lob, receiver.try_iter().collect())
+    }
+
+    fn state(book: &LimitOrderbook) -> (Vec<(f64, f64, f64)>, Vec<(f64, f64, f64)>) {
+        (book.levels(Side::Bids, None), book.levels(Side::Asks, None))
+    }
+
+    #[test]
+    fn step_and_seek() {
+        let (lob, events) = record(3, 300);
+        assert!(Replayer::new(events.clone(), 0).is_err());
+        let mut replayer = Replayer::new(events.clone(), 50).unwrap();
+        assert_eq!(replayer.len(), events.len());
+        assert!(!replayer.is_empty());
+
+        assert_eq!(replayer.step(), Some(&events[0]));
+        assert_eq!(replayer.step_n(99), 99);
+        assert_eq!(replayer.sequence(), 100);
+
+        // straight replay of the same prefix
+        let mut straight = Replayer::new(events.clone(), usize::MAX).unwrap();
+        straight.step_n(100);
+        assert_eq!(state(replayer.book()), state(straight.book()));
+
+        // running to the end matches the recorded book
+        replayer.seek_to_sequence(usize::MAX);
+        assert_eq!(replayer.sequence(), events.len());
+        assert_eq!(replayer.step(), None);
+        assert_eq!(state(replayer.book()), state(&lob));
+
+        // seeking backwards restarts from a snapshot, or from scratch before the first one
+        for sequence in [173, 120, 100, 30, 0, 250] {
+            replayer.seek_to_sequence(sequence);
+            let mut straight = Replayer::new(events.clone(), usize::MAX).unwrap();
+            straight.step_n(sequence);
+            assert_eq!(replayer.sequence(), sequence);
+            assert_eq!(state(replayer.book()), state(straight.book()));
+            assert_eq!(replayer.book().len(), straight.book().len());
+        }
+    }
+
+    #[test]
+    fn validated_replay() {
+        let (lob, events) = record(5, 400);
+        let mut replayer = Replayer::new(events.clone(), 100).unwrap();
+        assert!(replayer.set_validate_every(Some(0)).is_err());
+        replayer.set_validate_every(Some(1)).unwrap();
+        assert_eq!(replayer.replay_to(150), Ok(150));
+        assert_eq!(replayer.replay_to(usize::MAX), Ok(events.len() - 150));
+        assert_eq!(state(replayer.book()), state(&lob));
+
+        // replaying an insert twice corrupts the book at that event
+        let mut corrupt = events.clone();
+        let duplicate = corrupt.iter().position(|event| matches!(event, BookEvent::Inserted { .. })).unwrap();
+        corrupt.insert(duplicate + 1, corrupt[duplicate].clone());
+        let mut replayer = Replayer::new(corrupt, 100).unwrap();
+        replayer.set_validate_every(Some(1)).unwrap();
+        let failure = replayer.replay_to(usize::MAX).unwrap_err();
+        assert_eq!(failure.sequence, duplicate + 2);
+        assert_eq!(replayer.sequence(), duplicate + 2);
+        assert!(failure.errors.iter().any(|error| error.starts_with("Order count mismatch")));
+
+        // a locked book passes validation
+        let locked = vec![
+            BookEvent::Inserted { uid: "0".to_string(), side: Side::Bids, price: 100.0, size: 1.0 },
+            BookEvent::Inserted { uid: "1".to_string(), side: Side::Asks, price: 100.0, size: 1.0 },
+        ];
+        let mut replayer = Replayer::new(locked, 100).unwrap();
+        replayer.set_validate_every(Some(1)).unwrap();
+        assert_eq!(replayer.replay_to(usize::MAX), Ok(2));
+        assert!(replayer.book().is_locked());
+    }
+}
@@ -0,0 +1,171 @@
+#![allow(dead_code)]
+
+// Standard Library
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+// Crates
+use chrono::Utc;
+// Homebrew
+use crate::orderbook::LimitOrderbook;
+use crate::sync::SyncOrderbook;
+
+/// Writes JSON snapshots of a book to timestamped files in a directory for crash recovery,
+/// keeping only the newest keep files. Only files written by this snapshotter are rotated;
+/// anything already in the directory is left alone.
+pub struct Snapshotter {
+    dir: PathBuf,
+    keep: usize,
+    written: VecDeque<PathBuf>,
+    count: usize,
+}
+
+/// Handle to a snapshotter running on a background thread
+pub struct SnapshotterHandle {
+    control: Sender<Control>,
+    thread: JoinHandle<Snapshotter>,
+}
+
+/// Messages from a handle to its background snapshotter, applied in the order sent
+enum Control {
+    Snapshot,
+    Stop,
+}
+
+impl Snapshotter {
+    /// Create a snapshotter writing into dir and keeping the newest keep snapshots
+    pub fn new<P: AsRef<Path>>(dir: P, keep: usize) -> Self {
+        assert!(keep > 0, "snapshotter must keep at least one snapshot");
+        Snapshotter {
+            dir: dir.as_ref().to_path_buf(),
+            keep,
+            written: VecDeque::new(),
+            count: 0,
+        }
+    }
+
+    /// Return paths of the retained snapshots, oldest first
+    pub fn snapshots(&self) -> Vec<PathBuf> {
+        self.written.iter().cloned().collect()
+    }
+
+    /// Write a snapshot of the book, then delete the oldest snapshots beyond keep.
+    /// Returns the path written.
+    pub fn write(&mut self, book: &LimitOrderbook) -> io::Result<PathBuf> {
+        self.write_json(&book.to_snapshot_json())
+    }
+
+    /// Snapshot the book every interval on a background thread until the handle is stopped.
+    /// IO errors are logged and the next snapshot is attempted on schedule.
+    pub fn spawn(mut self, book: Arc<SyncOrderbook>, interval: Duration) -> SnapshotterHandle {
+        let (control, received) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            loop {
+                match received.recv_timeout(interval) {
+                    Ok(Control::Snapshot) | Err(RecvTimeoutError::Timeout) => {},
+                    Ok(Control::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+                }
+                if let Err(e) = self.write_json(&book.to_snapshot_json()) {
+                    println!("Warning: failed to write orderbook snapshot to {}: {}", self.dir.display(), e);
+                }
+            }
+            self
+        });
+        SnapshotterHandle { control, thread }
+    }
+
+    /// Write the snapshot to a temporary file in the same directory and rename it into place,
+    /// so a crash mid-write never leaves a truncated snapshot behind
+    fn write_json(&mut self, json: &str) -> io::Result<PathBuf> {
+        // the count disambiguates snapshots taken within the same microsecond
+        let file_name = format!("snapshot_{}_{:06}.json", Utc::now().format("%Y%m%dT%H%M%S%6fZ"), self.count);
+        let path = self.dir.join(&file_name);
+        let temp_path = self.dir.join(format!(".{}.tmp", file_name));
+        if let Err(e) = fs::write(&temp_path, json).and_then(|_| fs::rename(&temp_path, &path)) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e)
+        }
+        self.count += 1;
+        self.written.push_back(path.clone());
+        while self.written.len() > self.keep {
+            let oldest = self.written.pop_front().unwrap();
+            if let Err(e) = fs::remove_file(&oldest) {
+                println!("Warning: failed to remove old orderbook snapshot {}: {}", oldest.display(), e);
+            }
+        }
+        Ok(path)
+    }
+}
+
+impl SnapshotterHandle {
+    /// Ask the background thread for a snapshot now, ahead of its schedule
+    pub fn snapshot_now(&self) {
+        let _ = self.control.send(Control::Snapshot);
+    }
+
+    /// Stop the background thread and return the snapshotter. Snapshots requested
+    /// before stopping are written first.
+    pub fn stop(self) -> Snapshotter {
+        let _ = self.control.send(Control::Stop);
+        self.thread.join().expect("snapshotter thread panicked")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{Order, Side, Submit};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rust_orderbook_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file_count(dir: &Path) -> usize {
+        fs::read_dir(dir).unwrap().count()
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = temp_dir("snapshot_rotation");
+        let mut lob = LimitOrderbook::new();
+        let mut snapshotter = Snapshotter::new(&dir, 3);
+        for uid in 0..7 {
+            let order = Order::new(uid.to_string(), Some(Side::Bids), Some(100.0), Some(1.0), "dummy_datetime".to_string());
+            lob.process(order, Submit::Insert).unwrap();
+            let path = snapshotter.write(&lob).unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), lob.to_snapshot_json());
+            assert_eq!(file_count(&dir), (uid + 1).min(3));
+        }
+        assert!(snapshotter.snapshots().iter().all(|path| path.exists()));
+        // no temporary files are left behind
+        assert!(fs::read_dir(&dir).unwrap().all(|entry| !entry.unwrap().file_name().to_string_lossy().ends_with(".tmp")));
+        fs::remove_dir_all(&dir).unwrap();
+
+        // a missing directory is an error, not a panic
+        assert!(snapshotter.write(&lob).is_err());
+        assert_eq!(snapshotter.snapshots().len(), 3);
+    }
+
+    #[test]
+    fn background() {
+        let dir = temp_dir("snapshot_background");
+        let book = Arc::new(SyncOrderbook::new());
+        // the interval never elapses, so only the explicit requests write snapshots
+        let handle = Snapshotter::new(&dir, 2).spawn(book, Duration::from_secs(3600));
+        for _ in 0..3 {
+            handle.snapshot_now();
+        }
+        let snapshotter = handle.stop();
+        assert_eq!(snapshotter.count, 3);
+        assert_eq!(file_count(&dir), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+#![allow(dead_code)]
+
+// Standard
+use std::collections::HashMap;
+// Crates
+use barter::data::MarketMeta;
+use barter::strategy::{Decision, Signal as BarterSignal, SignalGenerator, SignalStrength};
+use barter_data::model::MarketEvent;
+use pyo3::PyResult;
+// Homebrew
+use crate::orderbook::{LimitOrderbook, Order, Submit};
+
+/// Direction an imbalance strategy wants to be positioned in
+#[derive(Clone, Debug, PartialEq)]
+pub enum Signal {
+    Long,
+    Short,
+}
+
+/// Strategy that maintains a live orderbook from L3 updates and signals when the book's
+/// imbalance crosses into the long (>= long_threshold) or short (<= short_threshold) region.
+///
+/// A signal is emitted once per crossing: staying inside a region is silent, and the
+/// imbalance has to leave the region before the same signal fires again.
+///
+/// barter's MarketEvent carries no L3 data, so the book is fed through on_update and
+/// the SignalGenerator impl hands the latest crossing to barter on the next market event.
+pub struct ImbalanceStrategy {
+    book: LimitOrderbook,
+    depth: usize,
+    long_threshold: f64,
+    short_threshold: f64,
+    region: Option<Signal>,
+    pending: Option<Signal>,
+}
+
+impl ImbalanceStrategy {
+    /// Create a strategy measuring imbalance over the best depth levels of each side.
+    /// Thresholds are in [-1, 1], with short_threshold below long_threshold.
+    pub fn new(depth: usize, long_threshold: f64, short_threshold: f64) -> Result<Self, String> {
+        if depth == 0 {
+            return Err("imbalance depth must be positive".to_string());
+        }
+        if !(-1.0..=1.0).contains(&long_threshold) || !(-1.0..=1.0).contains(&short_threshold) {
+            return Err(format!(
+                "imbalance thresholds must be in [-1, 1], got long {} and short {}",
+                long_threshold, short_threshold
+            ));
+        }
+        if short_threshold >= long_threshold {
+            return Err(format!(
+                "short threshold {} must be below long threshold {}",
+                short_threshold, long_threshold
+            ));
+        }
+        Ok(ImbalanceStrategy {
+            book: LimitOrderbook::new(),
+            depth,
+            long_threshold,
+            short_threshold,
+            region: None,
+            pending: None,
+        })
+    }
+
+    /// Return the orderbook the strategy maintains
+    pub fn book(&self) -> &LimitOrderbook {
+        &self.book
+    }
+
+    /// Apply an L3 update to the book, returning a signal if it moved the imbalance
+    /// across a threshold. One-sided books produce no signal.
+    pub fn on_update(&mut self, order: Order, action: Submit) -> PyResult<Option<Signal>> {
+        self.book.process(order, action)?;
+        let imbalance = match self.book.imbalance(self.depth) {
+            Some(imbalance) => imbalance,
+            None => return Ok(None),
+        };
+        let region = if imbalance >= self.long_threshold {
+            Some(Signal::Long)
+        } else if imbalance <= self.short_threshold {
+            Some(Signal::Short)
+        } else {
+            None
+        };
+        let crossed = region.is_some() && region != self.region;
+        self.region = region;
+        if !crossed {
+            return Ok(None);
+        }
+        self.pending = self.region.clone();
+        Ok(self.region.clone())
+    }
+}
+
+impl SignalGenerator for ImbalanceStrategy {
+    /// Emit the most recent unreported crossing as a barter signal, priced at the book's
+    /// mid. The crossing stays pending while the book is one-sided.
+    fn generate_signal(&mut self, market: &MarketEvent) -> Option<BarterSignal> {
+        let close = self.book.mid_price()?;
+        let (open, close_opposite) = match self.pending.take()? {
+            Signal::Long => (Decision::Long, Decision::CloseShort),
+            Signal::Short => (Decision::Short, Decision::CloseLong),
+        };
+        let mut signals = HashMap::with_capacity(2);
+        signals.insert(open, SignalStrength(1.0));
+        signals.insert(close_opposite, SignalStrength(1.0));
+        Some(BarterSignal {
+            time: market.exchange_time,
+            exchange: market.exchange.clone(),
+            instrument: market.instrument.clone(),
+            signals,
+            market_meta: MarketMeta { close, time: market.exchange_time },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::Side;
+    use barter_data::model::{DataKind, PublicTrade};
+    use barter_integration::model::{Exchange, Instrument, InstrumentKind, Side as TradeSide};
+    use chrono::Utc;
+
+    fn order(uid: &str, side: Side, price: f64, size: f64) -> Order {
+        Order::new(uid.to_string(), Some(side), Some(price), Some(size), "dummy_datetime".to_string())
+    }
+
+    fn market_event(price: f64) -> MarketEvent {
+        MarketEvent {
+            exchange_time: Utc::now(),
+            received_time: Utc::now(),
+            exchange: Exchange::from("coinbase"),
+            instrument: Instrument::from(("btc", "usd", InstrumentKind::Spot)),
+            kind: DataKind::Trade(PublicTrade {
+                id: "0".to_string(),
+                price,
+                quantity: 1.0,
+                side: TradeSide::Buy,
+            }),
+        }
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(ImbalanceStrategy::new(0, 0.5, -0.5).is_err());
+        assert!(ImbalanceStrategy::new(1, -0.5, 0.5).is_err());
+        assert!(ImbalanceStrategy::new(1, 0.5, 0.5).is_err());
+        assert!(ImbalanceStrategy::new(1, 1.5, -0.5).is_err());
+        assert!(ImbalanceStrategy::new(1, f64::NAN, -0.5).is_err());
+        assert!(ImbalanceStrategy::new(1, 0.5, f64::NEG_INFINITY).is_err());
+    }
+
+    #[test]
+    fn imbalance_signals() {
+        let mut strategy = ImbalanceStrategy::new(1, 0.5, -0.5).unwrap();
+
+        assert_eq!(strategy.on_update(order("0", Side::Bids, 100.0, 1.0), Submit::Insert).unwrap(), None);
+        // balanced
+        assert_eq!(strategy.on_update(order("1", Side::Asks, 101.0, 1.0), Submit::Insert).unwrap(), None);
+
+        // bids grow to 3x the asks: imbalance 0.5 crosses the long threshold
+        assert_eq!(strategy.on_update(order("0", Side::Bids, 100.0, 3.0), Submit::Update).unwrap(), Some(Signal::Long));
+        // still long, no repeat
+        assert_eq!(strategy.on_update(order("0", Side::Bids, 100.0, 4.0), Submit::Update).unwrap(), None);
+
+        // asks grow past the bids: imbalance -0.6 crosses the short threshold
+        assert_eq!(strategy.on_update(order("1", Side::Asks, 101.0, 16.0), Submit::Update).unwrap(), Some(Signal::Short));
+
+        // back to neutral, then long again
+        assert_eq!(strategy.on_update(order("1", Side::Asks, 101.0, 4.0), Submit::Update).unwrap(), None);
+        assert_eq!(strategy.on_update(order("1", Side::Asks, 101.0, 1.0), Submit::Update).unwrap(), Some(Signal::Long));
+        assert_eq!(strategy.book().len(), 2);
+    }
+
+    #[test]
+    fn barter_signals() {
+        let mut strategy = ImbalanceStrategy::new(1, 0.5, -0.5).unwrap();
+        strategy.on_update(order("0", Side::Bids, 100.0, 3.0), Submit::Insert).unwrap();
+        // one-sided: nothing to report yet
+        assert_eq!(strategy.generate_signal(&market_event(100.0)), None);
+
+        assert_eq!(strategy.on_update(order("1", Side::Asks, 102.0, 1.0), Submit::Insert).unwrap(), Some(Signal::Long));
+        let signal = strategy.generate_signal(&market_event(101.0)).unwrap();
+        assert_eq!(signal.signals.len(), 2);
+        assert_eq!(signal.signals.get(&Decision::Long), Some(&SignalStrength(1.0)));
+        assert_eq!(signal.signals.get(&Decision::CloseShort), Some(&SignalStrength(1.0)));
+        assert_eq!(signal.market_meta.close, 101.0);
+        assert_eq!(signal.exchange, Exchange::from("coinbase"));
+        // the crossing is reported once
+        assert_eq!(strategy.generate_signal(&market_event(101.0)), None);
+
+        strategy.on_update(order("1", Side::Asks, 102.0, 16.0), Submit::Update).unwrap();
+        let signal = strategy.generate_signal(&market_event(101.0)).unwrap();
+        assert!(signal.signals.contains_key(&Decision::Short));
+        assert!(signal.signals.contains_key(&Decision::CloseLong));
+    }
+}
@@ -0,0 +1,134 @@
+#![allow(dead_code)]
+
+// Crates
+use parking_lot::RwLock;
+use pyo3::PyResult;
+// Homebrew
+use crate::orderbook::{LimitOrderbook, Order, Side, Submit};
+
+/// Thread-safe wrapper around a LimitOrderbook.
+///
+/// The orderbook's AVL-trees are built on raw pointers, so even reads walk memory that a
+/// concurrent writer may be rotating. Every access therefore goes through the lock: updates
+/// take the write lock and queries take the read lock.
+pub struct SyncOrderbook {
+    inner: RwLock<LimitOrderbook>,
+}
+
+impl SyncOrderbook {
+    /// Create a new, empty thread-safe orderbook
+    pub fn new() -> Self {
+        SyncOrderbook { inner: RwLock::new(LimitOrderbook::new()) }
+    }
+
+    /// Wrap an existing orderbook
+    pub fn from_orderbook(orderbook: LimitOrderbook) -> Self {
+        SyncOrderbook { inner: RwLock::new(orderbook) }
+    }
+
+    /// Process an order under the write lock
+    pub fn apply(&self, order: Order, action: Submit) -> PyResult<()> {
+        self.inner.write().process(order, action)
+    }
+
+    /// Return the highest bidding price in the book
+    pub fn best_bid(&self) -> Option<f64> {
+        self.inner.read().best_bid()
+    }
+
+    /// Return the lowest asking price in the book
+    pub fn best_ask(&self) -> Option<f64> {
+        self.inner.read().best_ask()
+    }
+
+    /// Return the midpoint between best bid and best ask
+    pub fn mid_price(&self) -> Option<f64> {
+        self.inner.read().mid_price()
+    }
+
+    /// Return (bid levels, ask levels), both read under a single lock so they're consistent
+    pub fn snapshot(&self) -> (Vec<(f64, f64, f64)>, Vec<(f64, f64, f64)>) {
+        let orderbook = self.inner.read();
+        (orderbook.levels(Side::Bids, None), orderbook.levels(Side::Asks, None))
+    }
+
+    /// Serialize the orderbook to a JSON snapshot under the read lock
+    pub fn to_snapshot_json(&self) -> String {
+        self.inner.read().to_snapshot_json()
+    }
+
+    /// Unwrap the inner orderbook
+    pub fn into_inner(self) -> LimitOrderbook {
+        self.inner.into_inner()
+    }
+}
+
+impl Default for SyncOrderbook {
+    fn default() -> Self {
+        SyncOrderbook::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use rand::Rng;
+    use super::*;
+
+    #[test]
+    fn concurrent_readers_and_writer() {
+        let book = Arc::new(SyncOrderbook::new());
+        let done = Arc::new(AtomicBool::new(false));
+
+        let writer = {
+            let book = Arc::clone(&book);
+            let done = Arc::clone(&done);
+            thread::spawn(move || {
+                let mut rng = rand::thread_rng();
+                for i in 0..5000 {
+                    // bids always below 100, asks always above, so the book can never cross
+                    let side = if rng.gen_bool(0.5) { Side::Bids } else { Side::Asks };
+                    let price = match side {
+                        Side::Bids => rng.gen_range(5100..10000) as f64 / 100.0,
+                        Side::Asks => rng.gen_range(10001..19000) as f64 / 100.0,
+                    };
+                    let order = Order::new(
+                        i.to_string(), Some(side), Some(price), Some(1.0), "dummy_datetime".to_string()
+                    );
+                    book.apply(order, Submit::Insert).unwrap();
+                    if i % 3 == 0 {
+                        let uid = rng.gen_range(0..=i).to_string();
+                        book.apply(Order::new(uid, None, None, None, "dummy_datetime".to_string()), Submit::Remove).unwrap();
+                    }
+                }
+                done.store(true, Ordering::SeqCst);
+            })
+        };
+
+        let readers: Vec<_> = (0..4).map(|_| {
+            let book = Arc::clone(&book);
+            let done = Arc::clone(&done);
+            thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    if let (Some(bid), Some(ask)) = (book.best_bid(), book.best_ask()) {
+                        assert!(bid < ask);
+                    }
+                    let (bids, asks) = book.snapshot();
+                    assert!(bids.windows(2).all(|w| w[0].0 > w[1].0));
+                    assert!(asks.windows(2).all(|w| w[0].0 < w[1].0));
+                }
+            })
+        }).collect();
+
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        let mut book = Arc::try_unwrap(book).ok().unwrap().into_inner();
+        book.check();
+        assert!(book.error_msgs().is_empty());
+    }
+}
@@ -0,0 +1,203 @@
+#![allow(dead_code)]
+
+// Homebrew
+use crate::orderbook::{Order, Side, Submit};
+
+/// Error raised when a wire message can't be decoded
+#[derive(Clone, Debug, PartialEq)]
+pub enum WireError {
+    Truncated { needed: usize, available: usize },
+    UnknownAction(u8),
+    UnknownSide(u8),
+    InvalidUtf8,
+    TrailingBytes(usize),
+}
+
+impl std::fmt::Display for WireError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WireError::Truncated { needed, available } => {
+                write!(f, "truncated update, needed {} more bytes but {} remain", needed, available)
+            },
+            WireError::UnknownAction(byte) => write!(f, "unknown action byte {}", byte),
+            WireError::UnknownSide(byte) => write!(f, "unknown side byte {}", byte),
+            WireError::InvalidUtf8 => write!(f, "string field is not valid UTF-8"),
+            WireError::TrailingBytes(count) => write!(f, "{} unexpected bytes after update", count),
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+/// Encode an update as a compact binary message. All numbers are little-endian:
+///
+/// | field       | layout                                       |
+/// |-------------|----------------------------------------------|
+/// | action      | u8                                           |
+/// | side        | u8                                           |
+/// | price       | f64                                          |
+/// | size        | f64                                          |
+/// | uid         | u32 length + UTF-8 bytes                     |
+/// | timestamp   | u32 length + UTF-8 bytes                     |
+/// | hidden_size | f64                                          |
+/// | reason      | u8 presence flag, then length-prefixed bytes |
+/// | meta        | u8 presence flag, then length-prefixed bytes |
+pub fn encode_update(order: &Order, submit: &Submit) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(34 + order.uid.len() + order.timestamp().len());
+    bytes.push(action_byte(submit));
+    bytes.push(match order.side {
+        Side::Bids => 0,
+        Side::Asks => 1,
+    });
+    bytes.extend(order.price.to_le_bytes());
+    bytes.extend(order.size.to_le_bytes());
+    put_str(&mut bytes, &order.uid);
+    put_str(&mut bytes, order.timestamp());
+    bytes.extend(order.hidden_size.to_le_bytes());
+    put_opt_str(&mut bytes, &order.reason);
+    put_opt_str(&mut bytes, &order.meta);
+    bytes
+}
+
+/// Decode a message produced by encode_update
+pub fn decode_update(bytes: &[u8]) -> Result<(Order, Submit), WireError> {
+    let mut reader = Reader { bytes, position: 0 };
+    let submit = match reader.u8()? {
+        0 => Submit::Insert,
+        1 => Submit::Remove,
+        2 => Submit::RemoveWithReason,
+        3 => Submit::Update,
+        4 => Submit::Fill,
+        5 => Submit::Move,
+        byte => return Err(WireError::UnknownAction(byte)),
+    };
+    let side = match reader.u8()? {
+        0 => Side::Bids,
+        1 => Side::Asks,
+        byte => return Err(WireError::UnknownSide(byte)),
+    };
+    let price = reader.f64()?;
+    let size = reader.f64()?;
+    let uid = reader.string()?;
+    let timestamp = reader.string()?;
+    let hidden_size = reader.f64()?;
+    let reason = reader.opt_string()?;
+    let meta = reader.opt_string()?;
+    let remaining = bytes.len() - reader.position;
+    if remaining > 0 {
+        return Err(WireError::TrailingBytes(remaining))
+    }
+
+    let mut order = Order::new(uid, Some(side), Some(price), Some(size), timestamp);
+    order.hidden_size = hidden_size;
+    order.reason = reason;
+    order.meta = meta;
+    Ok((order, submit))
+}
+
+fn action_byte(submit: &Submit) -> u8 {
+    match submit {
+        Submit::Insert => 0,
+        Submit::Remove => 1,
+        Submit::RemoveWithReason => 2,
+        Submit::Update => 3,
+        Submit::Fill => 4,
+        Submit::Move => 5,
+    }
+}
+
+fn put_str(bytes: &mut Vec<u8>, value: &str) {
+    bytes.extend((value.len() as u32).to_le_bytes());
+    bytes.extend(value.as_bytes());
+}
+
+fn put_opt_str(bytes: &mut Vec<u8>, value: &Option<String>) {
+    match value {
+        Some(value) => {
+            bytes.push(1);
+            put_str(bytes, value);
+        },
+        None => bytes.push(0),
+    }
+}
+
+/// Cursor over a message being decoded
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], WireError> {
+        let available = self.bytes.len() - self.position;
+        if count > available {
+            return Err(WireError::Truncated { needed: count, available })
+        }
+        let taken = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, WireError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn f64(&mut self) -> Result<f64, WireError> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, WireError> {
+        let length = u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize;
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| WireError::InvalidUtf8)
+    }
+
+    fn opt_string(&mut self) -> Result<Option<String>, WireError> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.string()?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut order = Order::new("6f1b-ü".to_string(), Some(Side::Asks), Some(1234.5678), Some(0.000123),
+                                   "2022-09-27T19:31:00.123456Z".to_string());
+        order.hidden_size = 4.5;
+        order.meta = Some(r#"{"strategy": "mm-1"}"#.to_string());
+        let actions = [Submit::Insert, Submit::Remove, Submit::RemoveWithReason, Submit::Update, Submit::Fill, Submit::Move];
+        for action in actions {
+            let mut order = order.clone();
+            if let Submit::RemoveWithReason = action {
+                order.reason = Some("filled".to_string());
+            }
+            let (decoded, decoded_action) = decode_update(&encode_update(&order, &action)).unwrap();
+            assert_eq!(decoded, order);
+            assert_eq!(action_byte(&decoded_action), action_byte(&action));
+        }
+
+        let bid = Order::new("b".to_string(), Some(Side::Bids), Some(f64::MAX), Some(-0.0), String::new());
+        assert_eq!(decode_update(&encode_update(&bid, &Submit::Insert)).unwrap().0, bid);
+    }
+
+    #[test]
+    fn malformed() {
+        let order = Order::new("0".to_string(), Some(Side::Bids), Some(10.0), Some(1.0), "t".to_string());
+        let bytes = encode_update(&order, &Submit::Insert);
+        for length in 0..bytes.len() {
+            assert!(matches!(decode_update(&bytes[..length]), Err(WireError::Truncated { .. })));
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(decode_update(&trailing).err(), Some(WireError::TrailingBytes(1)));
+
+        let mut bad_action = bytes.clone();
+        bad_action[0] = 9;
+        assert_eq!(decode_update(&bad_action).err(), Some(WireError::UnknownAction(9)));
+    }
+}